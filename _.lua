@@ -66,9 +66,50 @@ Handled = {
 ---@class Config Configuration for the bot
 ---@field paths Paths Path configuration
 ---@field twitch Twitch Twitch configuration
----@field spotify Spotify Spotify configuration
+---@field spotify Spotify? Spotify configuration, `spotify` is disabled without it
+---@field github Github? GitHub configuration, `github` is disabled without it
+---@field commands Commands? Command dispatch configuration
+---@field limits Limits? Limits for script handlers
+---@field sandbox Sandbox? Restricts what scripts can do
+---@field http Http? What the `http` global can do
 Config = {}
 
+---@class Commands Configuration for how commands are dispatched
+---@field cooldown CooldownConfig? How cooldowns are applied
+---@field prefixes string[]? What commands start with (default { "!" })
+---@field mention boolean? Commands can be used by mentioning the bot, e.g. `@bot song` (default true)
+---@field channels {[string]: ChannelCommands}? Per-channel overrides, by channel name
+Commands = {}
+
+---@class ChannelCommands
+---@field prefixes string[]? Replaces the default prefixes in this channel
+---@field modules string[]? Only commands from these modules (the names in `init.lua`) are enabled in this channel
+---@field disabled string[]? These commands are disabled in this channel
+---@field cooldowns {[string]: number}? Replaces the cooldown (in seconds) of these commands in this channel
+
+---@class CooldownConfig
+---@field bypass Access|"none"|nil Users at or above this access level ignore cooldowns (default "moderator")
+---@field notify boolean? Reply (once per cooldown) when a command is on cooldown, rather than ignoring it (default true)
+CooldownConfig = {}
+
+---@class Limits Budgets for a single handler call, handlers that go over them are stopped
+---@field instructions integer? The most Lua instructions a handler can run (default 10000000)
+---@field time number? How many seconds a handler can run for, not counting time spent waiting on requests (default 5)
+---@field memory integer? How many megabytes all scripts can use (default 128)
+---@field strikes integer? How many times a handler can be stopped before it is disabled until a reload, 0 never disables (default 3)
+---@field notify boolean? Tell the chat when a handler was stopped (default false)
+---@field workers integer? How many requests (`http`, `spotify`, `helix`, `github`, `crates`) can be made at once (default 4)
+
+---@class Sandbox Sandboxed scripts get a safe subset of the standard library (no `io`, `debug`, `load`, ...),
+---a read-only view of the bot's globals and can only access files through `store`
+---@field enabled boolean? Sandbox scripts (default false)
+---@field trusted string[]? Modules (e.g. "init" or "settings") that are not sandboxed
+
+---@class Http What the `http` global can do, nothing is allowed by default
+---@field allow string[]? Hosts that can be requested, "*.example.com" allows any subdomain
+---@field timeout number? The longest a request can take, in seconds (default 10)
+---@field max_size integer? The largest response body, in bytes (default 1048576)
+
 ---@class Paths Configuration for directories used by the bot
 ---@field data string The directory to store the bot data
 ---@field scripts string The directory to store the bots scripts
@@ -78,28 +119,165 @@ Paths = {}
 ---@field name string The name of the bot (that is associated with `helix_oauth`)
 ---@field channels string[] A list of channels to join
 ---@field helix_oauth string? An OAuth token for 'TMI' (e.g. helix)
----@field client_id string? A Helix Client-Id
----@field client_secret string? A Helix Client-Secret
+---@field client_id string? A Helix Client-Id, `helix` and `emotes` are disabled without it
+---@field client_secret string? A Helix Client-Secret, `helix` and `emotes` are disabled without it
+---@field rate_limit RateLimit? Outgoing message rate limits
+---@field split Split? How long responses are split into multiple messages
+---@field reconnect Reconnect? How long to wait before reconnecting
+---@field backlog Backlog? What to do with responses while disconnected
+---@field transport Transport? How to connect to Twitch chat (default "tls")
+---@field address string? Connect here instead of Twitch, e.g. "localhost:6667" (or a URL for "websocket")
 Twitch = {}
 
----@class Spotify Configuration for the Spotify parts of the bot
+---@alias Transport
+---| "tls"       # IRC over TLS
+---| "websocket" # IRC over a secure WebSocket
+---| "tcp"       # Plaintext IRC, the OAuth token is sent unencrypted
+
+---@class Backlog Responses are kept while disconnected, and sent after reconnecting
+---@field max_messages integer? The most responses to keep, the oldest are dropped first (default 50)
+---@field max_age number? Responses older than this aren't sent, in seconds (default 60)
+Backlog = {}
+
+---@class Reconnect The delay doubles after every failed attempt (with some jitter), up to `max_delay`
+---@field min_delay number? The delay after the first failure, in seconds (default 1)
+---@field max_delay number? The longest delay, in seconds (default 120)
+Reconnect = {}
+
+---@class Split How long responses are split up (Twitch allows 500 characters per message)
+---@field continuation string? Appended to a message that continues in the next one (default "…")
+---@field max_parts integer? The most messages a single response is split into (default 4)
+Split = {}
+
+---@class RateLimit Outgoing message budgets, per `window` seconds
+---@field user integer? Messages allowed in a channel where the bot isn't a moderator (default 20)
+---@field moderator integer? Messages allowed in a channel where the bot is a moderator (default 100)
+---@field global integer? Messages allowed across all channels, `moderator` is used instead for channels where the bot is a moderator (default 20)
+---@field window integer? The window, in seconds (default 30)
+RateLimit = {}
+
+---@class Spotify Configuration for the Spotify parts of the bot, it's ignored if none of the credentials are set
 ---@field client_id string? A spotify Client-Id
 ---@field client_secret string? A spotify Client-Secret
+---@field refresh_token string? A spotify refresh token
+---@field announce string? The channel to announce song changes in, e.g. "#museun"
 Spotify = {}
 
+---@class Github Configuration for the GitHub parts of the bot, it's ignored if `oauth_token` isn't set
+---@field settings_gist_id string The gist that `settings.lua` reads the editor settings from
+---@field oauth_token string? A GitHub OAuth token
+Github = {}
+
 ---@class Manifest
----@field commands {[string]: Command[]} Commands
+---@field commands {[string]: Module} Command modules, by name
 ---@field listeners (fun(msg: Message): Handled)[] Passive listeners
+---@field events Events? Handlers for Twitch events
 Manifest = {}
 
+---@class Module: {[integer]: Command} A list of commands
+---@field listeners (fun(msg: Message): Handled)[]? Passive listeners
+---@field events Events? Handlers for Twitch events
+---@field requires string[]? Globals this module needs (e.g. "spotify"), the module is disabled if any are missing
+
+---@alias EventHandler<T> fun(event: T): nil | (fun(event: T): nil)[]
+
+---@class Events Handlers for Twitch events (and ones emitted with `events:emit`), a command module can also have an `events` table
+---@field sub EventHandler<Notice>? Someone subscribed
+---@field resub EventHandler<Notice>? Someone resubscribed
+---@field gift_sub EventHandler<Notice>? Someone gifted a sub to someone else
+---@field mystery_gift EventHandler<Notice>? Someone gifted subs to the community
+---@field raid EventHandler<Notice>? Someone raided the channel
+---@field user_notice EventHandler<Notice>? Any other USERNOTICE
+---@field clear_chat EventHandler<Clear>? The chat was cleared
+---@field timeout EventHandler<Clear>? Someone was timed out
+---@field ban EventHandler<Clear>? Someone was banned
+---@field clear_msg EventHandler<Deleted>? A message was deleted
+---@field room_state EventHandler<Room>? The room modes changed
+---@field join EventHandler<Membership>? Someone joined the channel
+---@field part EventHandler<Membership>? Someone left the channel
+---@field connect EventHandler<BOT_USER>? We connected (or reconnected) to Twitch
+---@field disconnect EventHandler<Disconnected>? We were disconnected from Twitch
+---@field spotify_track_changed EventHandler<SpotifyTrackChanged>? The song playing on Spotify changed
+Events = {}
+
+---@class Notice A USERNOTICE
+---@field kind string            The event name (e.g. `raid`)
+---@field channel string         The channel this happened on
+---@field channel_id string      The Twitch ID for this channel
+---@field sender string          The user that caused this
+---@field sender_id string       The Twitch ID for the user
+---@field display_name string    The display name of the user
+---@field message string?        An optional message from the user
+---@field system_message string? The message Twitch would show for this
+---@field months integer?        Months subscribed (`sub`, `resub` and `gift_sub`)
+---@field streak integer?        Months subscribed in a row (`resub`)
+---@field plan string?           The sub plan (`sub`, `resub`, `gift_sub` and `mystery_gift`)
+---@field recipient string?      Who got the gift (`gift_sub`)
+---@field count integer?         How many subs were gifted (`mystery_gift`)
+---@field from string?           Who raided (`raid`)
+---@field viewers integer?       How many viewers came with the raid (`raid`)
+---@field id string?             The raw `msg-id` (`user_notice`)
+---@field say fun(event: Notice, data: string): nil Send a message to the channel
+Notice = {}
+
+---@class Clear A CLEARCHAT
+---@field kind string         Either `clear_chat`, `timeout` or `ban`
+---@field channel string      The channel this happened on
+---@field channel_id string   The Twitch ID for this channel
+---@field target string?      The user that was timed out or banned
+---@field target_id string?   The Twitch ID for the user
+---@field duration integer?   How many seconds the timeout is for
+---@field say fun(event: Clear, data: string): nil Send a message to the channel
+Clear = {}
+
+---@class Deleted A CLEARMSG
+---@field channel string The channel this happened on
+---@field sender string  The user whose message was deleted
+---@field msg_id string  The ID of the deleted message
+---@field data string    The deleted message
+---@field say fun(event: Deleted, data: string): nil Send a message to the channel
+Deleted = {}
+
+---@class Room A ROOMSTATE, only the modes that changed are set
+---@field channel string           The channel this happened on
+---@field channel_id string        The Twitch ID for this channel
+---@field slow integer?            Slow mode, in seconds (0 is disabled)
+---@field followers_only integer?  Followers only mode, in minutes (-1 is disabled)
+---@field subs_only boolean?       Subscriber only mode
+---@field emote_only boolean?      Emote only mode
+---@field unique_chat boolean?     Unique chat (r9k) mode
+---@field say fun(event: Room, data: string): nil Send a message to the channel
+Room = {}
+
+---@class Membership A JOIN or PART
+---@field channel string The channel this happened on
+---@field user string    The user that joined or left
+---@field say fun(event: Membership, data: string): nil Send a message to the channel
+Membership = {}
+
+---@alias DisconnectReason "reconnect" | "requested" | "network" | "auth"
+
+---@class Disconnected We were disconnected from Twitch
+---@field reason DisconnectReason Why we were disconnected (we don't reconnect after an `auth` failure)
+---@field error string?           The error, for `network` and `auth`
+---@field retry_in number?        How many seconds until we try to reconnect, if we will
+Disconnected = {}
+
 ---@enum UserClass
 UserClass = {
     user = 0,
-    vip = 1,
-    moderator = 2,
-    broadcaster = 3,
+    subscriber = 1,
+    founder = 2,
+    vip = 3,
+    moderator = 4,
+    broadcaster = 5,
 }
 
+---@alias Access "user" | "subscriber" | "founder" | "vip" | "moderator" | "broadcaster"
+
+---@class SendOptions
+---@field all boolean? Send every part of a long message, instead of at most `split.max_parts`
+
 ---@class Message
 ---@field our_user   string The bot's user name
 ---@field our_id     string The bot's user id
@@ -110,18 +288,101 @@ UserClass = {
 ---@field sender_id  string The Twitch ID for the sender
 ---@field data       string The text sent by the user
 ---@field class      UserClass  The class of the user
----@field say fun(msg: Message, data: string): nil Send a message in response
----@field reply fun(msg: Message, data: string): nil Reply to user from a message
+---@field say fun(msg: Message, data: string, opts: SendOptions?): nil Send a message in response
+---@field reply fun(msg: Message, data: string, opts: SendOptions?): nil Reply to user from a message
+---@field is_from_subscriber fun(msg: Message): boolean Is the sender a subscriber (or founder)?
+---@field is_from_user fun(msg: Message): boolean Is the sender not a broadcaster, moderator or vip? (subscribers are users too)
+---@field has_access fun(msg: Message, access: Access): boolean Is the sender at least this access level?
 Message = {}
 
+-- Pattern arguments
+--   <name>       a single word
+--   <name?>      an optional word
+--   <name...>    the rest of the words, as a list
+-- an argument can also have a type, e.g. `<count:int>` or `<users:@user...>`
+--   string       (the default) the word as-is
+--   int          an integer
+--   a|b|c        one of the choices, e.g. `<mode:on|off?>`
+--   @user        a user name, with the `@` removed and lowercased
+--   url          a http(s) url
+--   duration     a TimeSpan from `90`, `90s`, `5m` or `1h30m`
+-- words left over after the pattern don't match it, quote them or use `<name...>` instead
+
 ---@class Command         A command binding
----@field command string  A unique ID of the command
----@field args string?    A pattern for matching this command
+---@field command string  The name of the command, without a prefix (e.g. `song` for `!song`)
+---@field args string?    A pattern for matching this command, see `Pattern arguments`
 ---@field help string     Help description for the command
----@field handler handler Callback for the command
----@field elevated boolean? Whether this command requires moderator or higher status to use
+---@field handler handler? Callback for the command, only optional if it has subcommands
+---@field subcommands {[string]: Subcommand}? Subcommands, e.g. `add` for `!greeting add`
+---@field access Access? The minimum access level required to use this command (default "user")
+---@field elevated boolean? Deprecated: the same as `access = "vip"`
+---@field cooldown number? Seconds before anyone in the channel can use this command again
+---@field user_cooldown number? Seconds before the same user can use this command again
 Command = {}
 
+---@class Subcommand     A subcommand, its access level defaults to its parent's
+---@field args string?    A pattern for matching this subcommand
+---@field help string     Help description for the subcommand
+---@field handler handler? Callback for the subcommand
+---@field access Access?
+---@field cooldown number?
+---@field user_cooldown number?
+---@field subcommands {[string]: Subcommand}?
+Subcommand = {}
+
+cooldowns = {
+    --- Gets how long until a command can be used again, or nil if it can be used now
+    ---@param channel string The channel the command is used in
+    ---@param command string The command
+    ---@param user string? Also consider this user's cooldown
+    ---@return TimeSpan?
+    remaining = function(self, channel, command, user) end,
+    --- Resets the cooldowns for a command in a channel
+    ---@param channel string
+    ---@param command string
+    reset = function(self, channel, command) end,
+}
+
+---@class TimerHandle A scheduled timer
+---@field cancel fun(self: TimerHandle): boolean Cancel the timer, returns false if it wasn't active
+---@field is_active fun(self: TimerHandle): boolean Will this timer run again?
+
+---@class TimerOptions
+---@field min_messages integer? Only run if this many messages were sent since the last run
+---@field channel string? Only count messages from this channel
+
+--- Timers are cancelled when the scripts are reloaded
+timer = {
+    --- Run a function every N seconds
+    ---@param secs number How often to run the function
+    ---@param fn fun(handle: TimerHandle): nil The function to run
+    ---@param opts TimerOptions?
+    ---@return TimerHandle
+    every = function(self, secs, fn, opts) end,
+    --- Run a function once, after N seconds
+    ---@param secs number How long to wait
+    ---@param fn fun(handle: TimerHandle): nil The function to run
+    ---@return TimerHandle
+    after = function(self, secs, fn) end,
+}
+
+--- Lets modules talk to each other, events are delivered after the current handler returns
+---
+--- Modules can also handle these in their `events` table, subscriptions made with `events:on`
+--- are removed when the scripts are reloaded
+events = {
+    --- Emit an event to every module
+    ---@param name string The event name
+    ---@param payload any? Anything that can be turned into JSON (no functions or userdata)
+    ---@return nil
+    emit = function(self, name, payload) end,
+    --- Run a function whenever an event is emitted (or happens on Twitch, e.g. `raid`)
+    ---@param name string The event name
+    ---@param fn fun(payload: any): nil
+    ---@return nil
+    on = function(self, name, fn) end,
+}
+
 bot = {
     --- Get the name of the bot
     ---@type string
@@ -147,14 +408,16 @@ bot = {
     --- Replies to the message
     ---@param message Message
     ---@param data string
+    ---@param opts SendOptions?
     ---@return nil
-    reply = function(message, data) end,
+    reply = function(message, data, opts) end,
 
     --- Sends a message
     ---@param message Message
     ---@param data string
+    ---@param opts SendOptions?
     ---@return nil
-    say = function(message, data) end,
+    say = function(message, data, opts) end,
 }
 
 log = {
@@ -184,6 +447,11 @@ log = {
     error = function(self, data) end,
 }
 
+---@class Invocation A command being used
+---@field prefix string The prefix that was used (or the channel's prefix, if the bot was mentioned)
+---@field name string   The command name
+---@field args string   Everything after the command name
+
 ---@class Help A help listing
 ---@field command string     The command name
 ---@field usage string       The command usage
@@ -195,8 +463,10 @@ help = {
     ---@return Help[]
     list = function(self) end,
     --- Look up a command by its name
+    ---@param name string The command, without a prefix
+    ---@param prefix string? Prepended to the usage
     ---@return Help
-    lookup = function(self, name) end,
+    lookup = function(self, name, prefix) end,
     --- Get a list of all commands and aliases
     ---@param sort boolean? should the commands be sorted?
     ---@return string[]
@@ -239,6 +509,7 @@ Stream = {}
 ---@field name string The emote name used in the chat
 Emote = {}
 
+--- Only available when Helix is configured
 helix = {
     ---@param name string The stream name to lookup
     ---@return Stream
@@ -248,6 +519,7 @@ helix = {
     get_emotes_for = function(self, id) end,
 }
 
+--- Only available when Helix is configured
 emotes = {
     --- Looks up an emote name by id
     ---@param id string
@@ -276,6 +548,8 @@ rand = {
     shuffle = function(self, table) end
 }
 
+--- Stores live in the `scripts` directory inside the data directory.
+--- Keys (for `load` and `save`) and namespaces are file names, they cannot be paths
 store = {
     --- Load a table from the data directory at `key`
     ---@param key string The store key to use
@@ -317,6 +591,36 @@ json = {
     to_str = function(self, data) end
 }
 
+---@class HttpOptions
+---@field headers {[string]: string}? Extra request headers
+---@field query {[string]: string}? Query parameters to add to the url
+---@field body string? The request body
+---@field json table? The request body, encoded as JSON (this sets `content-type`)
+---@field timeout number? How long to wait, in seconds, up to `http.timeout` from the configuration
+
+---@class HttpResponse
+---@field status integer The status code
+---@field ok boolean Whether the status code is 2xx
+---@field headers {[string]: string} The response headers, the names are lowercase
+---@field body string The response body
+---@field json fun(self: HttpResponse): any Decodes the body, the same way as `json:from_str`
+
+--- Requests can only be made to the hosts in `http.allow`, and redirects aren't followed
+---
+--- Handlers wait for the response without blocking the bot, as do the ones that call
+--- `spotify`, `helix`, `github` and `crates`
+http = {
+    ---@param url string
+    ---@param opts HttpOptions?
+    ---@return HttpResponse?, string?
+    get = function(self, url, opts) end,
+    ---@param url string
+    ---@param opts HttpOptions?
+    ---@return HttpResponse?, string?
+    post = function(self, url, opts) end,
+}
+
+--- Only available when GitHub is configured
 github = {
     --- Get the files for a gist
     ---@param id string the gist id
@@ -324,11 +628,107 @@ github = {
     get_gist_files = function(self, id) end
 }
 
+--- Commands toggled on or off in a channel, these take precedence over the configuration
+channels = {
+    --- Enable a command in a channel
+    ---@param channel string
+    ---@param command string The command, without a prefix
+    ---@return boolean?,string?
+    enable = function(self, channel, command) end,
+    --- Disable a command in a channel
+    ---@param channel string
+    ---@param command string The command, without a prefix
+    ---@return boolean?,string?
+    disable = function(self, channel, command) end,
+    --- Remove the toggle for a command in a channel
+    ---@param channel string
+    ---@param command string The command, without a prefix
+    ---@return boolean?,string?
+    reset = function(self, channel, command) end,
+    --- Was this command toggled in this channel? nil if it wasn't
+    ---@param channel string
+    ---@param command string The command, without a prefix
+    ---@return boolean?
+    toggled = function(self, channel, command) end,
+}
+
 bot = {
     --- Reroute this command through the but
     ---@param msg Message The message to respond to with the new command
-    ---@param command string The command to reroute
-    reroute_command = function(self, msg, command) end
+    ---@param command string? The command to reroute, without a prefix (e.g. `song`)
+    reroute_command = function(self, msg, command) end,
+    --- Gets the command this message invokes, if it invokes one
+    ---@param msg Message
+    ---@return Invocation?
+    invocation = function(self, msg) end,
+    --- Gets the prefix used for commands in this channel
+    ---@param channel string
+    ---@return string
+    prefix = function(self, channel) end,
+    --- Removes this channel's command prefix from the input, if it has one
+    ---@param channel string
+    ---@param input string
+    ---@return string
+    strip_prefix = function(self, channel, input) end,
+    --- Send a message to a channel, without a message to respond to
+    ---@param channel string The channel to send the message to
+    ---@param data string The message
+    say = function(self, channel, data) end,
+    --- Join a channel, it'll be joined again after restarting or reconnecting
+    ---@param channel string
+    ---@return boolean?,string?
+    join = function(self, channel) end,
+    --- Leave a channel, it won't be joined again after restarting or reconnecting
+    ---@param channel string
+    ---@return boolean?,string?
+    part = function(self, channel) end,
+    --- Gets the channels the bot should be in
+    ---@return string[]
+    channels = function(self) end,
+    --- Gets how many messages are waiting to be sent because of rate limiting
+    ---@param channel string? The channel to check, or all channels if nil
+    ---@return integer
+    queue_depth = function(self, channel) end,
+}
+
+connection = {
+    --- Are we connected to Twitch?
+    ---@return boolean
+    is_connected = function(self) end,
+    --- How many times we've reconnected
+    ---@return integer
+    reconnects = function(self) end,
+    --- Why we were last disconnected, if we have been
+    ---@return {reason: DisconnectReason, error: string?}?
+    last_disconnect = function(self) end,
+}
+
+permissions = {
+    --- Allow a user to use a command, regardless of its access level
+    ---@param command string The command, or '*' for every command
+    ---@param user string
+    ---@return boolean?,string?
+    allow = function(self, command, user) end,
+    --- Deny a user from using a command, regardless of its access level
+    ---@param command string The command, or '*' for every command
+    ---@param user string
+    ---@return boolean?,string?
+    deny = function(self, command, user) end,
+    --- Remove an override for a user
+    ---@param command string The command, or '*' for every command
+    ---@param user string
+    ---@return boolean?,string?
+    reset = function(self, command, user) end,
+    --- Check the override for a user: true if allowed, false if denied, nil if there is none
+    ---
+    --- A subcommand without an override of its own uses its parent command's
+    ---@param command string
+    ---@param user string
+    ---@return boolean?
+    check = function(self, command, user) end,
+    --- Get all of the overrides
+    ---@return {command: string, user: string, allowed: boolean}[]
+    list = function(self) end,
 }
 
 ---@class Pattern
@@ -345,11 +745,17 @@ re = {
 ---@field duration TimeSpan
 ---@field name string
 ---@field id string
----@field artists string[]
+---@field artists {name: string, id: string}[]
 ---@field progress TimeSpan?
 
 ---@alias SpotifyUrn string
 
+---@class SpotifyTrackChanged: SpotifyItem The song playing on Spotify changed.
+---This is checked when the song should end, at least every 30 seconds (up to a minute when nothing is playing)
+---@field change "new"|"paused"|"resumed"|"skipped" What happened, `paused` and `resumed` have the same song as before
+---@field channel string? Where to announce this, from `spotify.announce`
+
+--- Only available when Spotify is configured
 spotify = {
     --- Tries to get the currently playing song from spotify
     ---@return SpotifyItem?, string
@@ -8,16 +8,62 @@ return {
         name = "shaken_bot",
         channels = { "#museun", "#shaken_bot" },
         helix_oauth = get_env("SHAKEN_TWITCH_OAUTH_TOKEN"),
+        -- optional, scripts that require `helix` or `emotes` are disabled without these
         client_id = get_env("SHAKEN_TWITCH_CLIENT_ID"),
         client_secret = get_env("SHAKEN_TWITCH_CLIENT_SECRET"),
+        transport = "tls",
+        reconnect = {
+            min_delay = 1,
+            max_delay = 120,
+        },
+        backlog = {
+            max_messages = 50,
+            max_age = 60,
+        },
     },
+    -- optional, scripts that require `spotify` are disabled without this (or if none of these are set)
     spotify = {
         client_id = get_env("SHAKEN_SPOTIFY_CLIENT_ID"),
         client_secret = get_env("SHAKEN_SPOTIFY_CLIENT_SECRET"),
         refresh_token = get_env("SHAKEN_SPOTIFY_REFRESH_TOKEN"),
+        -- optional, where `spotify.lua` announces the song when it changes
+        announce = "#museun",
     },
+    -- optional, scripts that require `github` are disabled without this (or if the token isn't set)
     github = {
         settings_gist_id = "6f7b1d5e0c293e927959f74c884b039c",
         oauth_token = get_env("SHAKEN_GITHUB_OAUTH_TOKEN")
-    }
+    },
+    commands = {
+        prefixes = { "!" },
+        mention = true,
+        channels = {
+            ["#shaken_bot"] = {
+                prefixes = { "!", "?" },
+                disabled = { "request", "skip" },
+                cooldowns = { song = 30 },
+            },
+        },
+        cooldown = {
+            bypass = "moderator",
+            notify = true,
+        },
+    },
+    sandbox = {
+        enabled = true,
+        trusted = {},
+    },
+    http = {
+        allow = { "wttr.in", "*.docs.rs" },
+        timeout = 10,
+        max_size = 1048576,
+    },
+    limits = {
+        instructions = 10000000,
+        time = 5,
+        memory = 128,
+        strikes = 3,
+        notify = false,
+        workers = 4,
+    },
 }
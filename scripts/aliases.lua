@@ -1,10 +1,14 @@
 ---@type Command
 local alias = {
-    command = "!alias",
+    command = "alias",
     args = "<src> to <dst>",
     help = "aliases a command to another name",
-    elevated = true,
+    access = "vip",
     handler = function(msg, args)
+        -- aliases are stored without a prefix
+        args.src = bot:strip_prefix(msg.channel, args.src)
+        args.dst = bot:strip_prefix(msg.channel, args.dst)
+
         if args.src == args.dst then
             msg:reply(string.format("cannot create a recursive alias for %s", args.src))
             return
@@ -37,11 +41,15 @@ local alias = {
 }
 
 local function redirect(msg)
-    local pattern <const> = "^(!%S+)(.*)$"
+    local invocation = bot:invocation(msg)
+    if not invocation then
+        return Handled.bubble
+    end
 
-    local head, tail = msg.data:match(pattern)
-    head = head or msg.data
-    tail = tail or ""
+    local head, tail = invocation.name, invocation.args
+    if tail ~= "" then
+        tail = " " .. tail
+    end
 
     local item, err = aliases:resolve(head);
     if err ~= nil then
@@ -1,6 +1,6 @@
 ---@param msg Message
 ---@return Handled
-return function(msg)
+local function another_viewer(msg)
     local found = {}
     for part in msg.data:gmatch("%S+") do
         if emotes:has(part) then
@@ -16,3 +16,8 @@ return function(msg)
 
     return Handled.bubble
 end
+
+return {
+    listeners = { another_viewer },
+    requires = { "emotes" }
+}
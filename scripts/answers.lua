@@ -30,26 +30,26 @@ local function create_answers()
             pattern("(?i).*?playlist.*?(\\?)?$")
         },
 
-        [c("!editor")]   = {
+        [c("editor")]   = {
             pattern("(?i)(ide|editor)\\?"),
             pattern("(?i)(what editor\\s*?(is (this|that))?)\\??"),
         },
 
-        [c("!song")]     = {
+        [c("song")]     = {
             pattern("(?i)song name\\??"),
             pattern("(?i)which song\\??"),
             pattern("(?i)what song is (this|that)\\??"),
         },
 
-        [c("!theme")]    = {
+        [c("theme")]    = {
             pattern("(i?)what theme.*?\\??"),
         },
 
-        [c("!font")]     = {
+        [c("font")]     = {
             pattern("(i?)what font.*?\\??"),
         },
 
-        [c("!os")]       = {
+        [c("os")]       = {
             pattern("(?i)what os\\s*?((are you using)|(is this))?\\??")
         },
 
@@ -59,7 +59,7 @@ local function create_answers()
             pattern("(?i)learn.*?rust")
         },
 
-        [c("!project")]  = {
+        [c("project")]  = {
             pattern("(?i)what are (u|you) building\\s?\\??"),
             pattern("(?i)what are you working on\\s?\\??"),
             pattern("(?i)what('s)? project(\\sis this)\\s??"),
@@ -75,7 +75,7 @@ local function create_answers()
             pattern("(?i)how can I sub(scribe)?\\??"),
         },
 
-        [c("!settings")] = {
+        [c("settings")] = {
             pattern("(?i)where can [Ii] get your (settings|config)\\??"),
             pattern("(?i)(what|where are\\s?)?editor (settings|config.*?)\\??"),
             pattern("(?i)^.*?(vscode (settings|config)).*?$")
@@ -0,0 +1,69 @@
+-- this command can't be disabled, otherwise it couldn't be enabled again
+local this <const> = "command"
+
+---@param msg Message
+---@param name string
+---@return string?
+local function resolve(msg, name)
+    local name = bot:strip_prefix(msg.channel, name)
+    if not help:lookup(name) then
+        msg:reply(string.format("%s is not a command", name))
+        return nil
+    end
+    return name
+end
+
+---@type Command
+local command = {
+    command = this,
+    help = "enable or disable commands in this channel",
+    access = "moderator",
+    subcommands = {
+        enable = {
+            args = "<command>",
+            help = "enables a command in this channel",
+            handler = function(msg, args)
+                local name = resolve(msg, args.command)
+                if not name then
+                    return
+                end
+                channels:enable(msg.channel, name)
+                msg:reply(string.format("%s is now enabled", name))
+            end
+        },
+        disable = {
+            args = "<command>",
+            help = "disables a command in this channel",
+            handler = function(msg, args)
+                local name = resolve(msg, args.command)
+                if not name then
+                    return
+                end
+                if name == this then
+                    msg:reply(string.format("%s cannot be disabled", name))
+                    return
+                end
+                channels:disable(msg.channel, name)
+                msg:reply(string.format("%s is now disabled", name))
+            end
+        },
+        reset = {
+            args = "<command>",
+            help = "uses the configured setting for a command in this channel",
+            handler = function(msg, args)
+                local name = resolve(msg, args.command)
+                if not name then
+                    return
+                end
+                if channels:reset(msg.channel, name) then
+                    msg:reply(string.format("%s now uses the configured setting", name))
+                else
+                    msg:reply(string.format("%s wasn't toggled in this channel", name))
+                end
+            end
+        },
+    }
+}
+
+---@type Command[]
+return { command }
@@ -1,24 +1,17 @@
 local ns <const> = "commands"
 
-local function split(s)
-    local head, tail = s:match("^(%S+)%s*(.*)$")
-    if not head then
-        return s, nil
-    end
-    return head, tail
-end
-
 local function is_empty(s)
     return s:match("^%s*$") ~= nil
 end
 
 ---@type Command
 local add = {
-    command = "!add",
+    command = "add",
     args = "<name> <body...>",
     help = "add a command",
-    elevated = true,
+    access = "vip",
     handler = function(msg, args)
+        args.name = bot:strip_prefix(msg.channel, args.name)
         local body = store:get(ns, args.name);
         if body ~= nil then
             msg:reply(string.format("command %s already exists (%s)", args.name, body))
@@ -37,11 +30,12 @@ local add = {
 
 ---@type Command
 local update = {
-    command = "!update",
+    command = "update",
     args = "<name> <body...>",
     help = "update a command",
-    elevated = true,
+    access = "vip",
     handler = function(msg, args)
+        args.name = bot:strip_prefix(msg.channel, args.name)
         local cmd = store:get(ns, args.name)
         if not cmd then
             msg:reply(string.format("command %s does not exist", args.name))
@@ -60,11 +54,12 @@ local update = {
 
 ---@type Command
 local remove = {
-    command = "!remove",
+    command = "remove",
     args = "<name>",
     help = "remove a command",
-    elevated = true,
+    access = "vip",
     handler = function(msg, args)
+        args.name = bot:strip_prefix(msg.channel, args.name)
         if aliases:contains(args.name) then
             aliases:remove(args.name)
             msg:reply(string.format("removed alias %s", args.name))
@@ -84,8 +79,13 @@ local remove = {
 }
 
 local function dispatch(msg)
-    local command, target = split(msg.data)
-    local body = store:get(ns, command) or nil
+    local invocation = bot:invocation(msg)
+    if not invocation then
+        return Handled.bubble
+    end
+
+    local target = invocation.args
+    local body = store:get(ns, invocation.name) or nil
     if body ~= nil then
         if target ~= nil and target ~= "" then
             msg:say(string.format("%s: %s", target, body))
@@ -22,9 +22,10 @@ end
 
 ---@type Command
 local crate = {
-    command = "!crate",
+    command = "crate",
     args = "<crate_name>",
     help = "looks up a crate on crates.io",
+    user_cooldown = 15,
     handler = lookup_crate
 }
 
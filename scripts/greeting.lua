@@ -21,7 +21,7 @@ end
 
 ---@type Command
 local greet = {
-    command = "!hello",
+    command = "hello",
     help = "greets the user",
     handler = function(msg, args)
         local greeting = rand:choose(greetings) or "hello"
@@ -29,22 +29,61 @@ local greet = {
     end
 }
 
----@type Command
-local greet_add = {
-    command = "!greeting",
-    args = "add <greeting>",
-    help = "adds a greeting the bot can use",
-    elevated = true,
-    handler = function(msg, args)
-        if contains(greetings, args.greeting) then
-            msg:reply("that greeting already exists")
-            return
+local function index_of(table, value)
+    for i, key in ipairs(table) do
+        if key == value then
+            return i
         end
-        greetings[#greetings + 1] = args.greeting
-        store:save("greetings", greetings)
-        msg:reply(string.format("added %s as a greeting", args.greeting))
     end
+    return nil
+end
+
+---@type Command
+local greeting = {
+    command = "greeting",
+    help = "manages the greetings the bot can use",
+    subcommands = {
+        add = {
+            args = "<greeting>",
+            help = "adds a greeting the bot can use",
+            access = "vip",
+            handler = function(msg, args)
+                if contains(greetings, args.greeting) then
+                    msg:reply("that greeting already exists")
+                    return
+                end
+                greetings[#greetings + 1] = args.greeting
+                store:save("greetings", greetings)
+                msg:reply(string.format("added %s as a greeting", args.greeting))
+            end
+        },
+        remove = {
+            args = "<greeting>",
+            help = "removes a greeting",
+            access = "vip",
+            handler = function(msg, args)
+                local index = index_of(greetings, args.greeting)
+                if not index then
+                    msg:reply("that greeting doesn't exist")
+                    return
+                end
+                table.remove(greetings, index)
+                store:save("greetings", greetings)
+                msg:reply(string.format("removed %s as a greeting", args.greeting))
+            end
+        },
+        list = {
+            help = "lists the greetings",
+            handler = function(msg, args)
+                if #greetings == 0 then
+                    msg:reply("there are no greetings")
+                    return
+                end
+                msg:reply(table.concat(greetings, ", "))
+            end
+        },
+    }
 }
 
 ---@type Command[]
-return { greet, greet_add, listeners = { greet_user } }
+return { greet, greeting, listeners = { greet_user } }
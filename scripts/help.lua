@@ -1,42 +1,3 @@
-local max_width <const> = 50
-
----@param seq string[]
----@param each fun(string):nil
----@param w integer?
-local function wrap(seq, each, w)
-    local names = {}
-    local width = w or max_width
-
-    for _, help in ipairs(seq) do
-        table.insert(names, help)
-    end
-
-    local text = table.concat(names, " ")
-    local lines = {}
-    local current = ""
-
-    for word in text:gmatch("%S+") do
-        if #current + #word + 1 <= width then
-            if #current == 0 then
-                current = word
-            else
-                current = current .. " " .. word
-            end
-        else
-            table.insert(lines, current)
-            current = word
-        end
-    end
-
-    if #current > 0 then
-        table.insert(lines, current)
-    end
-
-    for _, line in pairs(lines) do
-        each(line)
-    end
-end
-
 local function find_nearest(key)
     local data = help:available_commands()
     return fuzzy.closest(key, data, true)
@@ -45,7 +6,7 @@ end
 local function lookup(msg, key, opts)
     local opts = opts or { closest = false }
 
-    local value = help:lookup(key)
+    local value = help:lookup(key, bot:prefix(msg.channel))
     if not value then
         return false
     end
@@ -116,13 +77,18 @@ end
 ---@type handler
 local function show_help(msg, args)
     if not args.command then
-        local commands = help:available_commands(true)
-        wrap(commands, function(line)
-            msg:say(line)
-        end)
+        local prefix = bot:prefix(msg.channel)
+        local commands = {}
+        for i, command in ipairs(help:available_commands(true)) do
+            commands[i] = prefix .. command
+        end
+        -- long lists shouldn't be cut off
+        msg:say(table.concat(commands, " "), { all = true })
         return
     end
 
+    args.command = bot:strip_prefix(msg.channel, args.command)
+
     if lookup(msg, args.command) then
         return
     end
@@ -140,7 +106,7 @@ end
 
 ---@type Command
 local help = {
-    command = "!help",
+    command = "help",
     args = "<command?>",
     help = "list commands, or looks up a command",
     handler = show_help
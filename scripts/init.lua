@@ -11,8 +11,9 @@ return {
         ["answers"] = require("answers"),
         ["spotify"] = require("spotify"),
         ["aliases"] = require("aliases"),
+        ["permissions"] = require("permissions"),
+        ["channel"] = require("channel"),
+        ["join"] = require("join"),
+        ["another_viewer"] = require("another_viewer"),
     },
-    listeners = {
-        require("another_viewer"),
-    }
 }
@@ -0,0 +1,63 @@
+---@param channel string
+---@return string
+local function normalize(channel)
+    return "#" .. channel:gsub("^#", ""):lower()
+end
+
+---@param channel string
+---@return boolean
+local function is_joined(channel)
+    for _, joined in ipairs(bot:channels()) do
+        if joined == channel then
+            return true
+        end
+    end
+    return false
+end
+
+---@type Command
+local join = {
+    command = "join",
+    args = "<channel>",
+    help = "joins a channel, and stays in it after restarting",
+    access = "broadcaster",
+    handler = function(msg, args)
+        local channel = normalize(args.channel)
+        if is_joined(channel) then
+            msg:reply(string.format("I'm already in %s", channel))
+            return
+        end
+
+        local _, err = bot:join(channel)
+        if err then
+            msg:error(string.format("cannot join %s: %s", channel, err))
+            return
+        end
+        msg:reply(string.format("joined %s", channel))
+    end
+}
+
+---@type Command
+local leave = {
+    command = "leave",
+    args = "<channel?>",
+    help = "leaves a channel (or this one), and doesn't rejoin it after restarting",
+    access = "broadcaster",
+    handler = function(msg, args)
+        local channel = normalize(args.channel or msg.channel)
+        if not is_joined(channel) then
+            msg:reply(string.format("I'm not in %s", channel))
+            return
+        end
+
+        -- say this first, otherwise it won't be sent if we're leaving this channel
+        msg:reply(string.format("leaving %s", channel))
+        local _, err = bot:part(channel)
+        if err then
+            msg:error(string.format("cannot leave %s: %s", channel, err))
+        end
+    end
+}
+
+---@type Command[]
+return { join, leave }
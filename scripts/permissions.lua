@@ -0,0 +1,28 @@
+---@type Command
+local access = {
+    command = "access",
+    args = "<action:allow|deny|reset> <user:@user> <command>",
+    help = "allow, deny or reset a user's access to a command ('*' for all commands)",
+    access = "moderator",
+    handler = function(msg, args)
+        local user = args.user
+        args.command = bot:strip_prefix(msg.channel, args.command)
+
+        if args.action == "allow" then
+            permissions:allow(args.command, user)
+            msg:reply(string.format("%s can now use %s", user, args.command))
+        elseif args.action == "deny" then
+            permissions:deny(args.command, user)
+            msg:reply(string.format("%s can no longer use %s", user, args.command))
+        elseif args.action == "reset" then
+            if permissions:reset(args.command, user) then
+                msg:reply(string.format("reset %s's access for %s", user, args.command))
+            else
+                msg:reply(string.format("%s had no override for %s", user, args.command))
+            end
+        end
+    end
+}
+
+---@type Command[]
+return { access }
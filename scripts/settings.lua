@@ -4,11 +4,10 @@
 ---@field theme_variant string
 ---@field theme_url string
 
-local settings_gist_id <const> = config.github.settings_gist_id;
-
 ---@return Settings|boolean
 local function get_current_settings(msg)
-    local gists = github:get_gist_files(settings_gist_id) or nil
+    -- this is only read when it's needed, the github config is optional
+    local gists = github:get_gist_files(config.github.settings_gist_id) or nil
     local settings = gists["vscode settings.json"] or nil
     if not settings then
         msg:reply("cannot get the current settings :(")
@@ -20,7 +19,7 @@ end
 
 ---@type Command
 local font = {
-    command = "!font",
+    command = "font",
     help = "the current VSCode fonts",
     handler = function(msg, args)
         local settings = get_current_settings(msg)
@@ -40,7 +39,7 @@ local font = {
 
 ---@type Command
 local theme = {
-    command = "!theme",
+    command = "theme",
     help = "the current VSCode theme",
     handler = function(msg, args)
         local settings = get_current_settings(msg)
@@ -56,4 +55,4 @@ local theme = {
 }
 
 ---@type Command[]
-return { theme, font }
+return { theme, font, requires = { "github" } }
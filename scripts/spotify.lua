@@ -12,8 +12,9 @@ end
 
 ---@type Command
 local song = {
-    command = "!song",
+    command = "song",
     help = "tries to get the currently playing song from spotify",
+    cooldown = 10,
     handler = function(msg, args)
         local current = spotify:current()
         if current ~= nil then
@@ -27,7 +28,7 @@ local song = {
 }
 ---@type Command
 local next = {
-    command = "!next",
+    command = "next",
     help = "tries to get the next song from spotify",
     handler = function(msg, args)
         local item = spotify:next()
@@ -45,9 +46,10 @@ local next = {
 
 ---@type Command
 local search = {
-    command = "!search",
+    command = "search",
     args = "<query...>",
     help = "looks up a song by its title on spotify",
+    user_cooldown = 30,
     handler = function(msg, args)
         if msg.channel_id ~= BOT_USER.user_id then
             msg:reply(string.format(
@@ -71,7 +73,7 @@ local search = {
 
 ---@type Command
 local previous = {
-    command = "!previous",
+    command = "previous",
     help = "tries to get the previous song from spotify",
     handler = function(msg, args)
         local item, err = spotify_history:last()
@@ -92,9 +94,9 @@ local previous = {
 
 ---@type Command
 local skip = {
-    command = "!skip",
+    command = "skip",
     help = "tries to skip the current song",
-    elevated = true,
+    access = "vip",
     handler = function(msg, args)
         spotify:skip()
     end
@@ -102,9 +104,10 @@ local skip = {
 
 ---@type Command
 local request = {
-    command = "!request",
+    command = "request",
     args = "<song>",
     help = "requests a song to be played on spotify",
+    user_cooldown = 60,
     handler = function(msg, args)
         local song_request = store:load("spotify") or {}
         if not song_request.enabled then
@@ -134,18 +137,14 @@ local request = {
 
 ---@type Command
 local toggle = {
-    command = "!spotify-toggle",
-    args = "<mode?>",
+    command = "spotify-toggle",
+    args = "<mode:on|off?>",
     help = "enables or disables song request",
-    elevated = true,
+    access = "vip",
     handler = function(msg, args)
         local song_request = store:load("spotify") or {}
         if args.mode then
-            if args.mode == "on" then
-                song_request.enabled = true
-            elseif args.mode == "off" then
-                song_request.enabled = false
-            end
+            song_request.enabled = args.mode == "on"
         else
             song_request.enabled = not song_request.enabled;
         end
@@ -163,7 +162,7 @@ local toggle = {
 
 ---@type Command
 local status = {
-    command = "!spotify-state",
+    command = "spotify-state",
     help = "gets the song request mode state",
     handler = function(msg, args)
         local song_request = store:load("spotify") or {}
@@ -178,5 +177,23 @@ local status = {
     end
 }
 
----@type Command[]
-return { song, next, previous, request, skip, status, toggle, search }
+-- says what's playing in `spotify.announce`, when the song changes
+---@param event SpotifyTrackChanged
+local function announce(event)
+    if event.channel == nil or (event.change ~= "new" and event.change ~= "skipped") then
+        return
+    end
+
+    bot:say(event.channel, string.format("now playing: %s - %s @ %s",
+        join_artists(event),
+        event.name,
+        get_link(event)
+    ))
+end
+
+---@type Module
+return {
+    song, next, previous, request, skip, status, toggle, search,
+    events = { spotify_track_changed = announce },
+    requires = { "spotify" }
+}
@@ -22,7 +22,7 @@ end
 
 ---@type Command
 local uptime = {
-    command = "!uptime",
+    command = "uptime",
     args = "<channel?>",
     help = "get the a twitch stream's uptime",
     ---@param args {channel: string?}
@@ -38,7 +38,7 @@ local uptime = {
 
 ---@type Command
 local viewers = {
-    command = "!viewers",
+    command = "viewers",
     args = "<channel?>",
     help = "get the number of viewers for twitch stream",
     ---@param args {channel: string?}
@@ -53,4 +53,4 @@ local viewers = {
 }
 
 ---@type Command[]
-return { uptime, viewers }
+return { uptime, viewers, requires = { "helix" } }
@@ -1,9 +1,13 @@
-use mlua::UserData;
+use mlua::{IntoLua as _, UserData};
 
-use crate::{irc, GlobalItem};
+use crate::{irc, joined::Joined, GlobalItem, Prefixes, Responder, ResultExt};
 
 pub struct Bot {
     tx: flume::Sender<irc::Message>,
+    queue_depth: irc::QueueDepth,
+    responder: Responder,
+    prefixes: Prefixes,
+    joined: Joined,
 }
 
 impl GlobalItem for Bot {
@@ -11,8 +15,20 @@ impl GlobalItem for Bot {
 }
 
 impl Bot {
-    pub const fn new(tx: flume::Sender<irc::Message>) -> Self {
-        Self { tx }
+    pub const fn new(
+        tx: flume::Sender<irc::Message>,
+        queue_depth: irc::QueueDepth,
+        responder: Responder,
+        prefixes: Prefixes,
+        joined: Joined,
+    ) -> Self {
+        Self {
+            tx,
+            queue_depth,
+            responder,
+            prefixes,
+            joined,
+        }
     }
 }
 
@@ -24,10 +40,63 @@ impl UserData for Bot {
         methods.add_method(
             "reroute_command",
             |_lua, this, (mut msg, command): (irc::Message, Option<String>)| {
-                msg.data = command.unwrap_or(msg.data);
+                if let Some(command) = command {
+                    msg.data = this.prefixes.invoke(&msg.channel, &msg.our_user, &command);
+                }
                 let _ = this.tx.send(msg);
                 Ok(())
             },
         );
+
+        methods.add_method("invocation", |lua, this, msg: irc::Message| {
+            match this.prefixes.parse(&msg) {
+                Some(invocation) => invocation.into_lua(lua),
+                None => Ok(mlua::Value::Nil),
+            }
+        });
+
+        methods.add_method("prefix", |_lua, this, channel: String| {
+            Ok(this.prefixes.primary(&channel).to_string())
+        });
+
+        methods.add_method(
+            "strip_prefix",
+            |_lua, this, (channel, input): (String, String)| {
+                Ok(this.prefixes.strip(&channel, &input).to_string())
+            },
+        );
+
+        methods.add_method("say", |_lua, this, (channel, data): (String, String)| {
+            this.responder.say_to(&channel, data);
+            Ok(())
+        });
+
+        methods.add_method("join", |_lua, this, channel: String| {
+            let result = this.joined.join(&channel);
+            if let Ok(channel) = &result {
+                let channel = channel.clone();
+                this.responder.send(irc::Response::Join { channel });
+            }
+            result.map(|_| true).into_lua_tuple()
+        });
+
+        methods.add_method("part", |_lua, this, channel: String| {
+            let result = this.joined.part(&channel);
+            if let Ok(channel) = &result {
+                let channel = channel.clone();
+                this.responder.send(irc::Response::Part { channel });
+            }
+            result.map(|_| true).into_lua_tuple()
+        });
+
+        methods.add_method("channels", |_lua, this, ()| Ok(this.joined.channels()));
+
+        methods.add_method("queue_depth", |_lua, this, channel: Option<String>| {
+            let depth = match channel {
+                Some(channel) => this.queue_depth.channel(&channel),
+                None => this.queue_depth.total(),
+            };
+            Ok(depth)
+        });
     }
 }
@@ -0,0 +1,122 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use mlua::UserData;
+
+use crate::{config, prefix::normalize, GlobalItem, Mapping, ResultExt, SharedKvSqlStore};
+
+/// Which commands are enabled in which channels
+///
+/// Commands can be turned on or off in a channel at runtime, which takes
+/// precedence over the configuration.
+#[derive(Clone, Debug)]
+pub struct Channels {
+    settings: HashMap<String, config::Channel>,
+    db: SharedKvSqlStore,
+}
+
+impl GlobalItem for Channels {
+    const MODULE: &'static str = "channels";
+}
+
+impl Channels {
+    pub fn new(settings: &config::Commands, db: impl Into<PathBuf>) -> Self {
+        let settings = settings
+            .channels
+            .iter()
+            .map(|(channel, settings)| (normalize(channel), settings.clone()))
+            .collect();
+
+        Self {
+            settings,
+            db: SharedKvSqlStore::new(db),
+        }
+    }
+
+    pub fn is_enabled(&self, channel: &str, mapping: &Mapping) -> bool {
+        if let Some(enabled) = self.toggled(channel, &mapping.command) {
+            return enabled;
+        }
+
+        let Some(settings) = self.settings.get(&normalize(channel)) else {
+            return true;
+        };
+
+        if settings.disabled.contains(&mapping.command) {
+            return false;
+        }
+
+        settings
+            .modules
+            .as_ref()
+            .is_none_or(|modules| modules.contains(&mapping.module))
+    }
+
+    /// The configured cooldown for this command in this channel
+    pub fn cooldown(&self, channel: &str, command: &str) -> Option<Duration> {
+        self.settings
+            .get(&normalize(channel))?
+            .cooldowns
+            .get(command)
+            .and_then(|&secs| Duration::try_from_secs_f64(secs).ok())
+    }
+
+    fn toggled(&self, channel: &str, command: &str) -> Option<bool> {
+        let db = self
+            .db
+            .get()
+            .inspect_err(|err| log::warn!("cannot open channel settings: {err}"))
+            .ok()?;
+
+        db.get(&Self::key(channel, command))
+            .ok()
+            .flatten()
+            .and_then(|value| value.as_bool())
+    }
+
+    fn key(channel: &str, command: &str) -> String {
+        format!("{channel} {command}", channel = normalize(channel))
+    }
+}
+
+impl UserData for Channels {
+    fn add_methods<M>(methods: &mut M)
+    where
+        M: mlua::UserDataMethods<Self>,
+    {
+        methods.add_method(
+            "enable",
+            |_lua, this, (channel, command): (String, String)| {
+                this.db
+                    .get()
+                    .and_then(|db| db.set(&Self::key(&channel, &command), true))
+                    .map(|_| true)
+                    .into_lua_tuple()
+            },
+        );
+
+        methods.add_method(
+            "disable",
+            |_lua, this, (channel, command): (String, String)| {
+                this.db
+                    .get()
+                    .and_then(|db| db.set(&Self::key(&channel, &command), false))
+                    .map(|_| true)
+                    .into_lua_tuple()
+            },
+        );
+
+        methods.add_method("reset", |_lua, this, (channel, command): (String, String)| {
+            this.db
+                .get()
+                .and_then(|db| db.remove(&Self::key(&channel, &command)))
+                .into_lua_tuple()
+        });
+
+        methods.add_method(
+            "toggled",
+            |_lua, this, (channel, command): (String, String)| {
+                Ok(this.toggled(&channel, &command))
+            },
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::{path::PathBuf, process::ExitCode};
+
+pub const USAGE: &str = "\
+usage: yomi [options] [command]
+
+commands:
+  run                    connect to Twitch and run the bot (the default)
+  check                  load the configuration and scripts, and report any problems
+  repl                   chat with the bot from the terminal, without connecting to Twitch
+  db list                list the data stores
+  db dump <name>         print everything in a data store as JSON
+
+options:
+  -c, --config <path>    the configuration file (default: config.lua)
+      --data-dir <dir>   store data here, instead of in `paths.data`
+      --log-level <filter>  e.g. `debug` or `yomi=trace`, instead of RUST_LOG
+  -h, --help             show this";
+
+/// The bot ran, and stopped without an error
+pub const SUCCESS: u8 = 0;
+/// Something went wrong while running
+pub const FAILURE: u8 = 1;
+/// The command line was invalid
+pub const USAGE_ERROR: u8 = 2;
+/// The configuration couldn't be loaded
+pub const CONFIG_ERROR: u8 = 3;
+/// `check` found problems with the scripts
+pub const CHECK_FAILED: u8 = 4;
+
+pub fn exit(code: u8) -> ExitCode {
+    ExitCode::from(code)
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Run,
+    Check,
+    Repl,
+    Db(Db),
+}
+
+#[derive(Debug)]
+pub enum Db {
+    List,
+    Dump { name: String },
+}
+
+#[derive(Debug)]
+pub struct Args {
+    pub command: Command,
+    pub config: PathBuf,
+    pub data_dir: Option<PathBuf>,
+    pub log_level: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("help was requested")]
+    Help,
+
+    #[error("unknown option: {0}")]
+    UnknownOption(String),
+
+    #[error("{0} needs a value")]
+    MissingValue(String),
+
+    #[error("unexpected argument: {0}")]
+    Unexpected(String),
+
+    #[error("db needs either `list` or `dump <name>`")]
+    Db,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Error> {
+        let mut args = args.into_iter();
+
+        let mut config = None;
+        let mut data_dir = None;
+        let mut log_level = None;
+        let mut positional = vec![];
+
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| Error::MissingValue(flag.to_string()))
+            };
+
+            match flag {
+                "-h" | "--help" => return Err(Error::Help),
+                "-c" | "--config" => config = Some(PathBuf::from(value()?)),
+                "--data-dir" => data_dir = Some(PathBuf::from(value()?)),
+                "--log-level" => log_level = Some(value()?),
+                flag if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(Error::UnknownOption(flag.to_string()))
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let command = match positional.next().as_deref() {
+            None | Some("run") => Command::Run,
+            Some("check") => Command::Check,
+            Some("repl") => Command::Repl,
+            Some("db") => match (positional.next().as_deref(), positional.next()) {
+                (Some("list"), None) => Command::Db(Db::List),
+                (Some("dump"), Some(name)) => Command::Db(Db::Dump { name }),
+                _ => return Err(Error::Db),
+            },
+            // `yomi config.lua` used to be the only way to run the bot
+            Some(path) if config.is_none() => {
+                config = Some(PathBuf::from(path));
+                Command::Run
+            }
+            Some(arg) => return Err(Error::Unexpected(arg.to_string())),
+        };
+
+        if let Some(arg) = positional.next() {
+            return Err(Error::Unexpected(arg));
+        }
+
+        Ok(Self {
+            command,
+            config: config.unwrap_or_else(|| PathBuf::from("config.lua")),
+            data_dir,
+            log_level,
+        })
+    }
+}
@@ -1,4 +1,7 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use mlua::{IntoLua, LuaSerdeExt as _};
 
@@ -59,6 +62,19 @@ pub struct Spotify {
 
     #[serde(default)]
     pub refresh_token: Secret<String>,
+
+    /// The channel to announce song changes in, e.g. `#museun`
+    #[serde(default)]
+    pub announce: Option<String>,
+}
+
+impl Spotify {
+    /// None of the credentials were provided, e.g. their environment variables aren't set
+    fn is_unset(&self) -> bool {
+        [&*self.client_id, &**self.client_secret, &**self.refresh_token]
+            .iter()
+            .all(|s| s.trim().is_empty())
+    }
 }
 
 #[derive(Default, Clone, Debug, serde::Deserialize)]
@@ -78,6 +94,419 @@ pub struct Twitch {
 
     #[serde(default)]
     pub client_secret: Secret<String>,
+
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+
+    #[serde(default)]
+    pub split: Split,
+
+    #[serde(default)]
+    pub reconnect: Reconnect,
+
+    #[serde(default)]
+    pub backlog: Backlog,
+
+    #[serde(default)]
+    pub transport: Transport,
+
+    /// Connect here instead of Twitch, e.g. `localhost:6667` (or a URL for `websocket`)
+    #[serde(default)]
+    pub address: Option<String>,
+}
+
+impl Twitch {
+    /// The Helix client id and secret, if both were provided
+    pub fn helix(&self) -> Option<(&str, &str)> {
+        let (id, secret) = (self.client_id.trim(), self.client_secret.trim());
+        (!id.is_empty() && !secret.is_empty()).then_some((id, secret))
+    }
+}
+
+/// How to connect to Twitch chat
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// Plaintext IRC, the OAuth token is sent unencrypted
+    Tcp,
+    /// IRC over TLS
+    #[default]
+    Tls,
+    /// IRC over a secure WebSocket
+    WebSocket,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Split {
+    /// Appended to a message when it continues in the next one
+    #[serde(default = "Split::default_continuation")]
+    pub continuation: String,
+
+    /// The most messages a single response can be split into
+    #[serde(default = "Split::default_max_parts")]
+    pub max_parts: usize,
+}
+
+impl Split {
+    fn default_continuation() -> String {
+        String::from("…")
+    }
+
+    const fn default_max_parts() -> usize {
+        4
+    }
+}
+
+impl Default for Split {
+    fn default() -> Self {
+        Self {
+            continuation: Self::default_continuation(),
+            max_parts: Self::default_max_parts(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct RateLimit {
+    /// Messages allowed per window in a channel we're not a moderator in
+    #[serde(default = "RateLimit::default_user")]
+    pub user: u32,
+
+    /// Messages allowed per window in a channel we're a moderator in
+    #[serde(default = "RateLimit::default_moderator")]
+    pub moderator: u32,
+
+    /// Messages allowed per window across all channels
+    ///
+    /// Twitch allows more in channels we're a moderator in, those use `moderator` instead
+    #[serde(default = "RateLimit::default_global")]
+    pub global: u32,
+
+    /// The window, in seconds
+    #[serde(default = "RateLimit::default_window")]
+    pub window: u64,
+}
+
+impl RateLimit {
+    const fn default_user() -> u32 {
+        20
+    }
+
+    const fn default_moderator() -> u32 {
+        100
+    }
+
+    const fn default_global() -> u32 {
+        20
+    }
+
+    const fn default_window() -> u64 {
+        30
+    }
+
+    pub const fn window(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.window)
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            user: Self::default_user(),
+            moderator: Self::default_moderator(),
+            global: Self::default_global(),
+            window: Self::default_window(),
+        }
+    }
+}
+
+/// How long to wait before reconnecting to Twitch
+///
+/// The delay doubles after every failed attempt, up to `max_delay`
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Reconnect {
+    /// The delay after the first failure, in seconds
+    #[serde(default = "Reconnect::default_min_delay")]
+    pub min_delay: f64,
+
+    /// The longest delay, in seconds
+    #[serde(default = "Reconnect::default_max_delay")]
+    pub max_delay: f64,
+}
+
+impl Reconnect {
+    const fn default_min_delay() -> f64 {
+        1.0
+    }
+
+    const fn default_max_delay() -> f64 {
+        120.0
+    }
+
+    pub fn min_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_secs_f64(self.min_delay.max(0.0))
+    }
+
+    pub fn max_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_secs_f64(self.max_delay.max(self.min_delay).max(0.0))
+    }
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            min_delay: Self::default_min_delay(),
+            max_delay: Self::default_max_delay(),
+        }
+    }
+}
+
+/// Responses that are kept while we're disconnected, to be sent once we reconnect
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Backlog {
+    /// The most responses to keep, the oldest are dropped first
+    #[serde(default = "Backlog::default_max_messages")]
+    pub max_messages: usize,
+
+    /// Responses older than this aren't sent, in seconds
+    #[serde(default = "Backlog::default_max_age")]
+    pub max_age: f64,
+}
+
+impl Backlog {
+    const fn default_max_messages() -> usize {
+        50
+    }
+
+    const fn default_max_age() -> f64 {
+        60.0
+    }
+
+    pub fn max_age(&self) -> std::time::Duration {
+        std::time::Duration::from_secs_f64(self.max_age.max(0.0))
+    }
+}
+
+impl Default for Backlog {
+    fn default() -> Self {
+        Self {
+            max_messages: Self::default_max_messages(),
+            max_age: Self::default_max_age(),
+        }
+    }
+}
+
+/// What the `http` global is allowed to do
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Http {
+    /// Hosts that scripts can make requests to, `*.example.com` allows any subdomain
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    /// How long a request can take, in seconds, unless the script asks for less
+    #[serde(default = "Http::default_timeout")]
+    pub timeout: f64,
+
+    /// The largest response body that will be read, in bytes
+    #[serde(default = "Http::default_max_size")]
+    pub max_size: u64,
+}
+
+impl Http {
+    const fn default_timeout() -> f64 {
+        10.0
+    }
+
+    const fn default_max_size() -> u64 {
+        1024 * 1024
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs_f64(self.timeout.max(0.0))
+    }
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            timeout: Self::default_timeout(),
+            max_size: Self::default_max_size(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Commands {
+    #[serde(default)]
+    pub cooldown: Cooldown,
+
+    /// What commands start with, e.g. `!` for `!song`
+    #[serde(default = "Commands::default_prefixes")]
+    pub prefixes: Vec<String>,
+
+    /// Commands can also be used by mentioning the bot, e.g. `@bot song`
+    #[serde(default = "Commands::default_mention")]
+    pub mention: bool,
+
+    /// Per-channel overrides
+    #[serde(default)]
+    pub channels: HashMap<String, Channel>,
+}
+
+impl Commands {
+    fn default_prefixes() -> Vec<String> {
+        vec![String::from("!")]
+    }
+
+    const fn default_mention() -> bool {
+        true
+    }
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Self {
+            cooldown: Cooldown::default(),
+            prefixes: Self::default_prefixes(),
+            mention: Self::default_mention(),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug, serde::Deserialize)]
+pub struct Channel {
+    /// Replaces the default prefixes in this channel
+    #[serde(default)]
+    pub prefixes: Option<Vec<String>>,
+
+    /// Only commands from these modules are enabled in this channel
+    #[serde(default)]
+    pub modules: Option<Vec<String>>,
+
+    /// These commands are disabled in this channel
+    #[serde(default)]
+    pub disabled: Vec<String>,
+
+    /// Replaces the cooldown (in seconds) of these commands in this channel
+    #[serde(default)]
+    pub cooldowns: HashMap<String, f64>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Cooldown {
+    /// Users at (or above) this access level ignore cooldowns
+    #[serde(default = "Cooldown::default_bypass")]
+    pub bypass: Option<String>,
+
+    /// Tell the user the command is on cooldown, rather than silently ignoring it
+    ///
+    /// This happens once per cooldown, so spamming the command only gets one reply
+    #[serde(default = "Cooldown::default_notify")]
+    pub notify: bool,
+}
+
+impl Cooldown {
+    fn default_bypass() -> Option<String> {
+        Some(String::from("moderator"))
+    }
+
+    const fn default_notify() -> bool {
+        true
+    }
+}
+
+impl Default for Cooldown {
+    fn default() -> Self {
+        Self {
+            bypass: Self::default_bypass(),
+            notify: Self::default_notify(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Limits {
+    /// The most Lua instructions a single handler can run
+    #[serde(default = "Limits::default_instructions")]
+    pub instructions: Option<u64>,
+
+    /// How long a single handler can run, in seconds
+    ///
+    /// Time spent waiting on things like http requests isn't counted
+    #[serde(default = "Limits::default_time")]
+    pub time: f64,
+
+    /// How much memory all of the scripts can use, in megabytes
+    #[serde(default = "Limits::default_memory")]
+    pub memory: Option<usize>,
+
+    /// How many times a handler can go over its budget before its disabled (0 never disables it)
+    #[serde(default = "Limits::default_strikes")]
+    pub strikes: u32,
+
+    /// Tell the chat when a handler was stopped
+    #[serde(default)]
+    pub notify: bool,
+
+    /// How many requests (http, spotify, etc.) handlers can be waiting on at once
+    #[serde(default = "Limits::default_workers")]
+    pub workers: usize,
+}
+
+impl Limits {
+    const fn default_instructions() -> Option<u64> {
+        Some(10_000_000)
+    }
+
+    const fn default_time() -> f64 {
+        5.0
+    }
+
+    const fn default_memory() -> Option<usize> {
+        Some(128)
+    }
+
+    const fn default_strikes() -> u32 {
+        3
+    }
+
+    const fn default_workers() -> usize {
+        4
+    }
+
+    pub fn time(&self) -> std::time::Duration {
+        std::time::Duration::from_secs_f64(self.time.max(0.0))
+    }
+
+    pub fn memory_bytes(&self) -> Option<usize> {
+        self.memory.map(|mb| mb * 1024 * 1024)
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            instructions: Self::default_instructions(),
+            time: Self::default_time(),
+            memory: Self::default_memory(),
+            strikes: Self::default_strikes(),
+            notify: false,
+            workers: Self::default_workers(),
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug, serde::Deserialize)]
+pub struct Sandbox {
+    /// Run scripts in their own restricted environment
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Modules that are not sandboxed
+    #[serde(default)]
+    pub trusted: Vec<String>,
 }
 
 #[derive(Default, Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -89,6 +518,13 @@ pub struct Github {
     pub oauth_token: Secret<String>,
 }
 
+impl Github {
+    /// The token wasn't provided, e.g. its environment variable isn't set
+    fn is_unset(&self) -> bool {
+        self.oauth_token.trim().is_empty()
+    }
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Config {
     #[serde(default)]
@@ -97,32 +533,57 @@ pub struct Config {
     #[serde(default, skip_serializing)]
     pub twitch: Twitch,
 
+    /// Spotify is disabled without this
     #[serde(default, skip_serializing)]
-    pub spotify: Spotify,
+    pub spotify: Option<Spotify>,
 
-    #[serde(default)]
-    pub github: Github,
+    /// GitHub is disabled without this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub github: Option<Github>,
+
+    #[serde(default, skip_serializing)]
+    pub commands: Commands,
+
+    #[serde(default, skip_serializing)]
+    pub limits: Limits,
+
+    #[serde(default, skip_serializing)]
+    pub sandbox: Sandbox,
+
+    #[serde(default, skip_serializing)]
+    pub http: Http,
 }
 
-impl Config {
-    pub fn load(path: impl AsRef<Path>) -> mlua::Result<Self> {
-        fn validate(table: &str, key: &str, input: &str, hint: &str, errors: &mut Vec<String>) {
-            if input.trim().is_empty() {
-                errors.push(format!("error: {table}.{key} is required\nnote: {hint}"));
-            }
-        }
+/// Why the configuration couldn't be loaded
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("cannot read configuration file at {}: {err}", .path.to_string_lossy())]
+    Read { path: PathBuf, err: std::io::Error },
+
+    #[error("cannot evaluate configuration file: {0}")]
+    Lua(#[from] mlua::Error),
+
+    #[error("invalid configuration file:\n{}", indent(.0))]
+    Invalid(Vec<String>),
+}
+
+fn indent(errors: &[String]) -> String {
+    errors
+        .iter()
+        .flat_map(|error| error.lines())
+        .map(|line| format!("  {line}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
+impl Config {
+    /// Loads and validates the configuration, without checking the credentials
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
         let path = path.as_ref();
-        let config = match std::fs::read_to_string(path) {
-            Ok(data) => data,
-            Err(..) => {
-                log::error!(
-                    "cannot read configuration file at {path}",
-                    path = path.to_string_lossy()
-                );
-                std::process::exit(1)
-            }
-        };
+        let config = std::fs::read_to_string(path).map_err(|err| ConfigError::Read {
+            path: path.to_path_buf(),
+            err,
+        })?;
 
         let lua = mlua::Lua::new();
 
@@ -134,6 +595,14 @@ impl Config {
         let value = lua.load(config).eval()?;
         let mut config: Config = lua.from_value(value)?;
 
+        // like helix, these are disabled when none of their credentials were provided
+        if config.spotify.as_ref().is_some_and(Spotify::is_unset) {
+            config.spotify = None;
+        }
+        if config.github.as_ref().is_some_and(Github::is_unset) {
+            config.github = None;
+        }
+
         let mut errors = vec![];
 
         for (table, key, val, hint) in [
@@ -143,54 +612,6 @@ impl Config {
                 &*config.twitch.name,
                 "this is the bots name",
             ),
-            (
-                "twitch",
-                "helix_oauth",
-                &**config.twitch.helix_oauth,
-                "this is an OAuth token",
-            ),
-            (
-                "twitch",
-                "client_id",
-                &*config.twitch.client_id,
-                "this is an public token",
-            ),
-            (
-                "twitch",
-                "client_secret",
-                &**config.twitch.client_secret,
-                "this is an private token",
-            ),
-            (
-                "spotify",
-                "client_id",
-                &*config.spotify.client_id,
-                "this is an public token",
-            ),
-            (
-                "spotify",
-                "client_secret",
-                &*config.spotify.client_secret,
-                "this is an private token",
-            ),
-            (
-                "spotify",
-                "refresh_token",
-                &*config.spotify.refresh_token,
-                "this is an private token",
-            ),
-            (
-                "github",
-                "settings_gist_id",
-                &*config.github.settings_gist_id,
-                "this is the gist for the current user configuration",
-            ),
-            (
-                "github",
-                "oauth_token",
-                &*config.github.oauth_token,
-                "this is an OAuth token",
-            ),
             (
                 "paths",
                 "data",
@@ -222,25 +643,122 @@ impl Config {
             ));
         }
 
-        if !errors.is_empty() {
-            log::warn!("invalid configuration file:");
-            for error in errors {
-                for line in error.lines() {
-                    log::warn!("  {line}");
-                }
-            }
+        for (key, secs) in [
+            ("twitch.reconnect.min_delay", config.twitch.reconnect.min_delay),
+            ("twitch.reconnect.max_delay", config.twitch.reconnect.max_delay),
+            ("twitch.backlog.max_age", config.twitch.backlog.max_age),
+            ("limits.time", config.limits.time),
+            ("http.timeout", config.http.timeout),
+        ] {
+            validate_seconds(key, secs, &mut errors);
+        }
 
-            log::info!(
-                "help:\n  \
-                 you can load secrets from the environment with:\n  \
-                 get_env(key) -> String"
-            );
+        for (channel, settings) in &config.commands.channels {
+            for (command, &secs) in &settings.cooldowns {
+                let key = format!("commands.channels[\"{channel}\"].cooldowns.{command}");
+                validate_seconds(&key, secs, &mut errors);
+            }
+        }
 
-            std::process::exit(1);
+        if !errors.is_empty() {
+            return Err(ConfigError::Invalid(errors));
         }
 
         Ok(config)
     }
+
+    /// Checks the credentials needed to connect to Twitch and the configured integrations
+    pub fn check_credentials(&self) -> Result<(), ConfigError> {
+        let mut required = vec![(
+            "twitch",
+            "helix_oauth",
+            &**self.twitch.helix_oauth,
+            "this is an OAuth token",
+        )];
+
+        // helix is optional, but it needs both of these
+        if !self.twitch.client_id.is_empty() || !self.twitch.client_secret.is_empty() {
+            required.extend([
+                (
+                    "twitch",
+                    "client_id",
+                    &*self.twitch.client_id,
+                    "this is an public token",
+                ),
+                (
+                    "twitch",
+                    "client_secret",
+                    &**self.twitch.client_secret,
+                    "this is an private token",
+                ),
+            ]);
+        }
+
+        if let Some(spotify) = &self.spotify {
+            required.extend([
+                (
+                    "spotify",
+                    "client_id",
+                    &*spotify.client_id,
+                    "this is an public token",
+                ),
+                (
+                    "spotify",
+                    "client_secret",
+                    &*spotify.client_secret,
+                    "this is an private token",
+                ),
+                (
+                    "spotify",
+                    "refresh_token",
+                    &*spotify.refresh_token,
+                    "this is an private token",
+                ),
+            ]);
+        }
+
+        if let Some(github) = &self.github {
+            required.extend([
+                (
+                    "github",
+                    "settings_gist_id",
+                    &*github.settings_gist_id,
+                    "this is the gist for the current user configuration",
+                ),
+                (
+                    "github",
+                    "oauth_token",
+                    &*github.oauth_token,
+                    "this is an OAuth token",
+                ),
+            ]);
+        }
+
+        let mut errors = vec![];
+        for (table, key, val, hint) in required {
+            validate(table, key, val, hint, &mut errors);
+        }
+
+        if !errors.is_empty() {
+            return Err(ConfigError::Invalid(errors));
+        }
+        Ok(())
+    }
+}
+
+fn validate(table: &str, key: &str, input: &str, hint: &str, errors: &mut Vec<String>) {
+    if input.trim().is_empty() {
+        errors.push(format!("error: {table}.{key} is required\nnote: {hint}"));
+    }
+}
+
+// a duration has to be added to the current time, so it can't be arbitrarily large
+fn validate_seconds(key: &str, secs: f64, errors: &mut Vec<String>) {
+    let valid = std::time::Duration::try_from_secs_f64(secs)
+        .is_ok_and(|duration| std::time::Instant::now().checked_add(duration).is_some());
+    if !valid {
+        errors.push(format!("error: {key} is invalid: {secs}\nnote: this is a number of seconds"));
+    }
 }
 
 impl IntoLua for &Config {
@@ -11,12 +11,15 @@ impl UserData for Crates {
     where
         M: mlua::UserDataMethods<Self>,
     {
-        methods.add_function("lookup", |_lua, name: String| Ok(lookup_crate(&name)));
+        methods.add_function("lookup", |lua, name: String| {
+            crate::manifest::spawn(lua, move || Ok(lookup_crate(&name)))
+        });
     }
 }
 
 impl GlobalItem for Crates {
     const MODULE: &'static str = "crates";
+    const ASYNC_METHODS: &'static [&'static str] = &["lookup"];
 }
 
 pub fn lookup_crate(name: &str) -> Option<Crate> {
@@ -24,7 +24,7 @@ impl UserData for Search {
     }
 }
 
-fn closest(query: &str, choices: &[String], case_insensitive: bool) -> Option<String> {
+pub(crate) fn closest(query: &str, choices: &[String], case_insensitive: bool) -> Option<String> {
     fn normalize(s: &str, case_insensitive: bool) -> Cow<'_, str> {
         if case_insensitive {
             Cow::from(s.to_lowercase())
@@ -10,12 +10,14 @@ pub enum Error {
     Http(#[from] attohttpc::Error),
 }
 
+#[derive(Clone)]
 pub struct Client {
     bearer_token: String,
 }
 
 impl GlobalItem for Client {
     const MODULE: &'static str = "github";
+    const ASYNC_METHODS: &'static [&'static str] = &["get_gist_files"];
 }
 
 impl UserData for Client {
@@ -23,8 +25,11 @@ impl UserData for Client {
     where
         M: mlua::UserDataMethods<Self>,
     {
-        methods.add_method("get_gist_files", |_lua, this, id: String| {
-            this.get_gist_files(&id).map_err(mlua::Error::external)
+        methods.add_method("get_gist_files", |lua, this, id: String| {
+            let this = this.clone();
+            crate::manifest::spawn(lua, move || {
+                this.get_gist_files(&id).map_err(mlua::Error::external)
+            })
         });
     }
 }
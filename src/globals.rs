@@ -15,6 +15,11 @@ impl<'a> Globals<'a> {
     pub fn get<T: FromLua>(&self, key: &str) -> Result<T, mlua::Error> {
         self.0.globals().get(key)
     }
+
+    /// Sets `key` to an empty table, so modules that require it can be loaded without it
+    pub fn stub(&self, key: &str) -> Result<(), mlua::Error> {
+        self.set(key, self.0.create_table()?)
+    }
 }
 
 impl Globals<'_> {
@@ -25,8 +30,16 @@ impl Globals<'_> {
 
 pub trait GlobalItem: IntoLua {
     const MODULE: &'static str;
+
+    /// Methods that make requests, handlers wait on these without blocking the main loop
+    const ASYNC_METHODS: &'static [&'static str] = &[];
+
     fn register(self, g: Globals<'_>) -> mlua::Result<()> {
-        g.set(Self::MODULE, self)
+        if Self::ASYNC_METHODS.is_empty() {
+            return g.set(Self::MODULE, self);
+        }
+        let value = crate::manifest::wrap(g.0, self.into_lua(g.0)?, Self::ASYNC_METHODS)?;
+        g.set(Self::MODULE, value)
     }
 }
 
@@ -35,7 +48,8 @@ where
     for<'a> &'a T: IntoLua,
 {
     const MODULE: &'static str = <T as GlobalItem>::MODULE;
+    const ASYNC_METHODS: &'static [&'static str] = <T as GlobalItem>::ASYNC_METHODS;
     fn register(self, g: Globals<'_>) -> mlua::Result<()> {
-        g.set(Self::MODULE, self.clone())
+        self.clone().register(g)
     }
 }
@@ -1,6 +1,6 @@
 use std::collections::{HashMap, HashSet};
 
-use mlua::{IntoLua, UserData};
+use mlua::UserData;
 
 use crate::GlobalItem;
 
@@ -14,6 +14,7 @@ pub enum Error {
     Http(#[from] attohttpc::Error),
 }
 
+#[derive(Clone)]
 pub struct Client {
     agent: attohttpc::Session,
     oauth: OAuth,
@@ -22,6 +23,7 @@ pub struct Client {
 
 impl GlobalItem for Client {
     const MODULE: &'static str = "helix";
+    const ASYNC_METHODS: &'static [&'static str] = &["get_stream", "get_emotes_for"];
 }
 
 impl Client {
@@ -94,21 +96,26 @@ impl UserData for Client {
         M: mlua::UserDataMethods<Self>,
     {
         methods.add_method("get_stream", |lua, this, name: String| {
-            let name = name.strip_prefix('#').unwrap_or(&name);
-            let mut list = this.get_streams([name]).map_err(mlua::Error::external)?;
-            let item = match list.len() {
-                0 => return Ok(mlua::Value::Nil),
-                1 => list.pop().unwrap(),
-                _ => list.remove(0),
-            };
-            item.into_lua(lua)
+            let this = this.clone();
+            crate::manifest::spawn(lua, move || {
+                let name = name.strip_prefix('#').unwrap_or(&name);
+                let mut list = this.get_streams([name]).map_err(mlua::Error::external)?;
+                Ok(match list.len() {
+                    0 => None,
+                    1 => list.pop(),
+                    _ => Some(list.remove(0)),
+                })
+            })
         });
 
-        methods.add_method("get_emotes_for", |_lua, this, broadcaster_id: String| {
-            let (_, emotes) = this
-                .get_emotes_for(&broadcaster_id)
-                .map_err(mlua::Error::external)?;
-            Ok(emotes)
+        methods.add_method("get_emotes_for", |lua, this, broadcaster_id: String| {
+            let this = this.clone();
+            crate::manifest::spawn(lua, move || {
+                let (_, emotes) = this
+                    .get_emotes_for(&broadcaster_id)
+                    .map_err(mlua::Error::external)?;
+                Ok(emotes)
+            })
         });
     }
 }
@@ -6,15 +6,26 @@ use crate::{AliasesDb, KvSqlStore, Mapping};
 
 struct Help {
     command: String,
-    usage: String,
+    // a command with subcommands has several
+    usages: Vec<String>,
     description: String,
 }
 
+impl Help {
+    fn usage(&self, prefix: &str) -> String {
+        self.usages
+            .iter()
+            .map(|usage| format!("{prefix}{usage}"))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+}
+
 impl IntoLua for &Help {
     fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
         lua.create_table_from([
             ("command", &*self.command),
-            ("usage", &*self.usage),
+            ("usage", &*self.usage("")),
             ("description", &*self.description),
         ])
         .map(mlua::Value::Table)
@@ -63,23 +74,24 @@ impl mlua::UserData for HelpProvider {
             lua.create_sequence_from(this.list.iter())
         });
 
-        methods.add_method("lookup", |lua, this, pat: String| {
-            let Help {
-                usage, description, ..
-            } = match this
-                .list
-                .iter()
-                .find(|Help { command, .. }| command == &pat)
-            {
-                Some(help) => help,
-                None => return Ok(mlua::Value::Nil),
-            };
+        methods.add_method(
+            "lookup",
+            |lua, this, (pat, prefix): (String, Option<String>)| {
+                let help = match this
+                    .list
+                    .iter()
+                    .find(|Help { command, .. }| command == &pat)
+                {
+                    Some(help) => help,
+                    None => return Ok(mlua::Value::Nil),
+                };
 
-            let table = lua.create_table()?;
-            table.set("usage", usage.as_str())?;
-            table.set("description", description.as_str())?;
-            Ok(mlua::Value::Table(table))
-        });
+                let table = lua.create_table()?;
+                table.set("usage", help.usage(prefix.as_deref().unwrap_or_default()))?;
+                table.set("description", help.description.as_str())?;
+                Ok(mlua::Value::Table(table))
+            },
+        );
     }
 }
 
@@ -94,10 +106,7 @@ impl HelpProvider {
             .iter()
             .map(|mapping| Help {
                 command: mapping.command.clone(),
-                usage: match &mapping.raw_pattern {
-                    Some(p) => format!("{} {p}", mapping.command),
-                    None => mapping.command.clone(),
-                },
+                usages: mapping.usages(),
                 description: mapping.help.clone(),
             })
             .collect();
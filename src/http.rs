@@ -0,0 +1,259 @@
+use std::{collections::HashMap, io::Read as _, time::Duration};
+
+use mlua::{LuaSerdeExt as _, UserData};
+use url::Url;
+
+use crate::{config, GlobalItem, Json, ResultExt as _};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid url: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+
+    #[error("only http and https urls are allowed")]
+    InvalidScheme,
+
+    #[error("{0} isn't in http.allow")]
+    NotAllowed(String),
+
+    #[error("invalid header: {0}")]
+    InvalidHeader(String),
+
+    #[error("the response was larger than {0} bytes")]
+    TooLarge(u64),
+
+    #[error("http error: {0}")]
+    Http(#[from] attohttpc::Error),
+
+    #[error("cannot read the response: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Lets scripts make http requests to the hosts in `http.allow`
+#[derive(Clone)]
+pub struct Http {
+    allow: Vec<String>,
+    timeout: Duration,
+    max_size: u64,
+}
+
+impl GlobalItem for Http {
+    const MODULE: &'static str = "http";
+    const ASYNC_METHODS: &'static [&'static str] = &["get", "post"];
+}
+
+impl Http {
+    pub fn new(config: &config::Http) -> Self {
+        Self {
+            allow: config.allow.iter().map(|host| host.to_lowercase()).collect(),
+            timeout: config.timeout(),
+            max_size: config.max_size,
+        }
+    }
+
+    fn is_allowed(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        self.allow.iter().any(|allowed| match allowed.strip_prefix("*.") {
+            Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.')),
+            None => allowed == host,
+        })
+    }
+
+    fn send(&self, method: attohttpc::Method, url: &str, opts: Options) -> Result<Response, Error> {
+        let url = Url::parse(url)?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Error::InvalidScheme);
+        }
+
+        let host = url.host_str().unwrap_or_default().to_string();
+        if !self.is_allowed(&host) {
+            return Err(Error::NotAllowed(host));
+        }
+
+        let timeout = opts
+            .timeout
+            .map(Duration::from_secs_f64)
+            .map_or(self.timeout, |timeout| timeout.min(self.timeout));
+
+        // redirects could go anywhere, so the script has to follow them itself
+        let mut req = attohttpc::RequestBuilder::new(method, url)
+            .header("user-agent", crate::USER_AGENT)
+            .follow_redirects(false)
+            .timeout(timeout)
+            .params(opts.query);
+
+        let body = match (opts.body, opts.json) {
+            (_, Some(json)) => {
+                req = req.header("content-type", "application/json");
+                Some(serde_json::to_vec(&json).expect("valid json"))
+            }
+            (Some(body), None) => Some(body.into_bytes()),
+            (None, None) => None,
+        };
+
+        for (name, value) in opts.headers {
+            let name = attohttpc::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| Error::InvalidHeader(name.clone()))?;
+            req = req
+                .try_header(name, value.as_str())
+                .map_err(|_| Error::InvalidHeader(value))?;
+        }
+
+        let resp = match body {
+            Some(body) => req.bytes(body).send()?,
+            None => req.send()?,
+        };
+
+        let (status, headers, reader) = resp.split();
+
+        let mut body = vec![];
+        reader.take(self.max_size + 1).read_to_end(&mut body)?;
+        if body.len() as u64 > self.max_size {
+            return Err(Error::TooLarge(self.max_size));
+        }
+
+        let mut map = HashMap::<String, String>::new();
+        for (name, value) in &headers {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            map.entry(name.as_str().to_string())
+                .and_modify(|existing| {
+                    existing.push_str(", ");
+                    existing.push_str(&value);
+                })
+                .or_insert_with(|| value.to_string());
+        }
+
+        Ok(Response {
+            status: status.as_u16(),
+            headers: map,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }
+}
+
+impl UserData for Http {
+    fn add_methods<M>(methods: &mut M)
+    where
+        M: mlua::UserDataMethods<Self>,
+    {
+        methods.add_method("get", |lua, this, (url, opts): (String, mlua::Value)| {
+            let (this, opts) = (this.clone(), Options::parse(lua, opts)?);
+            crate::manifest::spawn(lua, move || {
+                this.send(attohttpc::Method::GET, &url, opts).into_lua_tuple()
+            })
+        });
+
+        methods.add_method("post", |lua, this, (url, opts): (String, mlua::Value)| {
+            let (this, opts) = (this.clone(), Options::parse(lua, opts)?);
+            crate::manifest::spawn(lua, move || {
+                this.send(attohttpc::Method::POST, &url, opts).into_lua_tuple()
+            })
+        });
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+struct Options {
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    query: HashMap<String, String>,
+    body: Option<String>,
+    json: Option<serde_json::Value>,
+    timeout: Option<f64>,
+}
+
+impl Options {
+    fn parse(lua: &mlua::Lua, value: mlua::Value) -> mlua::Result<Self> {
+        let opts: Self = match value {
+            mlua::Value::Nil => Self::default(),
+            value => lua.from_value(value)?,
+        };
+
+        match opts.timeout {
+            Some(secs) if !Duration::try_from_secs_f64(secs).is_ok_and(|d| !d.is_zero()) => {
+                Err(mlua::Error::runtime(format!(
+                    "a timeout must be a positive number of seconds, got: {secs}"
+                )))
+            }
+            _ => Ok(opts),
+        }
+    }
+}
+
+pub struct Response {
+    status: u16,
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+impl UserData for Response {
+    fn add_fields<F>(fields: &mut F)
+    where
+        F: mlua::UserDataFields<Self>,
+    {
+        fields.add_field_method_get("status", |_lua, this| Ok(this.status));
+        fields.add_field_method_get("ok", |_lua, this| Ok((200..300).contains(&this.status)));
+        fields.add_field_method_get("headers", |_lua, this| Ok(this.headers.clone()));
+        fields.add_field_method_get("body", |_lua, this| Ok(this.body.clone()));
+    }
+
+    fn add_methods<M>(methods: &mut M)
+    where
+        M: mlua::UserDataMethods<Self>,
+    {
+        methods.add_method("json", |lua, this, ()| Json::from_str(lua, &this.body));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http(allow: &[&str]) -> Http {
+        Http::new(&config::Http {
+            allow: allow.iter().map(|host| host.to_string()).collect(),
+            ..config::Http::default()
+        })
+    }
+
+    #[test]
+    fn exact() {
+        let http = http(&["docs.rs"]);
+        assert!(http.is_allowed("docs.rs"));
+        assert!(!http.is_allowed("a.docs.rs"));
+        assert!(!http.is_allowed("evildocs.rs"));
+    }
+
+    #[test]
+    fn subdomains() {
+        let http = http(&["*.docs.rs"]);
+        assert!(http.is_allowed("a.docs.rs"));
+        assert!(http.is_allowed("a.b.docs.rs"));
+        assert!(!http.is_allowed("docs.rs"));
+        assert!(!http.is_allowed("evildocs.rs"));
+        assert!(!http.is_allowed("docs.rs.example.com"));
+    }
+
+    #[test]
+    fn mixed_case() {
+        let http = http(&["*.Docs.RS", "Example.com"]);
+        assert!(http.is_allowed("A.DOCS.rs"));
+        assert!(http.is_allowed("example.COM"));
+        assert!(!http.is_allowed("EvilDocs.rs"));
+    }
+
+    #[test]
+    fn timeout() {
+        let lua = mlua::Lua::new();
+        let parse = |code: &str| Options::parse(&lua, lua.load(code).eval().unwrap());
+
+        assert_eq!(parse("{ timeout = 5 }").unwrap().timeout, Some(5.0));
+        assert!(parse("nil").unwrap().timeout.is_none());
+
+        for timeout in ["math.huge", "1e30", "-1", "0", "0/0"] {
+            let code = format!("{{ timeout = {timeout} }}");
+            assert!(parse(&code).is_err(), "{timeout} should be rejected");
+        }
+    }
+}
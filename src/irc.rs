@@ -2,23 +2,44 @@ use std::{future::Future, time::Duration};
 
 use mlua::{AnyUserData, FromLua, IntoLua};
 use serde::de::Error;
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt},
-    net::TcpStream,
-};
 use twitch_message::{
     encode::{self, Encodable, ALL_CAPABILITIES},
     messages::{MsgIdRef, Privmsg, TwitchMessage},
     IntoStatic, PingTracker,
 };
 
-use crate::{config::Twitch, responder::Responder, GlobalItem};
+use crate::{
+    config::Twitch,
+    joined::Joined,
+    responder::{Responder, SendOptions},
+    GlobalItem,
+};
+
+mod limiter;
+pub use limiter::QueueDepth;
+use limiter::SendQueue;
+
+mod backlog;
+use backlog::Backlog;
+
+mod connection;
+use connection::Backoff;
+
+mod transport;
+use transport::{LineReader, LineWriter};
+pub use connection::{Connection, Disconnect, Disconnected};
+
+mod events;
+pub use events::{Clear, Deleted, Membership, Notice, NoticeKind, Room};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Response {
     Join {
         channel: String,
     },
+    Part {
+        channel: String,
+    },
     Error {
         channel: String,
         data: String,
@@ -35,6 +56,18 @@ pub enum Response {
     Disconnect,
 }
 
+impl Response {
+    /// The channel this response is rate limited against, if any
+    pub fn channel(&self) -> Option<&str> {
+        match self {
+            Self::Error { channel, .. }
+            | Self::Reply { channel, .. }
+            | Self::Say { channel, .. } => Some(channel),
+            Self::Join { .. } | Self::Part { .. } | Self::Disconnect => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Message {
     pub our_user: String,
@@ -48,10 +81,12 @@ pub struct Message {
     pub class: MessageClass,
 }
 
-#[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub enum MessageClass {
     #[default]
     User,
+    Subscriber,
+    Founder,
     Vip,
     Moderator,
     Broadcaster,
@@ -59,6 +94,7 @@ pub enum MessageClass {
 
 impl MessageClass {
     pub fn classify(msg: &Privmsg) -> Self {
+        let has_badge = |name: &str| msg.badges().any(|badge| badge.name == name);
         match (
             msg.is_from_broadcaster(),
             msg.is_from_moderator(),
@@ -67,9 +103,36 @@ impl MessageClass {
             (true, _, _) => Self::Broadcaster,
             (_, true, _) => Self::Moderator,
             (_, _, true) => Self::Vip,
+            _ if has_badge("founder") => Self::Founder,
+            _ if has_badge("subscriber") => Self::Subscriber,
             _ => Self::User,
         }
     }
+
+    /// Parses an access level, as used by `access = "moderator"` in a command
+    pub fn parse_access(input: &str) -> Option<Self> {
+        let class = match input {
+            "user" | "everyone" => Self::User,
+            "subscriber" => Self::Subscriber,
+            "founder" => Self::Founder,
+            "vip" => Self::Vip,
+            "moderator" => Self::Moderator,
+            "broadcaster" => Self::Broadcaster,
+            _ => return None,
+        };
+        Some(class)
+    }
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Subscriber => "subscriber",
+            Self::Founder => "founder",
+            Self::Vip => "vip",
+            Self::Moderator => "moderator",
+            Self::Broadcaster => "broadcaster",
+        }
+    }
 }
 
 impl IntoLua for MessageClass {
@@ -100,8 +163,16 @@ impl Message {
         matches!(self.class, MessageClass::Vip)
     }
 
+    pub const fn is_from_subscriber(&self) -> bool {
+        matches!(self.class, MessageClass::Subscriber | MessageClass::Founder)
+    }
+
+    /// Not a broadcaster, moderator or vip, subscribers and founders are users too
     pub const fn is_from_user(&self) -> bool {
-        matches!(self.class, MessageClass::User)
+        matches!(
+            self.class,
+            MessageClass::User | MessageClass::Subscriber | MessageClass::Founder
+        )
     }
 
     pub const fn is_elevated(&self) -> bool {
@@ -136,8 +207,8 @@ impl IntoLua for &Message {
             "reply",
             lua.create_function({
                 let responder = responder.clone();
-                move |_lua, (this, data): (Message, String)| {
-                    responder.reply(&this, data);
+                move |_lua, (this, data, options): (Message, String, SendOptions)| {
+                    responder.reply_with(&this, data, options);
                     Ok(())
                 }
             })?,
@@ -147,8 +218,8 @@ impl IntoLua for &Message {
             "say",
             lua.create_function({
                 let responder = responder.clone();
-                move |_lua, (this, data): (Message, String)| {
-                    responder.say(&this, data);
+                move |_lua, (this, data, options): (Message, String, SendOptions)| {
+                    responder.say_with(&this.channel, data, options);
                     Ok(())
                 }
             })?,
@@ -186,11 +257,26 @@ impl IntoLua for &Message {
             lua.create_function(move |_lua, this: Message| Ok(this.is_from_moderator()))?,
         )?;
 
+        table.set(
+            "is_from_subscriber",
+            lua.create_function(move |_lua, this: Message| Ok(this.is_from_subscriber()))?,
+        )?;
+
         table.set(
             "is_from_user",
             lua.create_function(move |_lua, this: Message| Ok(this.is_from_user()))?,
         )?;
 
+        table.set(
+            "has_access",
+            lua.create_function(move |_lua, (this, access): (Message, String)| {
+                let access = MessageClass::parse_access(&access).ok_or_else(|| {
+                    mlua::Error::runtime(format!("unknown access level: {access}"))
+                })?;
+                Ok(this.class >= access)
+            })?,
+        )?;
+
         table.set(
             "is_elevated",
             lua.create_function(move |_lua, this: Message| Ok(this.is_elevated()))?,
@@ -245,11 +331,21 @@ impl IntoLua for &User {
 #[derive(Debug)]
 pub enum Event {
     Connected { user: User },
-    Disconnected {},
+    Disconnected { disconnected: Disconnected },
     Message { msg: Privmsg<'static> },
+    Notice { notice: Notice },
+    Clear { clear: Clear },
+    Deleted { deleted: Deleted },
+    Room { room: Room },
+    Membership { membership: Membership },
 }
 
-pub fn connect(config: Twitch, response: flume::Receiver<Response>) -> flume::Receiver<Event> {
+pub fn connect(
+    config: Twitch,
+    response: flume::Receiver<Response>,
+    depth: QueueDepth,
+    joined: Joined,
+) -> flume::Receiver<Event> {
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_io()
         .enable_time()
@@ -259,12 +355,53 @@ pub fn connect(config: Twitch, response: flume::Receiver<Response>) -> flume::Re
     let (events, out) = flume::unbounded();
     let _ = std::thread::spawn(move || {
         rt.block_on(async move {
+            let mut backoff = Backoff::new(&config.reconnect);
+            let mut backlog = Backlog::new(&config.backlog, depth);
             loop {
-                // std::future::pending::<()>().await;
-                match connect_to_twitch(config.clone(), &events, &response).await {
-                    Next::Restart => continue,
+                let next = connect_to_twitch(
+                    config.clone(),
+                    &events,
+                    &response,
+                    &joined,
+                    &mut backoff,
+                    &mut backlog,
+                );
+                let reason = match next.await {
+                    Next::Restart(reason) => reason,
                     Next::Stop => return,
-                    Next::Nothing => {}
+                    Next::Nothing => continue,
+                };
+
+                let retry_in = match &reason {
+                    Disconnect::Auth { .. } => None,
+                    // these aren't failures, so don't wait any longer than we have to
+                    Disconnect::Reconnect | Disconnect::Requested => {
+                        backoff.reset();
+                        Some(backoff.next_delay())
+                    }
+                    Disconnect::Network { .. } => Some(backoff.next_delay()),
+                };
+
+                match retry_in {
+                    Some(delay) => log::warn!("{reason}, reconnecting in {delay:.1?}"),
+                    None => log::error!("{reason}, not reconnecting"),
+                }
+
+                let disconnected = Disconnected { reason, retry_in };
+                if !send(&events, Event::Disconnected { disconnected }) {
+                    return;
+                }
+
+                let Some(delay) = retry_in else { return };
+
+                // keep anything the scripts want to send until we're connected again
+                let sleep = tokio::time::sleep(delay);
+                tokio::pin!(sleep);
+                loop {
+                    tokio::select! {
+                        _ = &mut sleep => break,
+                        Ok(queued) = response.recv_async() => backlog.push(queued),
+                    }
                 }
             }
         });
@@ -273,7 +410,7 @@ pub fn connect(config: Twitch, response: flume::Receiver<Response>) -> flume::Re
 }
 
 enum Next {
-    Restart,
+    Restart(Disconnect),
     Stop,
     Nothing,
 }
@@ -282,28 +419,33 @@ fn send(events: &flume::Sender<Event>, event: Event) -> bool {
     events.send(event).is_ok()
 }
 
-async fn maybe_reconnect(events: &flume::Sender<Event>, msg: std::fmt::Arguments<'_>) -> Next {
-    log::warn!("{msg}");
-    if !send(events, Event::Disconnected {}) {
-        return Next::Stop;
-    }
-    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-    Next::Restart
+const fn reconnect(reason: Disconnect) -> Next {
+    Next::Restart(reason)
+}
+
+// Twitch sends a NOTICE and closes the connection if it doesn't like our token
+fn auth_failure(line: &str) -> Option<&str> {
+    let (_, message) = line.split_once(" NOTICE * :")?;
+    let message = message.trim_end();
+    ["Login authentication failed", "Improperly formatted auth"]
+        .iter()
+        .any(|failure| message.starts_with(failure))
+        .then_some(message)
 }
 
 async fn connect_to_twitch(
     config: Twitch,
     events: &flume::Sender<Event>,
     response: &flume::Receiver<Response>,
+    joined: &Joined,
+    backoff: &mut Backoff,
+    backlog: &mut Backlog,
 ) -> Next {
-    let stream = match TcpStream::connect(twitch_message::TWITCH_IRC_ADDRESS).await {
+    let stream = transport::connect(config.transport, config.address.as_deref()).await;
+    let (mut lines, mut write) = match stream {
         Ok(stream) => stream,
         Err(err) => {
-            return maybe_reconnect(
-                events,
-                format_args!("cannot connect, trying again because: {err}"),
-            )
-            .await;
+            return reconnect(Disconnect::network(format_args!("cannot connect: {err}")));
         }
     };
 
@@ -313,40 +455,38 @@ async fn connect_to_twitch(
         user_id: String::new(),
     };
 
-    let (read, mut write) = stream.into_split();
-    let read = tokio::io::BufReader::new(read);
-
     let msg = encode::register(
         &config.name, //
         &config.helix_oauth,
         ALL_CAPABILITIES,
     );
 
-    match encode_to(msg, &mut write, events).await {
+    match encode_to(msg, &mut write).await {
         Next::Nothing => {}
         next => return next,
     }
 
     let pt = PingTracker::new(Duration::from_secs(3 * 60));
 
-    let mut lines = read.lines();
-    // is it this one?
-
     'outer: loop {
         let data = match lines.next_line().await {
             Ok(Some(data)) => data,
-            Ok(None) => {
-                return maybe_reconnect(events, format_args!("read an unexpected eof")).await
-            }
+            Ok(None) => return reconnect(Disconnect::network(format_args!("unexpected EOF"))),
             Err(err) => {
-                return maybe_reconnect(events, format_args!("cannot read line: {err}")).await
+                return reconnect(Disconnect::network(format_args!("cannot read line: {err}")))
             }
         };
 
+        if let Some(error) = auth_failure(&data) {
+            return reconnect(Disconnect::Auth {
+                error: error.to_string(),
+            });
+        }
+
         for msg in twitch_message::parse_many(&data).flatten() {
             pt.update(&msg);
             if let Some(msg) = pt.should_pong() {
-                match encode_to(msg, &mut write, events).await {
+                match encode_to(msg, &mut write).await {
                     Next::Nothing => {}
                     next => return next,
                 }
@@ -364,6 +504,7 @@ async fn connect_to_twitch(
                         .user_id()
                         .map(ToString::to_string)
                         .expect("we must have a user id");
+                    backoff.reset();
                     break 'outer;
                 }
                 _ => {}
@@ -371,11 +512,26 @@ async fn connect_to_twitch(
         }
     }
 
-    let next = main_loop(user, events, response, lines, &mut write).await;
+    // join before sending anything from the backlog
+    for channel in joined.channels() {
+        match encode_to(encode::join(&channel), &mut write).await {
+            Next::Nothing => {}
+            next => return next,
+        }
+    }
+
+    let mut queue = SendQueue::new(config.rate_limit.clone(), backlog.depth());
+    backlog.extend(response.try_iter());
+    for queued in backlog.take() {
+        queue.push(queued);
+    }
+
+    let next = main_loop(user, events, response, &mut queue, lines, &mut write).await;
+    backlog.extend(queue.take_pending());
 
     log::info!("sending quit message");
     let quit = encode::quit("bye");
-    let _ = encode_to(quit, &mut write, events).await;
+    let _ = encode_to(quit, &mut write).await;
     next
 }
 
@@ -383,8 +539,9 @@ async fn main_loop(
     user: User,
     events: &flume::Sender<Event>,
     response: &flume::Receiver<Response>,
-    mut stream: tokio::io::Lines<tokio::io::BufReader<tokio::net::tcp::OwnedReadHalf>>,
-    mut write: &mut (impl AsyncWrite + Unpin),
+    queue: &mut SendQueue,
+    mut stream: LineReader,
+    write: &mut LineWriter,
 ) -> Next {
     log::info!("connected to Twitch: {user:#?}");
 
@@ -394,44 +551,46 @@ async fn main_loop(
 
     let pt = PingTracker::new(Duration::from_secs(3 * 60));
 
-    // drain any pending responses
-    for _ in response.try_iter() {}
-
     loop {
+        match flush(queue, write).await {
+            Next::Nothing => {}
+            next => return next,
+        }
+
         tokio::pin! {
             let read_line = stream.next_line();
             let next_response = response.recv_async();
         }
 
-        let msg = match select2(&mut read_line, &mut next_response).await {
+        let msg = match select(&mut read_line, &mut next_response, queue.next_ready()).await {
             Either::Left(Err(err)) => {
-                return maybe_reconnect(events, format_args!("cannot read: {err}")).await;
+                return reconnect(Disconnect::network(format_args!("cannot read: {err}")));
             }
 
             Either::Left(Ok(None)) => {
-                return maybe_reconnect(events, format_args!("unexpected EOF")).await;
+                return reconnect(Disconnect::network(format_args!("unexpected EOF")));
             }
 
             Either::Left(Ok(Some(data))) => {
                 for msg in twitch_message::parse_many(&data).flatten() {
                     pt.update(&msg);
                     if let Some(msg) = pt.should_pong() {
-                        match encode_to(msg, &mut write, events).await {
+                        match encode_to(msg, write).await {
                             Next::Nothing => {}
                             next => return next,
                         }
                     }
 
                     match msg.as_enum() {
-                        TwitchMessage::Reconnect(..) => {
-                            return maybe_reconnect(
-                                events,
-                                format_args!("cannot read, server asked us to reconnect"),
-                            )
-                            .await
+                        TwitchMessage::Reconnect(..) => return reconnect(Disconnect::Reconnect),
+
+                        TwitchMessage::UserState(state) => {
+                            let moderator = state.badges().any(|badge| {
+                                matches!(&*badge.name, "moderator" | "vip" | "broadcaster")
+                            });
+                            queue.set_moderator(&state.channel, moderator);
                         }
 
-                        TwitchMessage::RoomState(..) => {}
                         TwitchMessage::Privmsg(privmsg) => {
                             let event = Event::Message {
                                 msg: privmsg.into_static(),
@@ -440,12 +599,39 @@ async fn main_loop(
                                 return Next::Stop;
                             }
                         }
-                        _ => {}
+
+                        msg => {
+                            let event = match msg {
+                                TwitchMessage::UserNotice(msg) => Event::Notice {
+                                    notice: Notice::parse(&msg),
+                                },
+                                TwitchMessage::ClearChat(msg) => Event::Clear {
+                                    clear: Clear::parse(&msg),
+                                },
+                                TwitchMessage::ClearMsg(msg) => Event::Deleted {
+                                    deleted: Deleted::parse(&msg),
+                                },
+                                TwitchMessage::RoomState(msg) => Event::Room {
+                                    room: Room::parse(&msg),
+                                },
+                                TwitchMessage::Join(msg) => Event::Membership {
+                                    membership: Membership::join(&msg),
+                                },
+                                TwitchMessage::Part(msg) => Event::Membership {
+                                    membership: Membership::part(&msg),
+                                },
+                                _ => continue,
+                            };
+                            if !send(events, event) {
+                                return Next::Stop;
+                            }
+                        }
                     }
                 }
                 continue;
             }
             Either::Right(msg) => msg,
+            Either::Elapsed => continue,
         };
 
         let Ok(msg) = msg else { return Next::Stop };
@@ -453,19 +639,27 @@ async fn main_loop(
         match msg {
             Response::Join { channel } => {
                 let msg = encode::join(&channel);
-                match encode_to(msg, &mut write, events).await {
+                match encode_to(msg, write).await {
                     Next::Nothing => {}
                     next => return next,
                 }
             }
 
+            Response::Disconnect => return reconnect(Disconnect::Requested),
+
+            msg => queue.push(msg),
+        }
+    }
+}
+
+// writes out everything that the rate limiter will currently allow
+async fn flush(queue: &mut SendQueue, write: &mut LineWriter) -> Next {
+    while let Some(msg) = queue.pop_ready() {
+        let next = match msg {
             Response::Error { channel, data } => {
                 let data = format!("error: {data}");
                 let msg = encode::privmsg(&channel, &data);
-                match encode_to(msg, &mut write, events).await {
-                    Next::Nothing => {}
-                    next => return next,
-                }
+                encode_to(msg, write).await
             }
 
             Response::Reply {
@@ -474,65 +668,75 @@ async fn main_loop(
                 data,
             } => {
                 let msg = encode::reply(MsgIdRef::from_str(&msg_id), &channel, &data);
-                match encode_to(msg, &mut write, events).await {
-                    Next::Nothing => {}
-                    next => return next,
-                }
+                encode_to(msg, write).await
             }
 
             Response::Say { channel, data } => {
                 let msg = encode::privmsg(&channel, &data);
-                match encode_to(msg, &mut write, events).await {
-                    Next::Nothing => {}
-                    next => return next,
-                }
+                encode_to(msg, write).await
+            }
+
+            Response::Join { channel } => {
+                let msg = encode::join(&channel);
+                encode_to(msg, write).await
             }
 
-            Response::Disconnect => {
-                return maybe_reconnect(events, format_args!("user requested to reconnect")).await
+            Response::Part { channel } => {
+                let msg = encode::part(&channel);
+                encode_to(msg, write).await
             }
+
+            Response::Disconnect => Next::Nothing,
+        };
+
+        match next {
+            Next::Nothing => {}
+            next => return next,
         }
     }
+
+    if !queue.is_empty() {
+        log::trace!("rate limited, waiting to send queued messages");
+    }
+
+    Next::Nothing
 }
 
 enum Either<L, R> {
     Left(L),
     Right(R),
+    Elapsed,
 }
 
-async fn select2<L, R>(left: &mut L, right: &mut R) -> Either<L::Output, R::Output>
+async fn select<L, R>(
+    left: &mut L,
+    right: &mut R,
+    deadline: Option<std::time::Instant>,
+) -> Either<L::Output, R::Output>
 where
     L: Future + Unpin,
     R: Future + Unpin,
 {
+    let elapsed = async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+            None => std::future::pending().await,
+        }
+    };
+
     tokio::select! {
         left = left => Either::Left(left),
         right = right => Either::Right(right),
+        _ = elapsed => Either::Elapsed,
     }
 }
 
-async fn encode_to(
-    msg: impl Encodable,
-    write: &mut (impl AsyncWrite + Unpin),
-    events: &flume::Sender<Event>,
-) -> Next {
+async fn encode_to(msg: impl Encodable, write: &mut LineWriter) -> Next {
     let mut buf = vec![];
     msg.encode(&mut buf).expect("valid encoding");
 
-    if let Err(err) = write.write_all(&buf).await {
-        return maybe_reconnect(
-            events,
-            format_args!("cannot write, reconnecting because: {err}"),
-        )
-        .await;
-    }
-
-    if let Err(err) = write.flush().await {
-        return maybe_reconnect(
-            events,
-            format_args!("cannot flush, reconnecting because: {err}"),
-        )
-        .await;
+    if let Err(err) = write.send(&buf).await {
+        return reconnect(Disconnect::network(format_args!("cannot write: {err}")));
     }
 
     Next::Nothing
@@ -0,0 +1,183 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use super::{QueueDepth, Response};
+use crate::config;
+
+/// Responses that were produced while we weren't connected
+///
+/// These are sent once we've reconnected, unless they've gotten too old.
+/// If too many pile up, the oldest are dropped.
+#[derive(Debug)]
+pub(super) struct Backlog {
+    max_len: usize,
+    max_age: Duration,
+    entries: VecDeque<(Instant, Response)>,
+    depth: QueueDepth,
+}
+
+impl Backlog {
+    pub(super) fn new(config: &config::Backlog, depth: QueueDepth) -> Self {
+        Self {
+            max_len: config.max_messages,
+            max_age: config.max_age(),
+            entries: VecDeque::new(),
+            depth,
+        }
+    }
+
+    pub(super) fn depth(&self) -> QueueDepth {
+        self.depth.clone()
+    }
+
+    pub(super) fn push(&mut self, response: Response) {
+        self.push_at(response, Instant::now())
+    }
+
+    pub(super) fn extend(&mut self, responses: impl IntoIterator<Item = Response>) {
+        let now = Instant::now();
+        for response in responses {
+            self.push_at(response, now);
+        }
+    }
+
+    /// Takes everything that hasn't expired, oldest first
+    pub(super) fn take(&mut self) -> Vec<Response> {
+        self.take_at(Instant::now())
+    }
+
+    fn push_at(&mut self, response: Response, now: Instant) {
+        // we're already reconnecting
+        if let Response::Disconnect = response {
+            return;
+        }
+
+        if self.max_len == 0 {
+            log::warn!("not connected, dropping: {response:?}");
+            return;
+        }
+
+        if self.entries.len() >= self.max_len {
+            if let Some((_, oldest)) = self.entries.pop_front() {
+                log::warn!("backlog is full, dropping: {oldest:?}");
+                self.decrement(&oldest);
+            }
+        }
+
+        if let Some(channel) = response.channel() {
+            self.depth.increment(channel);
+        }
+        self.entries.push_back((now, response));
+    }
+
+    fn take_at(&mut self, now: Instant) -> Vec<Response> {
+        let mut fresh = Vec::with_capacity(self.entries.len());
+
+        while let Some((at, response)) = self.entries.pop_front() {
+            self.decrement(&response);
+
+            let age = now.saturating_duration_since(at);
+            if age > self.max_age {
+                log::warn!("dropping a response that expired after {age:.1?}: {response:?}");
+                continue;
+            }
+            fresh.push(response);
+        }
+
+        if !fresh.is_empty() {
+            log::info!("sending {} responses from while we were disconnected", fresh.len());
+        }
+        fresh
+    }
+
+    fn decrement(&self, response: &Response) {
+        if let Some(channel) = response.channel() {
+            self.depth.decrement(channel);
+        }
+    }
+}
+
+impl Drop for Backlog {
+    fn drop(&mut self) {
+        for (_, response) in &self.entries {
+            self.decrement(response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn say(data: &str) -> Response {
+        Response::Say {
+            channel: String::from("#test"),
+            data: data.to_string(),
+        }
+    }
+
+    fn backlog(max_messages: usize) -> Backlog {
+        let config = config::Backlog {
+            max_messages,
+            max_age: 60.0,
+        };
+        Backlog::new(&config, QueueDepth::default())
+    }
+
+    #[test]
+    fn drops_the_oldest() {
+        let now = Instant::now();
+        let mut backlog = backlog(2);
+        for data in ["a", "b", "c"] {
+            backlog.push_at(say(data), now);
+        }
+        assert_eq!(backlog.depth().channel("#test"), 2);
+
+        assert_eq!(backlog.take_at(now), [say("b"), say("c")]);
+        assert_eq!(backlog.depth().total(), 0);
+    }
+
+    #[test]
+    fn drops_expired() {
+        let now = Instant::now();
+        let mut backlog = backlog(10);
+        backlog.push_at(say("old"), now);
+        backlog.push_at(say("new"), now + Duration::from_secs(30));
+
+        let later = now + Duration::from_secs(61);
+        assert_eq!(backlog.take_at(later), [say("new")]);
+        assert_eq!(backlog.depth().total(), 0);
+    }
+
+    #[test]
+    fn ignores_disconnect() {
+        let now = Instant::now();
+        let mut backlog = backlog(10);
+        backlog.push_at(Response::Disconnect, now);
+        backlog.push_at(say("a"), now);
+        assert_eq!(backlog.take_at(now), [say("a")]);
+    }
+
+    #[test]
+    fn disabled() {
+        let now = Instant::now();
+        let mut backlog = backlog(0);
+        backlog.push_at(say("a"), now);
+        assert_eq!(backlog.depth().total(), 0);
+        assert!(backlog.take_at(now).is_empty());
+    }
+
+    #[test]
+    fn drop_clears_depth() {
+        let depth = {
+            let mut backlog = backlog(10);
+            backlog.push(say("a"));
+            backlog.push(say("b"));
+            assert_eq!(backlog.depth().total(), 2);
+            backlog.depth()
+        };
+        assert_eq!(depth.total(), 0);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use mlua::{IntoLua, UserData};
+
+use crate::{config, GlobalItem};
+
+/// Why we were disconnected from Twitch
+#[derive(Clone, Debug)]
+pub enum Disconnect {
+    /// The server asked us to reconnect
+    Reconnect,
+    /// A script asked us to reconnect
+    Requested,
+    /// We couldn't connect, or the connection was lost
+    Network { error: String },
+    /// Twitch rejected our credentials, we won't try again
+    Auth { error: String },
+}
+
+impl Disconnect {
+    pub(super) fn network(error: std::fmt::Arguments<'_>) -> Self {
+        Self::Network {
+            error: error.to_string(),
+        }
+    }
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Reconnect => "reconnect",
+            Self::Requested => "requested",
+            Self::Network { .. } => "network",
+            Self::Auth { .. } => "auth",
+        }
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            Self::Network { error } | Self::Auth { error } => Some(error),
+            Self::Reconnect | Self::Requested => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Disconnect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reconnect => f.write_str("the server asked us to reconnect"),
+            Self::Requested => f.write_str("a reconnect was requested"),
+            Self::Network { error } => write!(f, "network error: {error}"),
+            Self::Auth { error } => write!(f, "authentication failed: {error}"),
+        }
+    }
+}
+
+/// We were disconnected from Twitch
+#[derive(Clone, Debug)]
+pub struct Disconnected {
+    pub reason: Disconnect,
+    /// When we'll try to connect again, if we will
+    pub retry_in: Option<Duration>,
+}
+
+impl Disconnected {
+    pub const fn name(&self) -> &'static str {
+        "disconnect"
+    }
+}
+
+impl IntoLua for &Disconnected {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table()?;
+        table.set("reason", self.reason.as_str())?;
+        table.set("error", self.reason.error())?;
+        table.set("retry_in", self.retry_in.map(|d| d.as_secs_f64()))?;
+        Ok(mlua::Value::Table(table))
+    }
+}
+
+/// Capped exponential backoff, with jitter
+#[derive(Debug)]
+pub(super) struct Backoff {
+    min: Duration,
+    max: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub(super) fn new(config: &config::Reconnect) -> Self {
+        Self {
+            min: config.min_delay(),
+            max: config.max_delay(),
+            attempts: 0,
+        }
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    pub(super) fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .min
+            .saturating_mul(2_u32.saturating_pow(self.attempts))
+            .min(self.max);
+        self.attempts = self.attempts.saturating_add(1);
+
+        // somewhere between half and all of the delay,
+        // so everything doesn't reconnect at the same time after an outage
+        delay.mul_f64(0.5 + fastrand::f64() * 0.5)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Status {
+    connected: bool,
+    reconnects: u64,
+    last: Option<Disconnect>,
+}
+
+/// The state of the connection to Twitch, as seen by scripts
+#[derive(Clone, Debug, Default)]
+pub struct Connection {
+    status: Rc<RefCell<Status>>,
+}
+
+impl GlobalItem for Connection {
+    const MODULE: &'static str = "connection";
+}
+
+impl Connection {
+    pub fn connected(&self) {
+        let mut status = self.status.borrow_mut();
+        if status.last.is_some() {
+            status.reconnects += 1;
+        }
+        status.connected = true;
+    }
+
+    pub fn disconnected(&self, disconnected: &Disconnected) {
+        let mut status = self.status.borrow_mut();
+        status.connected = false;
+        status.last = Some(disconnected.reason.clone());
+    }
+}
+
+impl UserData for Connection {
+    fn add_methods<M>(methods: &mut M)
+    where
+        M: mlua::UserDataMethods<Self>,
+    {
+        methods.add_method("is_connected", |_lua, this, ()| {
+            Ok(this.status.borrow().connected)
+        });
+
+        methods.add_method("reconnects", |_lua, this, ()| {
+            Ok(this.status.borrow().reconnects)
+        });
+
+        methods.add_method("last_disconnect", |lua, this, ()| {
+            let status = this.status.borrow();
+            let Some(last) = &status.last else {
+                return Ok(mlua::Value::Nil);
+            };
+            let table = lua.create_table()?;
+            table.set("reason", last.as_str())?;
+            table.set("error", last.error())?;
+            Ok(mlua::Value::Table(table))
+        });
+    }
+}
@@ -0,0 +1,352 @@
+use mlua::{AnyUserData, IntoLua};
+use twitch_message::messages::{ClearChat, ClearMsg, Join, Part, RoomState, UserNotice};
+
+use crate::responder::Responder;
+
+#[derive(Clone, Debug)]
+pub enum NoticeKind {
+    Sub {
+        months: u64,
+        plan: String,
+    },
+    Resub {
+        months: u64,
+        streak: Option<u64>,
+        plan: String,
+    },
+    GiftSub {
+        recipient: String,
+        months: u64,
+        plan: String,
+    },
+    MysteryGift {
+        count: u64,
+        plan: String,
+    },
+    Raid {
+        from: String,
+        viewers: u64,
+    },
+    Other {
+        id: String,
+    },
+}
+
+/// A USERNOTICE: subs, resubs, gift subs, raids, ...
+#[derive(Clone, Debug)]
+pub struct Notice {
+    pub channel: String,
+    pub channel_id: String,
+    pub sender: String,
+    pub sender_id: String,
+    pub display_name: String,
+    pub message: Option<String>,
+    pub system_message: Option<String>,
+    pub kind: NoticeKind,
+}
+
+impl Notice {
+    pub(super) fn parse(msg: &UserNotice<'_>) -> Self {
+        let tag = |key: &str| msg.tags.get(key).map(ToString::to_string);
+        let number = |key: &str| msg.tags.get(key).and_then(|s| s.parse().ok());
+        let plan = || tag("msg-param-sub-plan").unwrap_or_default();
+
+        let id = tag("msg-id").unwrap_or_default();
+        let kind = match &*id {
+            "sub" => NoticeKind::Sub {
+                months: number("msg-param-cumulative-months").unwrap_or(1),
+                plan: plan(),
+            },
+            "resub" => NoticeKind::Resub {
+                months: number("msg-param-cumulative-months").unwrap_or(1),
+                streak: number("msg-param-streak-months"),
+                plan: plan(),
+            },
+            "subgift" => NoticeKind::GiftSub {
+                recipient: tag("msg-param-recipient-user-name").unwrap_or_default(),
+                months: number("msg-param-months").unwrap_or(1),
+                plan: plan(),
+            },
+            "submysterygift" => NoticeKind::MysteryGift {
+                count: number("msg-param-mass-gift-count").unwrap_or(1),
+                plan: plan(),
+            },
+            "raid" => NoticeKind::Raid {
+                from: tag("msg-param-login").unwrap_or_default(),
+                viewers: number("msg-param-viewerCount").unwrap_or(0),
+            },
+            _ => NoticeKind::Other { id },
+        };
+
+        Self {
+            channel: msg.channel.to_string(),
+            channel_id: tag("room-id").unwrap_or_default(),
+            sender: tag("login").unwrap_or_default(),
+            sender_id: tag("user-id").unwrap_or_default(),
+            display_name: tag("display-name").unwrap_or_default(),
+            message: msg.message.as_deref().map(ToString::to_string),
+            system_message: tag("system-msg"),
+            kind,
+        }
+    }
+
+    /// The name of the manifest event handler for this notice
+    pub const fn name(&self) -> &'static str {
+        match self.kind {
+            NoticeKind::Sub { .. } => "sub",
+            NoticeKind::Resub { .. } => "resub",
+            NoticeKind::GiftSub { .. } => "gift_sub",
+            NoticeKind::MysteryGift { .. } => "mystery_gift",
+            NoticeKind::Raid { .. } => "raid",
+            NoticeKind::Other { .. } => "user_notice",
+        }
+    }
+}
+
+impl IntoLua for &Notice {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table_from([
+            ("kind", self.name()),
+            ("channel", &*self.channel),
+            ("channel_id", &*self.channel_id),
+            ("sender", &*self.sender),
+            ("sender_id", &*self.sender_id),
+            ("display_name", &*self.display_name),
+        ])?;
+        table.set("message", self.message.as_deref())?;
+        table.set("system_message", self.system_message.as_deref())?;
+
+        match &self.kind {
+            NoticeKind::Sub { months, plan } => {
+                table.set("months", *months)?;
+                table.set("plan", &**plan)?;
+            }
+            NoticeKind::Resub {
+                months,
+                streak,
+                plan,
+            } => {
+                table.set("months", *months)?;
+                table.set("streak", *streak)?;
+                table.set("plan", &**plan)?;
+            }
+            NoticeKind::GiftSub {
+                recipient,
+                months,
+                plan,
+            } => {
+                table.set("recipient", &**recipient)?;
+                table.set("months", *months)?;
+                table.set("plan", &**plan)?;
+            }
+            NoticeKind::MysteryGift { count, plan } => {
+                table.set("count", *count)?;
+                table.set("plan", &**plan)?;
+            }
+            NoticeKind::Raid { from, viewers } => {
+                table.set("from", &**from)?;
+                table.set("viewers", *viewers)?;
+            }
+            NoticeKind::Other { id } => {
+                table.set("id", &**id)?;
+            }
+        }
+
+        attach_say(lua, &table, &self.channel)?;
+        Ok(mlua::Value::Table(table))
+    }
+}
+
+/// A CLEARCHAT: a timeout, a ban or the entire chat being cleared
+#[derive(Clone, Debug)]
+pub struct Clear {
+    pub channel: String,
+    pub channel_id: String,
+    pub target: Option<String>,
+    pub target_id: Option<String>,
+    pub duration: Option<u64>,
+}
+
+impl Clear {
+    pub(super) fn parse(msg: &ClearChat<'_>) -> Self {
+        Self {
+            channel: msg.channel.to_string(),
+            channel_id: msg.tags.get("room-id").unwrap_or_default().to_string(),
+            target: msg.target.as_deref().map(ToString::to_string),
+            target_id: msg.tags.get("target-user-id").map(ToString::to_string),
+            duration: msg.tags.get("ban-duration").and_then(|s| s.parse().ok()),
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match (&self.target, self.duration) {
+            (None, _) => "clear_chat",
+            (Some(..), Some(..)) => "timeout",
+            (Some(..), None) => "ban",
+        }
+    }
+}
+
+impl IntoLua for &Clear {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table_from([
+            ("kind", self.name()),
+            ("channel", &*self.channel),
+            ("channel_id", &*self.channel_id),
+        ])?;
+        table.set("target", self.target.as_deref())?;
+        table.set("target_id", self.target_id.as_deref())?;
+        table.set("duration", self.duration)?;
+        attach_say(lua, &table, &self.channel)?;
+        Ok(mlua::Value::Table(table))
+    }
+}
+
+/// A CLEARMSG: a single message was deleted
+#[derive(Clone, Debug)]
+pub struct Deleted {
+    pub channel: String,
+    pub sender: String,
+    pub msg_id: String,
+    pub data: String,
+}
+
+impl Deleted {
+    pub(super) fn parse(msg: &ClearMsg<'_>) -> Self {
+        Self {
+            channel: msg.channel.to_string(),
+            sender: msg.tags.get("login").unwrap_or_default().to_string(),
+            msg_id: msg.tags.get("target-msg-id").unwrap_or_default().to_string(),
+            data: msg.message.to_string(),
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        "clear_msg"
+    }
+}
+
+impl IntoLua for &Deleted {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table_from([
+            ("channel", &*self.channel),
+            ("sender", &*self.sender),
+            ("msg_id", &*self.msg_id),
+            ("data", &*self.data),
+        ])?;
+        attach_say(lua, &table, &self.channel)?;
+        Ok(mlua::Value::Table(table))
+    }
+}
+
+/// A ROOMSTATE: the room modes changed (or we joined the room)
+///
+/// Only the modes that were sent are `Some`
+#[derive(Clone, Debug)]
+pub struct Room {
+    pub channel: String,
+    pub channel_id: String,
+    pub slow: Option<u64>,
+    pub followers_only: Option<i64>,
+    pub subs_only: Option<bool>,
+    pub emote_only: Option<bool>,
+    pub unique_chat: Option<bool>,
+}
+
+impl Room {
+    pub(super) fn parse(msg: &RoomState<'_>) -> Self {
+        let flag = |key: &str| msg.tags.get(key).map(|s| s == "1");
+        Self {
+            channel: msg.channel.to_string(),
+            channel_id: msg.tags.get("room-id").unwrap_or_default().to_string(),
+            slow: msg.tags.get("slow").and_then(|s| s.parse().ok()),
+            followers_only: msg.tags.get("followers-only").and_then(|s| s.parse().ok()),
+            subs_only: flag("subs-only"),
+            emote_only: flag("emote-only"),
+            unique_chat: flag("r9k"),
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        "room_state"
+    }
+}
+
+impl IntoLua for &Room {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table_from([
+            ("channel", &*self.channel),
+            ("channel_id", &*self.channel_id),
+        ])?;
+        table.set("slow", self.slow)?;
+        table.set("followers_only", self.followers_only)?;
+        table.set("subs_only", self.subs_only)?;
+        table.set("emote_only", self.emote_only)?;
+        table.set("unique_chat", self.unique_chat)?;
+        attach_say(lua, &table, &self.channel)?;
+        Ok(mlua::Value::Table(table))
+    }
+}
+
+/// A JOIN or a PART
+#[derive(Clone, Debug)]
+pub struct Membership {
+    pub channel: String,
+    pub user: String,
+    pub joined: bool,
+}
+
+impl Membership {
+    pub(super) fn join(msg: &Join<'_>) -> Self {
+        Self {
+            channel: msg.channel.to_string(),
+            user: msg.user.to_string(),
+            joined: true,
+        }
+    }
+
+    pub(super) fn part(msg: &Part<'_>) -> Self {
+        Self {
+            channel: msg.channel.to_string(),
+            user: msg.user.to_string(),
+            joined: false,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        if self.joined {
+            "join"
+        } else {
+            "part"
+        }
+    }
+}
+
+impl IntoLua for &Membership {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table_from([
+            ("channel", &*self.channel), //
+            ("user", &*self.user),
+        ])?;
+        attach_say(lua, &table, &self.channel)?;
+        Ok(mlua::Value::Table(table))
+    }
+}
+
+// events aren't replies to anything, so they can only `say` to their channel
+fn attach_say(lua: &mlua::Lua, table: &mlua::Table, channel: &str) -> mlua::Result<()> {
+    let responder = lua
+        .globals()
+        .get::<AnyUserData>("_RESPONDER")?
+        .borrow::<Responder>()?
+        .clone();
+
+    let channel = channel.to_string();
+    table.set(
+        "say",
+        lua.create_function(move |_lua, (_this, data): (mlua::Value, String)| {
+            responder.say_to(&channel, data);
+            Ok(())
+        })?,
+    )
+}
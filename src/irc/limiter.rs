@@ -0,0 +1,356 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::config::RateLimit;
+
+use super::Response;
+
+// remembers when messages were sent, so no more than `capacity` go out in any window
+#[derive(Debug)]
+struct Bucket {
+    capacity: usize,
+    window: Duration,
+    sent: VecDeque<Instant>,
+}
+
+impl Bucket {
+    fn new(capacity: u32, window: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1) as usize,
+            window,
+            sent: VecDeque::new(),
+        }
+    }
+
+    fn resize(&mut self, capacity: u32) {
+        self.capacity = capacity.max(1) as usize;
+    }
+
+    fn ready_at(&self, now: Instant) -> Instant {
+        let expired = self.sent.partition_point(|&at| at + self.window <= now);
+        if self.sent.len() - expired < self.capacity {
+            return now;
+        }
+        // wait until enough of them have left the window
+        self.sent[self.sent.len() - self.capacity] + self.window
+    }
+
+    fn take(&mut self, now: Instant) {
+        while self.sent.front().is_some_and(|&at| at + self.window <= now) {
+            self.sent.pop_front();
+        }
+        self.sent.push_back(now);
+    }
+}
+
+#[derive(Debug)]
+struct Channel {
+    bucket: Bucket,
+    moderator: bool,
+}
+
+/// How many messages are waiting to be sent, per channel
+#[derive(Clone, Debug, Default)]
+pub struct QueueDepth(Arc<Mutex<HashMap<String, usize>>>);
+
+impl QueueDepth {
+    pub fn total(&self) -> usize {
+        self.0.lock().unwrap().values().sum()
+    }
+
+    pub fn channel(&self, channel: &str) -> usize {
+        self.0.lock().unwrap().get(channel).copied().unwrap_or(0)
+    }
+
+    pub(super) fn increment(&self, channel: &str) {
+        *self
+            .0
+            .lock()
+            .unwrap()
+            .entry(channel.to_string())
+            .or_default() += 1;
+    }
+
+    pub(super) fn decrement(&self, channel: &str) {
+        let mut map = self.0.lock().unwrap();
+        if let Some(n) = map.get_mut(channel) {
+            *n = n.saturating_sub(1);
+            if *n == 0 {
+                map.remove(channel);
+            }
+        }
+    }
+}
+
+/// Delays outgoing chat messages so we stay within Twitch's rate limits
+///
+/// There is a global budget shared by every channel, and a per-channel budget
+/// which is larger if we're a moderator (or vip/broadcaster) in that channel.
+/// Twitch counts every message towards the account's limit, which is only raised
+/// for channels we moderate, so those have their own (larger) global budget.
+/// Messages are never dropped, they just wait until there is room.
+#[derive(Debug)]
+pub struct SendQueue {
+    limits: RateLimit,
+    global: Bucket,
+    moderated: Bucket,
+    channels: HashMap<String, Channel>,
+    pending: VecDeque<Response>,
+    depth: QueueDepth,
+}
+
+impl SendQueue {
+    pub fn new(limits: RateLimit, depth: QueueDepth) -> Self {
+        let global = Bucket::new(limits.global, limits.window());
+        let moderated = Bucket::new(limits.moderator, limits.window());
+        Self {
+            limits,
+            global,
+            moderated,
+            channels: HashMap::new(),
+            pending: VecDeque::new(),
+            depth,
+        }
+    }
+
+    pub fn set_moderator(&mut self, channel: &str, moderator: bool) {
+        let capacity = self.capacity(moderator);
+        let channel = self.channel_mut(channel);
+        if channel.moderator != moderator {
+            log::debug!("moderator status changed: {moderator}");
+            channel.moderator = moderator;
+            channel.bucket.resize(capacity);
+        }
+    }
+
+    pub fn push(&mut self, response: Response) {
+        if let Some(channel) = response.channel() {
+            self.depth.increment(channel);
+        }
+        self.pending.push_back(response);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Takes everything that hasn't been sent yet
+    pub fn take_pending(&mut self) -> Vec<Response> {
+        let pending: Vec<_> = self.pending.drain(..).collect();
+        for response in &pending {
+            if let Some(channel) = response.channel() {
+                self.depth.decrement(channel);
+            }
+        }
+        pending
+    }
+
+    pub fn pop_ready(&mut self) -> Option<Response> {
+        self.pop_ready_at(Instant::now())
+    }
+
+    pub fn next_ready(&self) -> Option<Instant> {
+        self.next_ready_at(Instant::now())
+    }
+
+    fn pop_ready_at(&mut self, now: Instant) -> Option<Response> {
+        let index = (0..self.pending.len()).find(|&index| {
+            let response = &self.pending[index];
+            // leaving a channel waits until everything queued for it has been sent
+            if let Response::Part { channel } = response {
+                return !self
+                    .pending
+                    .range(..index)
+                    .any(|queued| queued.channel() == Some(channel));
+            }
+            let Some(channel) = response.channel() else {
+                return true;
+            };
+            self.ready_at(channel, now) <= now
+        })?;
+
+        let response = self.pending.remove(index)?;
+        if let Some(channel) = response.channel() {
+            self.global.take(now);
+            self.moderated.take(now);
+            self.channel_mut(channel).bucket.take(now);
+            self.depth.decrement(channel);
+        }
+        Some(response)
+    }
+
+    fn next_ready_at(&self, now: Instant) -> Option<Instant> {
+        self.pending
+            .iter()
+            .filter_map(|response| match response {
+                // these are sent right after whatever they're waiting on
+                Response::Part { .. } => None,
+                response => Some(match response.channel() {
+                    Some(channel) => self.ready_at(channel, now),
+                    None => now,
+                }),
+            })
+            .min()
+    }
+
+    fn ready_at(&self, channel: &str, now: Instant) -> Instant {
+        match self.channels.get(channel) {
+            Some(channel) if channel.moderator => {
+                let global = self.moderated.ready_at(now);
+                global.max(channel.bucket.ready_at(now))
+            }
+            Some(channel) => {
+                let global = self.global.ready_at(now);
+                global.max(channel.bucket.ready_at(now))
+            }
+            None => self.global.ready_at(now),
+        }
+    }
+
+    fn capacity(&self, moderator: bool) -> u32 {
+        if moderator {
+            self.limits.moderator
+        } else {
+            self.limits.user
+        }
+    }
+
+    fn channel_mut(&mut self, channel: &str) -> &mut Channel {
+        let capacity = self.limits.user;
+        let window = self.limits.window();
+        self.channels
+            .entry(channel.to_string())
+            .or_insert_with(|| Channel {
+                bucket: Bucket::new(capacity, window),
+                moderator: false,
+            })
+    }
+}
+
+impl Drop for SendQueue {
+    fn drop(&mut self) {
+        for response in &self.pending {
+            if let Some(channel) = response.channel() {
+                self.depth.decrement(channel);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(30);
+
+    fn say(channel: &str) -> Response {
+        Response::Say {
+            channel: channel.to_string(),
+            data: String::from("hello"),
+        }
+    }
+
+    fn limits() -> RateLimit {
+        RateLimit {
+            user: 20,
+            moderator: 100,
+            global: 20,
+            window: WINDOW.as_secs(),
+        }
+    }
+
+    fn drain(queue: &mut SendQueue, now: Instant) -> usize {
+        std::iter::from_fn(|| queue.pop_ready_at(now)).count()
+    }
+
+    #[test]
+    fn bucket() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(2, WINDOW);
+        assert_eq!(bucket.ready_at(now), now);
+
+        bucket.take(now);
+        bucket.take(now + Duration::from_secs(10));
+        assert_eq!(bucket.ready_at(now + Duration::from_secs(10)), now + WINDOW);
+
+        // the first one has left the window, but not the second
+        bucket.take(now + WINDOW);
+        let later = now + WINDOW + Duration::from_secs(1);
+        assert_eq!(bucket.ready_at(later), now + Duration::from_secs(10) + WINDOW);
+    }
+
+    #[test]
+    fn bucket_resize() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(1, WINDOW);
+        bucket.take(now);
+        assert_eq!(bucket.ready_at(now), now + WINDOW);
+
+        bucket.resize(2);
+        assert_eq!(bucket.ready_at(now), now);
+    }
+
+    #[test]
+    fn stays_within_the_window() {
+        let now = Instant::now();
+        let mut queue = SendQueue::new(limits(), QueueDepth::default());
+        for _ in 0..50 {
+            queue.push(say("#a"));
+        }
+
+        assert_eq!(drain(&mut queue, now), 20);
+        assert_eq!(queue.next_ready_at(now), Some(now + WINDOW));
+
+        // nothing more goes out until the whole window has passed
+        assert_eq!(drain(&mut queue, now + WINDOW - Duration::from_secs(1)), 0);
+        assert_eq!(drain(&mut queue, now + WINDOW), 20);
+        assert_eq!(queue.depth.channel("#a"), 10);
+    }
+
+    #[test]
+    fn global_limit() {
+        let now = Instant::now();
+        let mut queue = SendQueue::new(limits(), QueueDepth::default());
+        for _ in 0..15 {
+            queue.push(say("#a"));
+            queue.push(say("#b"));
+        }
+
+        assert_eq!(drain(&mut queue, now), 20);
+        assert_eq!(queue.depth.total(), 10);
+    }
+
+    #[test]
+    fn moderator() {
+        let now = Instant::now();
+        let mut queue = SendQueue::new(limits(), QueueDepth::default());
+        queue.set_moderator("#a", true);
+        for _ in 0..150 {
+            queue.push(say("#a"));
+        }
+        queue.push(say("#b"));
+
+        assert_eq!(drain(&mut queue, now), 100);
+        // the messages in the moderated channel count towards the global limit
+        assert!(queue.pending.iter().any(|response| response.channel() == Some("#b")));
+    }
+
+    #[test]
+    fn part_waits() {
+        let now = Instant::now();
+        let mut queue = SendQueue::new(limits(), QueueDepth::default());
+        for _ in 0..21 {
+            queue.push(say("#a"));
+        }
+        queue.push(Response::Part {
+            channel: String::from("#a"),
+        });
+
+        assert_eq!(drain(&mut queue, now), 20);
+        assert_eq!(queue.pending.len(), 2);
+        assert_eq!(drain(&mut queue, now + WINDOW), 2);
+    }
+}
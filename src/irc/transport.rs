@@ -0,0 +1,139 @@
+use std::{collections::VecDeque, io, sync::Arc};
+
+use futures_util::{stream::SplitSink, stream::SplitStream, SinkExt as _, StreamExt as _};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncRead, AsyncWrite, AsyncWriteExt as _, BufReader, Lines},
+    net::TcpStream,
+};
+use tokio_rustls::rustls::{self, pki_types::ServerName};
+use tokio_tungstenite::{tungstenite, MaybeTlsStream, WebSocketStream};
+
+use crate::config::Transport;
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+const TCP_ADDRESS: &str = "irc.chat.twitch.tv:6667";
+const TLS_ADDRESS: &str = "irc.chat.twitch.tv:6697";
+const WEBSOCKET_URL: &str = "wss://irc-ws.chat.twitch.tv:443";
+
+/// Reads IRC lines, regardless of what they were sent over
+pub(super) enum LineReader {
+    Stream(Lines<BufReader<Box<dyn AsyncRead + Unpin>>>),
+    WebSocket {
+        stream: SplitStream<WebSocket>,
+        // a frame can have more than one line in it
+        pending: VecDeque<String>,
+    },
+}
+
+impl LineReader {
+    /// The next line, or `None` if the connection was closed
+    ///
+    /// This is cancel safe
+    pub(super) async fn next_line(&mut self) -> io::Result<Option<String>> {
+        let (stream, pending) = match self {
+            Self::Stream(lines) => return lines.next_line().await,
+            Self::WebSocket { stream, pending } => (stream, pending),
+        };
+
+        loop {
+            if let Some(line) = pending.pop_front() {
+                return Ok(Some(line));
+            }
+
+            let msg = match stream.next().await {
+                Some(Ok(msg)) => msg,
+                Some(Err(err)) => return Err(io::Error::other(err)),
+                None => return Ok(None),
+            };
+
+            match msg {
+                tungstenite::Message::Close(..) => return Ok(None),
+                tungstenite::Message::Text(..) | tungstenite::Message::Binary(..) => {
+                    let text = msg.to_text().map_err(io::Error::other)?;
+                    pending.extend(
+                        text.lines()
+                            .filter(|line| !line.is_empty())
+                            .map(ToString::to_string),
+                    );
+                }
+                // tungstenite answers pings for us
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Writes encoded IRC messages, regardless of what they're sent over
+pub(super) enum LineWriter {
+    Stream(Box<dyn AsyncWrite + Unpin>),
+    WebSocket(SplitSink<WebSocket, tungstenite::Message>),
+}
+
+impl LineWriter {
+    pub(super) async fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Stream(write) => {
+                write.write_all(data).await?;
+                write.flush().await
+            }
+            Self::WebSocket(sink) => {
+                let data = std::str::from_utf8(data).map_err(io::Error::other)?;
+                sink.send(tungstenite::Message::text(data.trim_end()))
+                    .await
+                    .map_err(io::Error::other)
+            }
+        }
+    }
+}
+
+/// Connects to Twitch using `transport`, or to `address` if one was given
+pub(super) async fn connect(
+    transport: Transport,
+    address: Option<&str>,
+) -> io::Result<(LineReader, LineWriter)> {
+    match transport {
+        Transport::Tcp => {
+            let stream = TcpStream::connect(address.unwrap_or(TCP_ADDRESS)).await?;
+            Ok(split(stream))
+        }
+
+        Transport::Tls => {
+            let address = address.unwrap_or(TLS_ADDRESS);
+            let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+            let name = ServerName::try_from(host.to_string()).map_err(io::Error::other)?;
+
+            let stream = TcpStream::connect(address).await?;
+            let stream = tls_connector().connect(name, stream).await?;
+            Ok(split(stream))
+        }
+
+        Transport::WebSocket => {
+            let (stream, _) = tokio_tungstenite::connect_async(address.unwrap_or(WEBSOCKET_URL))
+                .await
+                .map_err(io::Error::other)?;
+            let (sink, stream) = stream.split();
+            let reader = LineReader::WebSocket {
+                stream,
+                pending: VecDeque::new(),
+            };
+            Ok((reader, LineWriter::WebSocket(sink)))
+        }
+    }
+}
+
+fn split(stream: impl AsyncRead + AsyncWrite + Unpin + 'static) -> (LineReader, LineWriter) {
+    let (read, write) = tokio::io::split(stream);
+    let read: Box<dyn AsyncRead + Unpin> = Box::new(read);
+    let reader = LineReader::Stream(BufReader::new(read).lines());
+    (reader, LineWriter::Stream(Box::new(write)))
+}
+
+fn tls_connector() -> tokio_rustls::TlsConnector {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    tokio_rustls::TlsConnector::from(Arc::new(config))
+}
@@ -0,0 +1,113 @@
+use std::path::PathBuf;
+
+use crate::{prefix::normalize, sql::DbError, SharedKvSqlStore};
+
+#[derive(Debug, thiserror::Error)]
+pub enum JoinError {
+    #[error("a channel name cannot be empty")]
+    EmptyName,
+
+    #[error(transparent)]
+    Db(#[from] DbError),
+}
+
+/// The channels the bot should be in
+///
+/// This is the configured channels, plus any joined at runtime, minus any left at runtime.
+/// Changes are kept in the data dir so they survive restarts and reconnects.
+#[derive(Clone, Debug)]
+pub struct Joined {
+    configured: Vec<String>,
+    db: SharedKvSqlStore,
+}
+
+impl Joined {
+    pub fn new(configured: &[String], db: impl Into<PathBuf>) -> Self {
+        Self {
+            configured: configured.iter().map(|c| normalize(c)).collect(),
+            db: SharedKvSqlStore::new(db),
+        }
+    }
+
+    /// The channels to join when connecting
+    pub fn channels(&self) -> Vec<String> {
+        let mut channels = self.configured.clone();
+
+        match self.db.get() {
+            Ok(db) => {
+                let is_joined =
+                    |channel: &str| db.get(channel).ok().flatten().and_then(|v| v.as_bool());
+
+                channels.retain(|channel| is_joined(channel) != Some(false));
+                for channel in db.keys().unwrap_or_default() {
+                    if is_joined(&channel) == Some(true) && !channels.contains(&channel) {
+                        channels.push(channel)
+                    }
+                }
+            }
+            Err(err) => log::warn!("cannot open joined channels: {err}"),
+        }
+
+        channels.into_iter().map(|c| format!("#{c}")).collect()
+    }
+
+    /// Remembers `channel` as joined, returning its name as it should be sent to Twitch
+    pub fn join(&self, channel: &str) -> Result<String, JoinError> {
+        let channel = Self::name(channel)?;
+        self.db.get()?.set(&channel, true)?;
+        Ok(format!("#{channel}"))
+    }
+
+    /// Remembers `channel` as left, returning its name as it should be sent to Twitch
+    pub fn part(&self, channel: &str) -> Result<String, JoinError> {
+        let channel = Self::name(channel)?;
+        let db = self.db.get()?;
+        // configured channels have to be remembered as left, otherwise they'd be joined again
+        if self.configured.contains(&channel) {
+            db.set(&channel, false)?;
+        } else {
+            db.remove(&channel)?;
+        }
+        Ok(format!("#{channel}"))
+    }
+
+    fn name(channel: &str) -> Result<String, JoinError> {
+        let channel = normalize(channel);
+        if channel.is_empty() {
+            return Err(JoinError::EmptyName);
+        }
+        Ok(channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_and_part() {
+        let joined = Joined::new(&[String::from("#museun")], ":memory:");
+        assert_eq!(joined.channels(), ["#museun"]);
+
+        assert_eq!(joined.join(" #Shaken_Bot ").unwrap(), "#shaken_bot");
+        assert_eq!(joined.channels(), ["#museun", "#shaken_bot"]);
+
+        assert_eq!(joined.part("#museun").unwrap(), "#museun");
+        assert_eq!(joined.part("shaken_bot").unwrap(), "#shaken_bot");
+        assert!(joined.channels().is_empty());
+
+        // a clone shares the same database
+        joined.clone().join("museun").unwrap();
+        assert_eq!(joined.channels(), ["#museun"]);
+    }
+
+    #[test]
+    fn empty_name() {
+        let joined = Joined::new(&[], ":memory:");
+        for name in ["", "  ", "#", " # "] {
+            assert!(matches!(joined.join(name), Err(JoinError::EmptyName)), "{name:?}");
+            assert!(matches!(joined.part(name), Err(JoinError::EmptyName)), "{name:?}");
+        }
+        assert!(joined.channels().is_empty());
+    }
+}
@@ -18,10 +18,13 @@ impl UserData for Json {
             Ok(data)
         });
 
-        methods.add_method("from_str", |lua, _this, data: String| {
-            let value: serde_json::Value =
-                serde_json::from_str(&data).map_err(mlua::Error::external)?;
-            lua.to_value(&value)
-        });
+        methods.add_method("from_str", |lua, _this, data: String| Json::from_str(lua, &data));
+    }
+}
+
+impl Json {
+    pub(crate) fn from_str(lua: &mlua::Lua, data: &str) -> mlua::Result<mlua::Value> {
+        let value: serde_json::Value = serde_json::from_str(data).map_err(mlua::Error::external)?;
+        lua.to_value(&value)
     }
 }
@@ -2,22 +2,30 @@ const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VE
 
 mod aliases;
 mod bot;
+mod channels;
 mod config;
 mod format;
+#[cfg(feature = "github")]
 mod github;
 mod globals;
+#[cfg(feature = "helix")]
 mod helix;
 mod help;
+mod http;
+mod joined;
 mod json;
 mod loaded;
 mod logger;
 mod manifest;
 mod pattern;
+mod permissions;
+mod prefix;
 mod rand;
 mod re;
 mod responder;
+mod sandbox;
+#[cfg(feature = "spotify")]
 mod spotify;
-mod sql;
 mod store;
 mod time;
 mod watcher;
@@ -25,22 +33,35 @@ mod watcher;
 pub mod crates;
 pub mod fuzzy;
 pub mod irc;
+pub mod repl;
+pub mod sql;
 
 pub use aliases::{Aliases, AliasesDb};
 pub use bot::Bot;
-pub use config::Config;
+pub use channels::Channels;
+pub use config::{Config, ConfigError, Twitch};
+#[cfg(feature = "github")]
 pub use github::Client as GithubClient;
 pub use globals::{GlobalItem, Globals};
+#[cfg(feature = "helix")]
 pub use helix::{Client as HelixClient, EmoteMap};
+pub use http::Http;
+pub use joined::Joined;
 pub use json::Json;
 pub use loaded::LoadedModules;
 pub use logger::Logger;
-pub use manifest::{Handled, Manifest, Mapping};
+pub use manifest::{
+    Completed, Emitted, Emitter, Handled, Manifest, Mapping, Settings as ManifestSettings,
+};
+pub use permissions::Permissions;
+pub use prefix::{Invocation, Prefixes};
 pub use rand::Rando;
 pub use re::Regexp;
-pub use responder::Responder;
+pub use responder::{Responder, SendOptions};
+pub use sandbox::Sandbox;
+#[cfg(feature = "spotify")]
 pub use spotify::{Client as SpotifyClient, SpotifyHistory};
-pub use store::{KvSqlStore, Store};
+pub use store::{KvSqlStore, SharedKvSqlStore, Store};
 pub use watcher::Watcher;
 
 use mlua::{IntoLua, IntoLuaMulti};
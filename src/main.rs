@@ -1,15 +1,22 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
 use yomi::{
     irc::{self, MessageClass},
-    Aliases, Config, EmoteMap, GithubClient, GlobalItem, Globals, HelixClient, Manifest,
-    SpotifyClient, SpotifyHistory, Watcher,
+    Aliases, Channels, Config, ConfigError, GlobalItem, Globals, Manifest, Permissions, Watcher,
 };
 
+mod cli;
+
 #[derive(Debug)]
 enum Next {
     Event(irc::Event),
     Route(irc::Message),
+    Task(yomi::Completed),
+    Emit(yomi::Emitted),
+    Timer,
     Continue,
     Quit,
 }
@@ -47,6 +54,65 @@ fn handle_fs_event(
     Next::Continue
 }
 
+// databases in the data directory that belong to the bot, rather than to a script's store
+const BOT_DATABASES: [&str; 5] = [
+    "aliases",
+    "channels",
+    "joined",
+    "permissions",
+    "spotify_history",
+];
+
+// script stores (e.g. `commands.db`, `greetings.json`) used to be next to the bot's own databases
+fn migrate_store_files(data_dir: &Path, store_dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(data_dir) else {
+        return;
+    };
+
+    for old in entries.flatten().map(|entry| entry.path()) {
+        let is_store = match old.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => true,
+            Some("db") => old
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| !BOT_DATABASES.contains(&stem)),
+            _ => false,
+        };
+        let Some(name) = old.file_name().filter(|_| is_store && old.is_file()) else {
+            continue;
+        };
+
+        let new = store_dir.join(name);
+        if new.exists() {
+            log::warn!("not moving {}, {} already exists", old.display(), new.display());
+            continue;
+        }
+
+        let moved = std::fs::create_dir_all(store_dir).and_then(|()| std::fs::rename(&old, &new));
+        match moved {
+            Ok(()) => log::info!("moved {} to {}", old.display(), new.display()),
+            Err(err) => log::warn!("cannot move {} to {}: {err}", old.display(), new.display()),
+        }
+    }
+}
+
+// commands and aliases used to be stored with the `!` they were invoked with
+fn migrate_prefixes(commands_db: &Path, aliases_db: &Path) {
+    let aliases = [
+        ("commands", "command"),
+        ("aliases", "command"),
+        ("aliases", "alias"),
+    ];
+
+    for (db, columns) in [(commands_db, &[("kv", "key")][..]), (aliases_db, &aliases[..])] {
+        match yomi::sql::strip_old_prefix(db, columns) {
+            Ok(0) => {}
+            Ok(n) => log::info!("removed the old prefix from {n} names in {}", db.display()),
+            Err(err) => log::warn!("cannot remove the old prefix in {}: {err}", db.display()),
+        }
+    }
+}
+
 fn handle_irc_event(ev: Result<irc::Event, flume::RecvError>) -> Next {
     ev.map(Next::Event).unwrap_or(Next::Quit)
 }
@@ -55,67 +121,195 @@ fn handle_reroute_event(ev: Result<irc::Message, flume::RecvError>) -> Next {
     ev.map(Next::Route).unwrap_or(Next::Quit)
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn handle_task_event(ev: Result<yomi::Completed, flume::RecvError>) -> Next {
+    ev.map(Next::Task).unwrap_or(Next::Quit)
+}
+
+fn handle_bus_event(ev: Result<yomi::Emitted, flume::RecvError>) -> Next {
+    ev.map(Next::Emit).unwrap_or(Next::Quit)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mode {
+    Run,
+    Repl,
+    Check,
+}
+
+fn main() -> ExitCode {
     simple_env_load::load_env_from([".dev.env", ".secrets.env"]);
+
+    let args = match cli::Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(cli::Error::Help) => {
+            println!("{}", cli::USAGE);
+            return cli::exit(cli::SUCCESS);
+        }
+        Err(err) => {
+            eprintln!("error: {err}\n\n{}", cli::USAGE);
+            return cli::exit(cli::USAGE_ERROR);
+        }
+    };
+
+    if let Some(level) = &args.log_level {
+        std::env::set_var("RUST_LOG", level);
+    }
     alto_logger::init_term_logger().expect("single initalization of logger");
 
-    // TODO actually parse cli args instead of this hack
-    let arg = std::env::args().nth(1);
-    let config_path = arg.as_deref().unwrap_or("config.lua");
-    let config = Config::load(config_path)?;
+    let mut config = match Config::load(&args.config) {
+        Ok(config) => config,
+        Err(err) => return config_error(&err),
+    };
+
+    if let Some(dir) = args.data_dir {
+        config.paths.data = dir;
+    }
 
-    let lua = mlua::Lua::new();
+    let mode = match args.command {
+        cli::Command::Run => Mode::Run,
+        cli::Command::Repl => Mode::Repl,
+        cli::Command::Check => Mode::Check,
+        cli::Command::Db(db) => return inspect_db(&config, db),
+    };
 
-    let (sender, responses) = flume::unbounded();
-    let events = irc::connect(
-        config.twitch.clone(), //
-        responses,
-    );
-    let responder = yomi::Responder::new(sender);
+    if mode == Mode::Run {
+        if let Err(err) = config.check_credentials() {
+            return config_error(&err);
+        }
+    }
 
-    let watcher = Watcher::new(&config.paths.scripts);
+    match run(config, mode) {
+        Ok(code) => code,
+        Err(err) => {
+            log::error!("{err}");
+            cli::exit(cli::FAILURE)
+        }
+    }
+}
 
-    let helix = HelixClient::new(
-        &config.twitch.client_id, //
-        &config.twitch.client_secret,
-    )?;
-    let emote_map = EmoteMap::fetch_emotes(&helix)?;
+fn config_error(err: &ConfigError) -> ExitCode {
+    log::error!("{err}");
+    if let ConfigError::Invalid(..) = err {
+        log::info!(
+            "help:\n  \
+             you can load secrets from the environment with:\n  \
+             get_env(key) -> String"
+        );
+    }
+    cli::exit(cli::CONFIG_ERROR)
+}
+
+fn inspect_db(config: &Config, db: cli::Db) -> ExitCode {
+    let result = match db {
+        cli::Db::List => list_dbs(config),
+        cli::Db::Dump { name } => dump_db(config, &name),
+    };
+
+    match result {
+        Ok(()) => cli::exit(cli::SUCCESS),
+        Err(err) => {
+            log::error!("{err}");
+            cli::exit(cli::FAILURE)
+        }
+    }
+}
 
-    let github = GithubClient::new(&config.github.oauth_token);
+fn list_dbs(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&config.paths.data)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "db"))
+        .collect();
+    paths.sort();
 
-    let spotify = SpotifyClient::new(
-        &config.spotify.client_id,
-        &*config.spotify.client_secret,
-        &*config.spotify.refresh_token,
-    )?;
+    for path in paths {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        match yomi::sql::tables(&path) {
+            Ok(tables) => {
+                println!("{name}");
+                for (table, rows) in tables {
+                    println!("  {table}: {rows} rows");
+                }
+            }
+            Err(err) => println!("{name}: {err}"),
+        }
+    }
+    Ok(())
+}
+
+fn dump_db(config: &Config, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let path = config.paths.data(name).with_extension("db");
+    if !path.is_file() {
+        return Err(format!("there's no data store at {}", path.to_string_lossy()).into());
+    }
+
+    let dump = yomi::sql::dump(&path)?;
+    println!("{}", serde_json::to_string_pretty(&dump)?);
+    Ok(())
+}
+
+fn run(config: Config, mode: Mode) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let lua = mlua::Lua::new();
+
+    let (reroute_tx, reroute) = flume::unbounded();
+
+    let (sender, responses) = flume::unbounded();
+    let queue_depth = irc::QueueDepth::default();
+    let joined = yomi::Joined::new(
+        &config.twitch.channels,
+        config.paths.data("joined").with_extension("db"),
+    );
+    let responder = yomi::Responder::new(sender, config.twitch.split.clone());
 
     let spotify_history_db = config.paths.data("spotify_history").with_extension("db");
-    SpotifyClient::listen_for_changes(&spotify, &spotify_history_db);
+
+    // scripts get their own directory, so their stores can't overwrite the bot's databases
+    let store_dir = config.paths.data("scripts");
 
     let aliases_db = config.paths.data("aliases").with_extension("db");
-    let commands_db = config.paths.data("commands").with_extension("db");
+    // this is written by the `commands` script through its store
+    let commands_db = store_dir.join("commands").with_extension("db");
+    migrate_store_files(&config.paths.data, &store_dir);
+    migrate_prefixes(&commands_db, &aliases_db);
+    let permissions = Permissions::new(config.paths.data("permissions").with_extension("db"));
+    let channels = Channels::new(
+        &config.commands,
+        config.paths.data("channels").with_extension("db"),
+    );
 
-    let (reroute_tx, reroute) = flume::unbounded();
+    let connection = irc::Connection::default();
+
+    // events from the integrations (e.g. spotify track changes), this keeps
+    // the channel open for the main loop when none of them are running
+    let (emitter, integration_events) = yomi::Emitter::channel();
 
-    Globals::new(&lua)
+    let globals = Globals::new(&lua)
         .register(&config)?
         .register(yomi::LoadedModules)?
+        .register(yomi::Sandbox::new(&config.sandbox))?
         .register(yomi::Logger)?
         .register(yomi::Regexp)?
         .register(yomi::Json)?
-        .register(yomi::Store::new(&config.paths.data))?
-        .register(yomi::Bot::new(reroute_tx))?
+        .register(yomi::Store::new(&store_dir))?
+        .register(yomi::Bot::new(
+            reroute_tx.clone(),
+            queue_depth.clone(),
+            responder.clone(),
+            yomi::Prefixes::new(&config.commands),
+            joined.clone(),
+        ))?
         .register(yomi::Rando::new())?
         .register(yomi::Handled::Sink)?
         .register(yomi::fuzzy::Search)?
         .register(yomi::crates::Crates)?
+        .register(yomi::Http::new(&config.http))?
         .register(responder.clone())?
-        .register(helix)?
-        .register(emote_map)?
-        .register(github)?
-        .register(spotify)?
-        .register(SpotifyHistory::new(spotify_history_db))?
-        .register(Aliases::new(&aliases_db))?;
+        .register(Aliases::new(&aliases_db))?
+        .register(permissions.clone())?
+        .register(channels.clone())?
+        .register(connection.clone())?;
+
+    register_integrations(&config, mode, globals, &spotify_history_db, &emitter)?;
 
     let data = std::fs::read_to_string(config.paths.script("init"))?;
     let mut manifest = Manifest::initialize(
@@ -124,18 +318,54 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         &data,
         &aliases_db,
         &commands_db,
+        yomi::ManifestSettings {
+            commands: &config.commands,
+            limits: &config.limits,
+            permissions,
+            channels,
+        },
     )?;
 
+    if mode == Mode::Check {
+        return Ok(check(&config, &manifest));
+    }
+
+    let watcher = Watcher::new(&config.paths.scripts);
+
+    let events = match mode {
+        Mode::Repl => {
+            let channel = config.twitch.channels.first().map_or("#repl", String::as_str);
+            yomi::repl::connect(&config.twitch.name, channel, reroute_tx, responses)
+        }
+        _ => irc::connect(
+            config.twitch.clone(), //
+            responses,
+            queue_depth,
+            joined,
+        ),
+    };
+
     let mut our_user = irc::User::default();
+    let completed = manifest.completed();
+    let emitted = manifest.emitted();
 
     loop {
-        let next = flume::Selector::new()
+        let timer = manifest.next_timer();
+
+        let selector = flume::Selector::new()
             .recv(watcher.next_event(), {
                 |ev| handle_fs_event(ev, &mut manifest, &lua, &aliases_db, &commands_db)
             })
             .recv(&events, handle_irc_event)
             .recv(&reroute, handle_reroute_event)
-            .wait();
+            .recv(&completed, handle_task_event)
+            .recv(&emitted, handle_bus_event)
+            .recv(&integration_events, handle_bus_event);
+
+        let next = match timer {
+            Some(deadline) => selector.wait_deadline(deadline).unwrap_or(Next::Timer),
+            None => selector.wait(),
+        };
 
         let event = match next {
             Next::Event(event) => event,
@@ -144,6 +374,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 continue;
             }
 
+            Next::Task(completed) => {
+                manifest.resume(&lua, completed);
+                continue;
+            }
+
+            Next::Emit(emitted) => {
+                manifest.deliver(&lua, emitted);
+                continue;
+            }
+
+            Next::Timer => {
+                manifest.run_timers(&lua);
+                continue;
+            }
+
             Next::Continue => continue,
             Next::Quit => break,
         };
@@ -153,13 +398,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 our_user = user;
                 our_user.register(Globals::new(&lua))?;
 
-                for channel in &config.twitch.channels {
-                    responder.send(irc::Response::Join {
-                        channel: channel.clone(),
-                    });
-                }
+                connection.connected();
+                manifest.dispatch_event(&lua, "connect", &our_user);
+            }
+            irc::Event::Disconnected { disconnected } => {
+                connection.disconnected(&disconnected);
+                manifest.dispatch_event(&lua, disconnected.name(), &disconnected);
             }
-            irc::Event::Disconnected {} => {}
             irc::Event::Message { msg } => {
                 let msg = irc::Message {
                     our_user: our_user.name.clone(),
@@ -174,8 +419,124 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 };
                 manifest.dispatch(msg, &lua, &responder)
             }
+            irc::Event::Notice { notice } => {
+                manifest.dispatch_event(&lua, notice.name(), &notice);
+            }
+            irc::Event::Clear { clear } => {
+                manifest.dispatch_event(&lua, clear.name(), &clear);
+            }
+            irc::Event::Deleted { deleted } => {
+                manifest.dispatch_event(&lua, deleted.name(), &deleted);
+            }
+            irc::Event::Room { room } => {
+                manifest.dispatch_event(&lua, room.name(), &room);
+            }
+            irc::Event::Membership { membership } => {
+                manifest.dispatch_event(&lua, membership.name(), &membership);
+            }
+        }
+    }
+
+    Ok(cli::exit(cli::SUCCESS))
+}
+
+// scripts that need an integration that isn't registered are disabled when they're loaded
+#[cfg_attr(
+    not(all(feature = "helix", feature = "spotify", feature = "github")),
+    allow(unused_variables)
+)]
+fn register_integrations(
+    config: &Config,
+    mode: Mode,
+    globals: Globals<'_>,
+    spotify_history_db: &Path,
+    emitter: &yomi::Emitter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let connect = mode == Mode::Run;
+    if mode == Mode::Repl {
+        log::info!("not connecting to Helix, Spotify or GitHub in the repl");
+    }
+
+    #[cfg(feature = "helix")]
+    match config.twitch.helix() {
+        Some((client_id, client_secret)) if connect => {
+            let helix = yomi::HelixClient::new(client_id, client_secret)?;
+            let emote_map = yomi::EmoteMap::fetch_emotes(&helix)?;
+            globals.register(helix)?.register(emote_map)?;
+        }
+        // `check` doesn't connect, but the modules that need it should still be checked
+        Some(..) if mode == Mode::Check => {
+            globals.stub(yomi::HelixClient::MODULE)?;
+            globals.stub(yomi::EmoteMap::MODULE)?;
+        }
+        Some(..) => {}
+        None => log::info!("helix is disabled, twitch.client_id and client_secret aren't set"),
+    }
+    #[cfg(not(feature = "helix"))]
+    if config.twitch.helix().is_some() {
+        log::warn!("helix is configured, but the `helix` feature isn't enabled");
+    }
+
+    #[cfg(feature = "spotify")]
+    {
+        globals.register(yomi::SpotifyHistory::new(spotify_history_db))?;
+        match &config.spotify {
+            Some(spotify) if connect => {
+                let client = yomi::SpotifyClient::new(
+                    &spotify.client_id,
+                    &*spotify.client_secret,
+                    &*spotify.refresh_token,
+                )?;
+                yomi::SpotifyClient::listen_for_changes(
+                    &client,
+                    spotify_history_db,
+                    emitter.clone(),
+                    spotify.announce.clone(),
+                );
+                globals.register(client)?;
+            }
+            Some(..) if mode == Mode::Check => globals.stub(yomi::SpotifyClient::MODULE)?,
+            Some(..) => {}
+            None => log::info!("spotify is disabled, there's no spotify configuration"),
+        }
+    }
+    #[cfg(not(feature = "spotify"))]
+    if config.spotify.is_some() {
+        log::warn!("spotify is configured, but the `spotify` feature isn't enabled");
+    }
+
+    #[cfg(feature = "github")]
+    match &config.github {
+        Some(github) if connect => {
+            globals.register(yomi::GithubClient::new(&github.oauth_token))?;
         }
+        Some(..) if mode == Mode::Check => globals.stub(yomi::GithubClient::MODULE)?,
+        Some(..) => {}
+        None => log::info!("github is disabled, there's no github configuration"),
+    }
+    #[cfg(not(feature = "github"))]
+    if config.github.is_some() {
+        log::warn!("github is configured, but the `github` feature isn't enabled");
     }
 
     Ok(())
 }
+
+// `run` would fail without the credentials, so they're reported along with the scripts
+fn check(config: &Config, manifest: &Manifest) -> ExitCode {
+    let mut problems = vec![];
+    if let Err(ConfigError::Invalid(errors)) = config.check_credentials() {
+        problems.extend(errors);
+    }
+    problems.extend(manifest.problems().iter().cloned());
+
+    if problems.is_empty() {
+        println!("ok");
+        return cli::exit(cli::SUCCESS);
+    }
+
+    for problem in problems {
+        println!("{problem}");
+    }
+    cli::exit(cli::CHECK_FAILED)
+}
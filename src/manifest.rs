@@ -1,9 +1,18 @@
 use std::{
-    borrow::Cow,
+    collections::HashMap,
     path::{Path, PathBuf},
 };
 
-use crate::{help::HelpProvider, irc::Message, pattern::Pattern, responder::Responder};
+use mlua::IntoLua;
+
+use crate::{
+    config,
+    help::HelpProvider,
+    irc::{Message, MessageClass},
+    pattern::Pattern,
+    responder::Responder,
+    Channels, GlobalItem, Globals, Permissions, Prefixes, Sandbox,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -18,13 +27,57 @@ mod handled;
 pub use handled::Handled;
 
 mod mapping;
+use mapping::Context;
 pub use mapping::Mapping;
 
+mod cooldown;
+use cooldown::Cooldowns;
+
+mod timer;
+use timer::Timers;
+
+mod budget;
+use budget::{Budget, Failure};
+
+mod bus;
+use bus::Bus;
+pub use bus::{Emitted, Emitter};
+
+mod tasks;
+pub(crate) use tasks::{spawn, wrap};
+pub use tasks::Completed;
+use tasks::Tasks;
+
+/// What the manifest is configured with, when it is first initialized
+pub struct Settings<'a> {
+    pub commands: &'a config::Commands,
+    pub limits: &'a config::Limits,
+    pub permissions: Permissions,
+    pub channels: Channels,
+}
+
 #[derive(Debug)]
 pub struct Manifest {
     pub init: PathBuf,
     commands: Vec<Mapping>,
-    listeners: Vec<mlua::Function>,
+    listeners: Vec<Handler>,
+    events: HashMap<String, Vec<Handler>>,
+    permissions: Permissions,
+    channels: Channels,
+    cooldowns: Cooldowns,
+    timers: Timers,
+    bus: Bus,
+    budget: Budget,
+    tasks: Tasks,
+    prefixes: Prefixes,
+    problems: Vec<String>,
+}
+
+/// A listener or event handler, named so it can be reported (and disabled)
+#[derive(Clone, Debug)]
+struct Handler {
+    name: String,
+    function: mlua::Function,
 }
 
 impl Manifest {
@@ -34,8 +87,28 @@ impl Manifest {
         source: &str,
         aliases_db: impl Into<PathBuf>,
         commands_db: impl Into<PathBuf>,
+        settings: Settings<'_>,
     ) -> mlua::Result<Self> {
         let scripts = scripts_dir.as_ref();
+        let Settings {
+            commands,
+            limits,
+            permissions,
+            channels,
+        } = settings;
+
+        let cooldowns = Cooldowns::new(&commands.cooldown);
+        cooldowns.clone().register(Globals::new(lua))?;
+
+        let timers = Timers::default();
+        timers.clone().register(Globals::new(lua))?;
+
+        let budget = Budget::new(lua, limits)?;
+        let tasks = Tasks::new(lua, limits);
+
+        let bus = Bus::new(tasks.depth());
+        bus.clone().register(Globals::new(lua))?;
+        let prefixes = Prefixes::new(commands);
 
         // BUG figure out the syntax for excluding a specific file
         // we don't want a cycle between init -> foo -> init
@@ -47,9 +120,20 @@ impl Manifest {
             init: scripts.join("init").with_extension("lua"),
             commands: vec![],
             listeners: vec![],
+            events: HashMap::new(),
+            permissions,
+            channels,
+            cooldowns,
+            timers,
+            bus,
+            budget,
+            tasks,
+            prefixes,
+            problems: vec![],
         };
         if let Err(err) = this.load(lua, source, aliases_db, commands_db) {
-            log::warn!("{err}")
+            log::warn!("{err}");
+            this.problems.push(err.to_string());
         }
         Ok(this)
     }
@@ -74,11 +158,34 @@ impl Manifest {
 
         _ = std::mem::take(&mut self.commands);
         _ = std::mem::take(&mut self.listeners);
+        _ = std::mem::take(&mut self.events);
+        self.timers.clear();
+        self.bus.clear();
+        self.tasks.clear();
+        self.budget.reset();
+        self.problems.clear();
 
-        let value = match lua.load(data).eval::<mlua::Table>() {
+        let chunk = match Sandbox::environment_for(lua, "init")? {
+            Some(env) => lua.load(data).set_environment(env),
+            None => lua.load(data),
+        };
+
+        // loading the manifest (and anything it requires) is limited like a handler
+        let value = chunk
+            .into_function()
+            .map_err(Failure::Lua)
+            .and_then(|function| self.budget.call::<mlua::Table>(lua, "init.lua", function, ()));
+
+        let value = match value {
             Ok(value) => value,
-            Err(err) => {
+            Err(failure) => {
+                let err = match failure {
+                    Failure::Exceeded { reason, .. } => reason.to_string(),
+                    Failure::Lua(err) => err.to_string(),
+                    Failure::Disabled => unreachable!("the budget was just reset"),
+                };
                 log::warn!("invalid manifest: {err}");
+                self.problems.push(format!("invalid manifest: {err}"));
                 return Ok(());
             }
         };
@@ -88,16 +195,13 @@ impl Manifest {
         // TODO redo this stuff
 
         // always load listeners
-        self.listeners = value
-            .get::<mlua::Table>("listeners")
-            .map(|listeners| {
-                listeners
-                    .pairs::<mlua::Value, mlua::Function>()
-                    .flatten()
-                    .map(|(_, function)| function)
-                    .collect()
-            })
-            .unwrap_or_default();
+        if let Ok(listeners) = value.get::<mlua::Table>("listeners") {
+            self.add_listeners("init", &listeners);
+        }
+
+        if let Ok(events) = value.get::<mlua::Table>("events") {
+            self.add_events("init", &events);
+        }
 
         let mut errors = vec![];
 
@@ -113,64 +217,56 @@ impl Manifest {
             }
         };
 
+        let mut disabled = vec![];
+
         for (module, table) in commands
             .iter()
             .flat_map(|t| t.pairs::<String, mlua::Table>().flatten())
         {
-            if let Ok(listeners) = table.get::<Vec<mlua::Function>>("listeners") {
-                self.listeners.extend(listeners);
+            if let Some(missing) = missing_requirement(lua, &table) {
+                log::info!("`{module}` is disabled, `{missing}` isn't available");
+                self.problems
+                    .push(format!("`{module}` is disabled, `{missing}` isn't available"));
+
+                // its commands are still checked, so mistakes show up before it's enabled
+                for (index, table) in table.pairs::<usize, mlua::Table>().flatten() {
+                    let label = format!("{module}[{index}]");
+                    load_mapping(&module, &label, None, &table, MessageClass::User, &mut errors);
+                }
+                disabled.push(module);
+                continue;
+            }
+
+            if let Ok(listeners) = table.get::<mlua::Table>("listeners") {
+                self.add_listeners(&module, &listeners);
+            }
+
+            if let Ok(events) = table.get::<mlua::Table>("events") {
+                self.add_events(&module, &events);
             }
 
             for (index, table) in table.pairs::<usize, mlua::Table>().flatten() {
-                match (
-                    table.get("command"),
-                    table.get::<Option<String>>("args"),
-                    table.get("help"),
-                    table.get("elevated"),
-                    table.get("handler"),
-                ) {
-                    (Ok(command), Ok(raw_pattern), Ok(help), Ok(elevated), Ok(handler)) => {
-                        let pattern = match raw_pattern.as_deref().map(Pattern::parse) {
-                            Some(Ok(pat)) => Some(pat),
-                            Some(Err(err)) => {
-                                errors.push(err.to_string());
-                                continue;
-                            }
-                            None => None,
-                        };
-
-                        let mapping = Mapping {
-                            command,
-                            pattern,
-                            raw_pattern,
-                            help,
-                            elevated,
-                            handler,
-                        };
-                        self.commands.push(mapping);
-                    }
-                    (command, pattern, help, _, handler, ..) => {
-                        if command.is_err() {
-                            errors.push(format!("missing `command` for `{module}[{index}]`"));
-                        }
-                        if pattern.is_err() {
-                            errors.push(format!("missing `args` for `{module}[{index}]`"));
-                        }
-                        if help.is_err() {
-                            errors.push(format!("missing `help` for `{module}[{index}]`"));
-                        }
-                        if handler.is_err() {
-                            errors.push(format!("missing `handler` for `{module}[{index}]`"));
-                        }
-                    }
+                let label = format!("{module}[{index}]");
+                if let Some(mapping) =
+                    load_mapping(&module, &label, None, &table, MessageClass::User, &mut errors)
+                {
+                    self.commands.push(mapping);
                 }
             }
         }
 
         report.push_str(&format!("\nlisteners: {}", self.listeners.len()));
+        report.push_str(&format!(
+            "\nevent handlers: {}",
+            self.events.values().map(Vec::len).sum::<usize>()
+        ));
+        if !disabled.is_empty() {
+            report.push_str(&format!("\ndisabled: {}", disabled.join(", ")));
+        }
         log::info!("{report}");
 
         // TODO redo this
+        self.problems.extend(errors.iter().cloned());
         if !errors.is_empty() {
             let join = |mut s: String, c: String| {
                 if !s.is_empty() {
@@ -186,23 +282,18 @@ impl Manifest {
         }
 
         if !self.commands.is_empty() {
-            let join = |mut a: String, pattern: Cow<'_, str>| {
+            let join = |mut a: String, usage: String| {
                 if !a.is_empty() {
                     a.push('\n');
                 }
-                a.push_str(&pattern);
+                a.push_str(&usage);
                 a
             };
 
             let out = self
                 .commands
                 .iter()
-                .map(|m| {
-                    m.raw_pattern
-                        .as_deref()
-                        .map(|p| Cow::from(format!("{} {p}", m.command)))
-                        .unwrap_or(Cow::from(&m.command))
-                })
+                .map(|mapping| mapping.usage(""))
                 .fold(String::from("loaded commands"), join);
             log::info!("{out}");
         }
@@ -211,23 +302,323 @@ impl Manifest {
         Ok(())
     }
 
+    /// Everything that went wrong the last time the scripts were loaded
+    pub fn problems(&self) -> &[String] {
+        &self.problems
+    }
+
+    fn add_listeners(&mut self, module: &str, listeners: &mlua::Table) {
+        for (i, (key, function)) in listeners
+            .pairs::<mlua::Value, mlua::Function>()
+            .flatten()
+            .enumerate()
+        {
+            let name = match key {
+                mlua::Value::String(key) => format!("{module}.listeners.{}", key.to_string_lossy()),
+                _ => format!("{module}.listeners[{}]", i + 1),
+            };
+            self.listeners.push(Handler { name, function });
+        }
+    }
+
+    // an event can have either a single handler, or a list of handlers
+    fn add_events(&mut self, module: &str, events: &mlua::Table) {
+        for (event, value) in events.pairs::<String, mlua::Value>().flatten() {
+            let name = format!("{module}.events.{event}");
+            let handlers = self.events.entry(event).or_default();
+            match value {
+                mlua::Value::Function(function) => handlers.push(Handler { name, function }),
+                mlua::Value::Table(table) => handlers.extend(
+                    table
+                        .sequence_values::<mlua::Function>()
+                        .flatten()
+                        .enumerate()
+                        .map(|(i, function)| Handler {
+                            name: format!("{name}[{}]", i + 1),
+                            function,
+                        }),
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn dispatch_event(&self, lua: &mlua::Lua, name: &str, payload: impl IntoLua) {
+        let subscribers = self.bus.subscribers(name);
+        let handlers = self
+            .events
+            .get(name)
+            .into_iter()
+            .flatten()
+            .chain(&subscribers)
+            .collect::<Vec<_>>();
+        if handlers.is_empty() {
+            return;
+        }
+
+        let payload = match payload.into_lua(lua) {
+            Ok(payload) => payload,
+            Err(err) => {
+                log::warn!("cannot create payload for event `{name}`: {err}");
+                return;
+            }
+        };
+
+        for handler in handlers {
+            let report = {
+                let name = name.to_string();
+                move |failure| {
+                    if let Failure::Lua(err) = failure {
+                        log::warn!("cannot call handler for event `{name}` because: {err}")
+                    }
+                }
+            };
+            self.tasks.call::<()>(
+                lua,
+                &self.budget,
+                &handler.name,
+                &handler.function,
+                payload.clone(),
+                report,
+            );
+        }
+    }
+
+    /// Events emitted by the scripts, see [`Manifest::deliver`]
+    pub fn emitted(&self) -> flume::Receiver<Emitted> {
+        self.bus.emitted()
+    }
+
+    /// Delivers an emitted event to its handlers
+    pub fn deliver(&self, lua: &mlua::Lua, emitted: Emitted) {
+        self.bus.deliver(lua, emitted, |name, payload| {
+            self.dispatch_event(lua, name, payload);
+        });
+    }
+
+    /// When the next script timer should run
+    pub fn next_timer(&self) -> Option<std::time::Instant> {
+        self.timers.next_deadline()
+    }
+
+    pub fn run_timers(&self, lua: &mlua::Lua) {
+        self.timers.run_due(lua, &self.budget, &self.tasks)
+    }
+
+    /// Requests that handlers are waiting on, see [`Manifest::resume`]
+    pub fn completed(&self) -> flume::Receiver<Completed> {
+        self.tasks.completed()
+    }
+
+    /// Resumes the handler that was waiting on this request
+    pub fn resume(&self, lua: &mlua::Lua, completed: Completed) {
+        self.tasks.resume(lua, &self.budget, completed)
+    }
+
     pub fn dispatch(&self, msg: Message, lua: &mlua::Lua, responder: &Responder) {
         log::trace!("[{}] {}: {}", msg.channel, msg.sender, msg.data);
+        self.timers.record_message(&msg.channel);
 
         for listener in &self.listeners {
-            match listener.call::<Handled>(&msg) {
-                Ok(Handled::Sink) => break,
-                Ok(Handled::Bubble) => {}
-                Err(err) => log::warn!("cannot call listener because: {err}"),
+            let report = {
+                let (responder, msg) = (responder.clone(), msg.clone());
+                let (name, notify) = (listener.name.clone(), self.budget.notify());
+                move |failure| match failure {
+                    Failure::Disabled => {}
+                    Failure::Exceeded { reason, disabled } => {
+                        if notify {
+                            responder.error(&msg, Budget::describe(&name, reason, disabled));
+                        }
+                    }
+                    Failure::Lua(err) => {
+                        log::warn!("cannot call listener {name} because: {err}")
+                    }
+                }
+            };
+
+            // a listener that's waiting on a request can't sink the message
+            let handled = self.tasks.call::<Handled>(
+                lua,
+                &self.budget,
+                &listener.name,
+                &listener.function,
+                &msg,
+                report,
+            );
+            if let Some(Handled::Sink) = handled {
+                break;
             }
         }
 
+        let Some(invocation) = self.prefixes.parse(&msg) else {
+            return;
+        };
+
+        let ctx = Context {
+            lua,
+            responder,
+            permissions: &self.permissions,
+            channels: &self.channels,
+            cooldowns: &self.cooldowns,
+            budget: &self.budget,
+            tasks: &self.tasks,
+        };
+
         let mut sink = false;
         for mapping in &self.commands {
-            mapping.dispatch(&msg, lua, responder, &mut sink);
+            mapping.dispatch(&msg, &invocation, &ctx, &mut sink);
             if sink {
                 break;
             }
         }
     }
 }
+
+// `command` is only provided for subcommands, which take their name from their parent
+fn load_mapping(
+    module: &str,
+    label: &str,
+    command: Option<String>,
+    table: &mlua::Table,
+    inherited: MessageClass,
+    errors: &mut Vec<String>,
+) -> Option<Mapping> {
+    let command = match command {
+        Some(command) => Ok(command),
+        None => table.get("command"),
+    };
+
+    match (
+        command,
+        table.get::<Option<String>>("args"),
+        table.get("help"),
+        table.get::<Option<String>>("access"),
+        table.get::<Option<bool>>("elevated"),
+        table.get::<Option<f64>>("cooldown"),
+        table.get::<Option<f64>>("user_cooldown"),
+        table.get::<Option<mlua::Function>>("handler"),
+        table.get::<Option<mlua::Table>>("subcommands"),
+    ) {
+        (
+            Ok(command),
+            Ok(raw_pattern),
+            Ok(help),
+            Ok(access),
+            Ok(elevated),
+            Ok(cooldown),
+            Ok(user_cooldown),
+            Ok(handler),
+            Ok(subcommands),
+        ) => {
+            // `elevated` is the same as `access = "vip"`
+            let access = match access.as_deref().map(MessageClass::parse_access) {
+                Some(Some(access)) => access,
+                Some(None) => {
+                    errors.push(format!(
+                        "invalid `access` for `{label}`: {access}",
+                        access = access.unwrap_or_default()
+                    ));
+                    return None;
+                }
+                None if elevated == Some(true) => MessageClass::Vip,
+                None => inherited,
+            };
+
+            let pattern = match raw_pattern.as_deref().map(Pattern::parse) {
+                Some(Ok(pat)) => Some(pat),
+                Some(Err(err)) => {
+                    errors.push(err.to_string());
+                    return None;
+                }
+                None => None,
+            };
+
+            let (cooldown, user_cooldown) = match (
+                cooldown.map(seconds).transpose(),
+                user_cooldown.map(seconds).transpose(),
+            ) {
+                (Ok(cooldown), Ok(user_cooldown)) => (cooldown, user_cooldown),
+                (Err(secs), _) | (_, Err(secs)) => {
+                    errors.push(format!("invalid cooldown for `{label}`: {secs}"));
+                    return None;
+                }
+            };
+
+            // subcommands inherit the access level of their parent
+            let mut subcommands = subcommands
+                .iter()
+                .flat_map(|t| t.pairs::<String, mlua::Table>().flatten())
+                .filter_map(|(name, table)| {
+                    let label = format!("{label}.subcommands.{name}");
+                    let command = format!("{command} {name}");
+                    load_mapping(module, &label, Some(command), &table, access, errors)
+                })
+                .collect::<Vec<_>>();
+            subcommands.sort_unstable_by(|l, r| l.command.cmp(&r.command));
+
+            if handler.is_none() && subcommands.is_empty() {
+                errors.push(format!("missing `handler` for `{label}`"));
+                return None;
+            }
+
+            Some(Mapping {
+                command,
+                module: module.to_string(),
+                pattern,
+                raw_pattern,
+                help,
+                access,
+                cooldown,
+                user_cooldown,
+                handler,
+                subcommands,
+            })
+        }
+        (command, pattern, help, access, _, cooldown, user_cooldown, handler, subcommands) => {
+            if command.is_err() {
+                errors.push(format!("missing `command` for `{label}`"));
+            }
+            if pattern.is_err() {
+                errors.push(format!("missing `args` for `{label}`"));
+            }
+            if help.is_err() {
+                errors.push(format!("missing `help` for `{label}`"));
+            }
+            if access.is_err() {
+                errors.push(format!("invalid `access` for `{label}`"));
+            }
+            if cooldown.is_err() {
+                errors.push(format!("invalid `cooldown` for `{label}`"));
+            }
+            if user_cooldown.is_err() {
+                errors.push(format!("invalid `user_cooldown` for `{label}`"));
+            }
+            if handler.is_err() {
+                errors.push(format!("invalid `handler` for `{label}`"));
+            }
+            if subcommands.is_err() {
+                errors.push(format!("invalid `subcommands` for `{label}`"));
+            }
+            None
+        }
+    }
+}
+
+// a module can list the globals it needs (e.g. `requires = { "spotify" }`),
+// it's skipped if any of them weren't registered
+fn missing_requirement(lua: &mlua::Lua, module: &mlua::Table) -> Option<String> {
+    let requires = module.get::<mlua::Table>("requires").ok()?;
+    let globals = lua.globals();
+    requires
+        .sequence_values::<String>()
+        .flatten()
+        .find(|name| !globals.contains_key(&**name).unwrap_or(false))
+}
+
+// cooldowns are given in seconds, and have to end at some point we can represent
+fn seconds(secs: f64) -> Result<std::time::Duration, f64> {
+    std::time::Duration::try_from_secs_f64(secs)
+        .ok()
+        .filter(|&duration| std::time::Instant::now().checked_add(duration).is_some())
+        .ok_or(secs)
+}
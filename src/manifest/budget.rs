@@ -0,0 +1,266 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use mlua::{FromLuaMulti, HookTriggers, IntoLuaMulti, VmState};
+
+use crate::config;
+
+// how often the hook checks the budget
+const STEP: u32 = 1000;
+
+/// Why a handler was stopped
+#[derive(Copy, Clone, Debug)]
+pub enum Exceeded {
+    Time(Duration),
+    Instructions(u64),
+    Memory,
+}
+
+impl std::fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Time(time) => write!(f, "took longer than {time:?}"),
+            Self::Instructions(count) => write!(f, "ran more than {count} instructions"),
+            Self::Memory => f.write_str("ran out of memory"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Failure {
+    /// The handler was disabled for going over its budget too many times
+    Disabled,
+    /// The handler was stopped, and maybe disabled
+    Exceeded { reason: Exceeded, disabled: bool },
+    /// The handler returned an error
+    Lua(mlua::Error),
+}
+
+#[derive(Debug, Default)]
+struct Strikes {
+    counts: HashMap<String, u32>,
+    disabled: HashSet<String>,
+}
+
+/// Per-invocation limits for Lua handlers
+///
+/// Handlers that go over their budget too many times are disabled until the scripts are reloaded
+#[derive(Debug)]
+pub struct Budget {
+    limits: config::Limits,
+    strikes: RefCell<Strikes>,
+}
+
+impl Budget {
+    pub fn new(lua: &mlua::Lua, limits: &config::Limits) -> mlua::Result<Self> {
+        if let Some(memory) = limits.memory_bytes() {
+            lua.set_memory_limit(memory)?;
+        }
+
+        Ok(Self {
+            limits: limits.clone(),
+            strikes: RefCell::default(),
+        })
+    }
+
+    pub const fn notify(&self) -> bool {
+        self.limits.notify
+    }
+
+    pub fn reset(&self) {
+        let mut strikes = self.strikes.borrow_mut();
+        if !strikes.disabled.is_empty() {
+            log::info!("re-enabling {} handlers", strikes.disabled.len());
+        }
+        *strikes = Strikes::default();
+    }
+
+    pub fn is_disabled(&self, name: &str) -> bool {
+        self.strikes.borrow().disabled.contains(name)
+    }
+
+    /// Resumes `thread` with the instruction and time budget applied to it
+    ///
+    /// The budget starts over each time the thread is resumed, so time spent waiting
+    /// on a request isn't counted
+    pub fn resume(
+        &self,
+        name: &str,
+        thread: &mlua::Thread,
+        args: mlua::MultiValue,
+    ) -> Result<mlua::MultiValue, Failure> {
+        if self.is_disabled(name) {
+            log::trace!("skipping disabled handler: {name}");
+            return Err(Failure::Disabled);
+        }
+
+        let tripped = Rc::new(Cell::new(None));
+        let instructions = Cell::new(0_u64);
+        let deadline = Instant::now() + self.limits.time();
+        let (time, max) = (self.limits.time(), self.limits.instructions);
+
+        // coroutines the handler creates inherit this
+        thread.set_hook(HookTriggers::new().every_nth_instruction(STEP), {
+            let tripped = Rc::clone(&tripped);
+            move |lua, _debug| {
+                if let Some(reason) = tripped.get() {
+                    return stop(lua, reason);
+                }
+
+                let count = instructions.get() + STEP as u64;
+                instructions.set(count);
+
+                let reason = match max {
+                    Some(max) if count > max => Exceeded::Instructions(max),
+                    _ if Instant::now() >= deadline => Exceeded::Time(time),
+                    _ => return Ok(VmState::Continue),
+                };
+
+                tripped.set(Some(reason));
+                stop(lua, reason)
+            }
+        });
+
+        // the handler could have caught the error and returned normally
+        let result = thread.resume(args);
+        let reason = match (tripped.get(), result) {
+            (Some(reason), _) => reason,
+            (None, Ok(values)) => return Ok(values),
+            (None, Err(err)) if is_memory_error(&err) => Exceeded::Memory,
+            (None, Err(err)) => return Err(Failure::Lua(err)),
+        };
+
+        let disabled = self.strike(name, reason);
+        Err(Failure::Exceeded { reason, disabled })
+    }
+
+    /// Calls `function` to completion with the budget applied to it, it cannot yield
+    pub fn call<T: FromLuaMulti>(
+        &self,
+        lua: &mlua::Lua,
+        name: &str,
+        function: mlua::Function,
+        args: impl IntoLuaMulti,
+    ) -> Result<T, Failure> {
+        let thread = lua.create_thread(function).map_err(Failure::Lua)?;
+        let args = args.into_lua_multi(lua).map_err(Failure::Lua)?;
+        let values = self.resume(name, &thread, args)?;
+        if thread.status() == mlua::ThreadStatus::Resumable {
+            return Err(Failure::Lua(mlua::Error::runtime(format!("{name} cannot yield"))));
+        }
+        T::from_lua_multi(values, lua).map_err(Failure::Lua)
+    }
+
+    /// A message for chat, explaining why the handler was stopped
+    pub fn describe(name: &str, reason: Exceeded, disabled: bool) -> String {
+        if disabled {
+            format!("{name} {reason}, it has been disabled until the scripts are reloaded")
+        } else {
+            format!("{name} {reason} and was stopped")
+        }
+    }
+
+    fn strike(&self, name: &str, reason: Exceeded) -> bool {
+        let mut strikes = self.strikes.borrow_mut();
+        let count = strikes.counts.entry(name.to_string()).or_default();
+        *count += 1;
+        let count = *count;
+
+        let max = self.limits.strikes;
+        log::warn!("{name} {reason} and was stopped (strike {count}/{max})");
+
+        if max == 0 || count < max {
+            return false;
+        }
+
+        log::error!("{name} has been disabled until the scripts are reloaded");
+        strikes.disabled.insert(name.to_string());
+        true
+    }
+}
+
+// a handler can catch the error with `pcall`, so once its over budget every instruction
+// of whichever thread is running errors, until there is nothing left to catch it
+fn stop(lua: &mlua::Lua, reason: Exceeded) -> mlua::Result<VmState> {
+    let triggers = HookTriggers::new().every_nth_instruction(1);
+    lua.current_thread().set_hook(triggers, move |_lua, _debug| {
+        Err(mlua::Error::runtime(reason.to_string()))
+    });
+    Err(mlua::Error::runtime(reason.to_string()))
+}
+
+fn is_memory_error(err: &mlua::Error) -> bool {
+    match err {
+        mlua::Error::MemoryError(..) => true,
+        mlua::Error::CallbackError { cause, .. } => is_memory_error(cause),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(code: &str) -> Result<mlua::MultiValue, Failure> {
+        let lua = mlua::Lua::new();
+        let limits = config::Limits {
+            instructions: Some(100_000),
+            ..config::Limits::default()
+        };
+        let budget = Budget::new(&lua, &limits).unwrap();
+        let function = lua.load(code).into_function().unwrap();
+        budget.call(&lua, "test", function, ())
+    }
+
+    fn exceeded(result: Result<mlua::MultiValue, Failure>) -> bool {
+        matches!(
+            result,
+            Err(Failure::Exceeded {
+                reason: Exceeded::Instructions(100_000),
+                ..
+            })
+        )
+    }
+
+    #[test]
+    fn within_budget() {
+        let values = run("local n = 0 for i = 1, 100 do n = n + i end return n").unwrap();
+        assert_eq!(values.len(), 1);
+    }
+
+    #[test]
+    fn infinite_loop() {
+        assert!(exceeded(run("while true do end")));
+    }
+
+    #[test]
+    fn pcall_loop() {
+        assert!(exceeded(run("while true do pcall(function() while true do end end) end")));
+    }
+
+    #[test]
+    fn nested_pcall_loop() {
+        assert!(exceeded(run("while true do pcall(pcall, function() while true do end end) end")));
+    }
+
+    #[test]
+    fn pcall_then_return() {
+        assert!(exceeded(run("pcall(function() while true do end end) return true")));
+    }
+
+    #[test]
+    fn coroutine_loop() {
+        assert!(exceeded(run(
+            "while true do coroutine.resume(coroutine.create(function() while true do end end)) end"
+        )));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(run("error('nope')"), Err(Failure::Lua(..))));
+    }
+}
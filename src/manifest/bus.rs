@@ -0,0 +1,214 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+use mlua::{IntoLua, LuaSerdeExt as _, UserData};
+
+use crate::{manifest::Handler, GlobalItem};
+
+// how many events handlers can emit in a row before we assume they're stuck in a loop
+const MAX_DEPTH: u32 = 8;
+
+// creates the payload on the main loop, where the Lua state is
+type Payload = Box<dyn FnOnce(&mlua::Lua) -> mlua::Result<mlua::Value> + Send>;
+
+/// An event waiting to be delivered to the scripts on the main loop
+pub struct Emitted {
+    name: String,
+    payload: Payload,
+    // how many event handlers led to this one being emitted
+    depth: u32,
+}
+
+impl std::fmt::Debug for Emitted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Emitted")
+            .field("name", &self.name)
+            .field("depth", &self.depth)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Emits events to the scripts, from any thread
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    tx: flume::Sender<Emitted>,
+}
+
+impl Emitter {
+    /// Creates an emitter, the events it sends should be given to [`Manifest::deliver`]
+    ///
+    /// [`Manifest::deliver`]: crate::Manifest::deliver
+    pub fn channel() -> (Self, flume::Receiver<Emitted>) {
+        let (tx, rx) = flume::unbounded();
+        (Self { tx }, rx)
+    }
+
+    pub fn emit(&self, name: &str, payload: impl IntoLua + Send + 'static) {
+        let _ = self.tx.send(Emitted {
+            name: name.to_string(),
+            payload: Box::new(move |lua| payload.into_lua(lua)),
+            depth: 0,
+        });
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    subscribers: HashMap<String, Vec<Handler>>,
+    next_id: u64,
+}
+
+/// Lets scripts emit events to each other
+///
+/// Events are delivered on the main loop, to the `events` tables of the modules
+/// and to anything subscribed with `events:on`. Subscriptions are cleared when
+/// the manifest is reloaded
+#[derive(Clone, Debug)]
+pub struct Bus {
+    state: Rc<RefCell<State>>,
+    // shared with the tasks, so a handler that waits on a request keeps its depth
+    depth: Rc<Cell<u32>>,
+    emitter: Emitter,
+    rx: flume::Receiver<Emitted>,
+}
+
+impl GlobalItem for Bus {
+    const MODULE: &'static str = "events";
+}
+
+impl Bus {
+    pub fn new(depth: Rc<Cell<u32>>) -> Self {
+        let (emitter, rx) = Emitter::channel();
+        Self {
+            state: Rc::default(),
+            depth,
+            emitter,
+            rx,
+        }
+    }
+
+    pub fn emitted(&self) -> flume::Receiver<Emitted> {
+        self.rx.clone()
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.borrow_mut();
+        let count = state.subscribers.values().map(Vec::len).sum::<usize>();
+        if count > 0 {
+            log::debug!("removing {count} event subscriptions");
+        }
+        state.subscribers.clear();
+    }
+
+    pub(super) fn subscribers(&self, name: &str) -> Vec<Handler> {
+        let state = self.state.borrow();
+        state.subscribers.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Runs `f` while delivering `emitted`, so anything it emits is counted towards the depth
+    pub(super) fn deliver(
+        &self,
+        lua: &mlua::Lua,
+        emitted: Emitted,
+        f: impl FnOnce(&str, mlua::Value),
+    ) {
+        let Emitted {
+            name,
+            payload,
+            depth,
+        } = emitted;
+
+        let payload = match payload(lua) {
+            Ok(payload) => payload,
+            Err(err) => {
+                log::warn!("cannot create payload for event `{name}`: {err}");
+                return;
+            }
+        };
+
+        let previous = self.depth.replace(depth);
+        f(&name, payload);
+        self.depth.set(previous);
+    }
+
+    fn emit(&self, lua: &mlua::Lua, name: String, payload: mlua::Value) -> mlua::Result<()> {
+        let depth = self.depth.get() + 1;
+        if depth > MAX_DEPTH {
+            return Err(mlua::Error::runtime(format!(
+                "`{name}` was emitted by {MAX_DEPTH} event handlers in a row, \
+                is a handler emitting the event it handles?"
+            )));
+        }
+
+        // events can't carry anything tied to this Lua state (e.g. functions)
+        let payload: serde_json::Value = lua.from_value(payload)?;
+        let _ = self.emitter.tx.send(Emitted {
+            name,
+            payload: Box::new(move |lua| lua.to_value(&payload)),
+            depth,
+        });
+        Ok(())
+    }
+
+    fn on(&self, event: String, function: mlua::Function) {
+        let mut state = self.state.borrow_mut();
+        state.next_id += 1;
+        let name = format!("events.on.{event}#{}", state.next_id);
+        let handler = Handler { name, function };
+        state.subscribers.entry(event).or_default().push(handler);
+    }
+}
+
+impl UserData for Bus {
+    fn add_methods<M>(methods: &mut M)
+    where
+        M: mlua::UserDataMethods<Self>,
+    {
+        methods.add_method("emit", |lua, this, (name, payload): (String, mlua::Value)| {
+            this.emit(lua, name, payload)
+        });
+
+        methods.add_method("on", |_lua, this, (name, function): (String, mlua::Function)| {
+            this.on(name, function);
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loop_protection() {
+        let lua = mlua::Lua::new();
+        let bus = Bus::new(Rc::default());
+        bus.clone().register(crate::Globals::new(&lua)).unwrap();
+
+        // a handler that emits the event it handles
+        lua.load(r#"events:on("ping", function(n) events:emit("ping", n + 1) end)"#)
+            .exec()
+            .unwrap();
+        lua.load(r#"events:emit("ping", 0)"#).exec().unwrap();
+
+        let (mut delivered, mut errors) = (0, vec![]);
+        while let Ok(emitted) = bus.emitted().try_recv() {
+            bus.deliver(&lua, emitted, |name, payload| {
+                delivered += 1;
+                for handler in bus.subscribers(name) {
+                    if let Err(err) = handler.function.call::<()>(payload.clone()) {
+                        errors.push(err.to_string());
+                    }
+                }
+            });
+        }
+
+        assert_eq!(delivered, MAX_DEPTH);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("in a row"), "{errors:?}");
+        assert_eq!(bus.depth.get(), 0);
+    }
+}
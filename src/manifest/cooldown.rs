@@ -0,0 +1,201 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use mlua::UserData;
+
+use crate::{config, irc::MessageClass, time::TimeSpan, GlobalItem};
+
+#[derive(Default, Debug)]
+struct State {
+    // (channel, command) -> ready at
+    commands: HashMap<(String, String), Instant>,
+    // (channel, command, user) -> ready at
+    users: HashMap<(String, String, String), Instant>,
+    // (channel, command) -> when we can tell someone about the cooldown again
+    notified: HashMap<(String, String), Instant>,
+}
+
+/// Tracks when commands can be used again, per channel and per user
+#[derive(Clone, Debug)]
+pub struct Cooldowns {
+    state: Rc<RefCell<State>>,
+    bypass: Option<MessageClass>,
+    notify: bool,
+}
+
+impl GlobalItem for Cooldowns {
+    const MODULE: &'static str = "cooldowns";
+}
+
+impl Cooldowns {
+    pub fn new(settings: &config::Cooldown) -> Self {
+        let bypass = settings.bypass.as_deref().and_then(|bypass| {
+            let class = MessageClass::parse_access(bypass);
+            if class.is_none() && bypass != "none" {
+                log::warn!("invalid cooldown bypass access level: {bypass}");
+            }
+            class
+        });
+
+        Self {
+            state: Rc::default(),
+            bypass,
+            notify: settings.notify,
+        }
+    }
+
+    /// Whether to tell the user this command is on cooldown for `remaining`
+    ///
+    /// This is only true once per cooldown, so spamming a command doesn't spam replies
+    pub fn notify(&self, channel: &str, command: &str, remaining: Duration) -> bool {
+        if !self.notify {
+            return false;
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.borrow_mut();
+        state.notified.retain(|_, until| *until > now);
+
+        let key = (channel.to_string(), command.to_string());
+        if state.notified.contains_key(&key) {
+            return false;
+        }
+        state.notified.insert(key, now + remaining);
+        true
+    }
+
+    pub fn can_bypass(&self, class: MessageClass) -> bool {
+        self.bypass.is_some_and(|bypass| class >= bypass)
+    }
+
+    /// How long until this command can be used by this user, if its on cooldown
+    pub fn remaining(&self, channel: &str, command: &str, user: Option<&str>) -> Option<Duration> {
+        let now = Instant::now();
+        let state = self.state.borrow();
+
+        let global = state
+            .commands
+            .get(&(channel.to_string(), command.to_string()));
+
+        let user = user.and_then(|user| {
+            let key = (
+                channel.to_string(),
+                command.to_string(),
+                user.to_lowercase(),
+            );
+            state.users.get(&key)
+        });
+
+        global
+            .into_iter()
+            .chain(user)
+            .max()
+            .and_then(|ready| ready.checked_duration_since(now))
+            .filter(|d| !d.is_zero())
+    }
+
+    pub fn start(
+        &self,
+        channel: &str,
+        command: &str,
+        user: &str,
+        cooldown: Option<Duration>,
+        user_cooldown: Option<Duration>,
+    ) {
+        let now = Instant::now();
+        let mut state = self.state.borrow_mut();
+
+        // drop anything that has already expired, so this doesn't grow forever
+        state.commands.retain(|_, ready| *ready > now);
+        state.users.retain(|_, ready| *ready > now);
+
+        let ready_at = |cooldown: Duration| {
+            let ready = now.checked_add(cooldown);
+            if ready.is_none() {
+                log::warn!("cooldown for {command} is too long, ignoring it: {cooldown:?}");
+            }
+            ready
+        };
+
+        if let Some(ready) = cooldown.and_then(ready_at) {
+            let key = (channel.to_string(), command.to_string());
+            state.commands.insert(key, ready);
+        }
+
+        if let Some(ready) = user_cooldown.and_then(ready_at) {
+            let key = (
+                channel.to_string(),
+                command.to_string(),
+                user.to_lowercase(),
+            );
+            state.users.insert(key, ready);
+        }
+    }
+
+    pub fn reset(&self, channel: &str, command: &str) {
+        let mut state = self.state.borrow_mut();
+        state
+            .commands
+            .retain(|(c, cmd), _| !(c == channel && cmd == command));
+        state
+            .users
+            .retain(|(c, cmd, _), _| !(c == channel && cmd == command));
+        state
+            .notified
+            .retain(|(c, cmd), _| !(c == channel && cmd == command));
+    }
+}
+
+impl UserData for Cooldowns {
+    fn add_methods<M>(methods: &mut M)
+    where
+        M: mlua::UserDataMethods<Self>,
+    {
+        methods.add_method(
+            "remaining",
+            |_lua, this, (channel, command, user): (String, String, Option<String>)| {
+                let remaining = this.remaining(&channel, &command, user.as_deref());
+                Ok(remaining.map(|d| {
+                    let secs = d.as_secs_f64().ceil() as i64;
+                    TimeSpan(::time::Duration::new(secs, 0))
+                }))
+            },
+        );
+
+        methods.add_method("reset", |_lua, this, (channel, command): (String, String)| {
+            this.reset(&channel, &command);
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start() {
+        let cooldowns = Cooldowns::new(&config::Cooldown::default());
+        let cooldown = Duration::from_secs(30);
+        cooldowns.start("#test", "hello", "museun", Some(cooldown), None);
+
+        let remaining = cooldowns.remaining("#test", "hello", None).unwrap();
+        assert!(remaining <= cooldown);
+        assert!(cooldowns.remaining("#other", "hello", None).is_none());
+
+        cooldowns.reset("#test", "hello");
+        assert!(cooldowns.remaining("#test", "hello", None).is_none());
+    }
+
+    #[test]
+    fn too_long() {
+        let cooldowns = Cooldowns::new(&config::Cooldown::default());
+        let long = Duration::from_secs_f64(1e19);
+        cooldowns.start("#test", "hello", "museun", Some(long), Some(Duration::MAX));
+        assert!(cooldowns.remaining("#test", "hello", Some("museun")).is_none());
+    }
+}
@@ -1,48 +1,182 @@
+use std::time::Duration;
+
 use crate::{
-    irc::Message,
-    manifest::handled::Handled,
+    format::FormatTime,
+    fuzzy,
+    irc::{Message, MessageClass},
+    manifest::{
+        budget::{Budget, Failure},
+        cooldown::Cooldowns,
+        handled::Handled,
+        tasks::Tasks,
+    },
     pattern::{Extract, Pattern},
-    Responder,
+    Channels, Invocation, Permissions, Responder,
 };
 
+/// Everything a mapping needs from the manifest to dispatch a message
+pub(crate) struct Context<'a> {
+    pub lua: &'a mlua::Lua,
+    pub responder: &'a Responder,
+    pub permissions: &'a Permissions,
+    pub channels: &'a Channels,
+    pub cooldowns: &'a Cooldowns,
+    pub budget: &'a Budget,
+    pub tasks: &'a Tasks,
+}
+
 #[derive(Debug)]
 pub struct Mapping {
     pub command: String,
+    /// The module (from `init.lua`) this command came from
+    pub module: String,
     pub pattern: Option<Pattern>,
     pub raw_pattern: Option<String>,
     pub help: String,
-    pub elevated: bool,
-    pub handler: mlua::Function,
+    pub access: MessageClass,
+    pub cooldown: Option<Duration>,
+    pub user_cooldown: Option<Duration>,
+    /// A command with subcommands doesn't need its own handler
+    pub handler: Option<mlua::Function>,
+    pub subcommands: Vec<Mapping>,
 }
 
 impl Mapping {
-    fn make_error(&self) -> String {
+    fn syntax(&self, prefix: &str) -> String {
         match &self.raw_pattern {
-            Some(p) => format!("invalid usage. syntax: {} {p}", self.command),
-            None => format!("invalid usage. syntax: {}", self.command),
+            Some(p) => format!("{prefix}{} {p}", self.command),
+            None => format!("{prefix}{}", self.command),
         }
     }
 
-    pub fn dispatch(&self, msg: &Message, lua: &mlua::Lua, responder: &Responder, sink: &mut bool) {
-        let Some(data) = msg.data.strip_prefix(&self.command) else {
+    fn make_error(&self, prefix: &str) -> String {
+        format!("invalid usage. syntax: {}", self.syntax(prefix))
+    }
+
+    /// The syntax for this command, and each of its subcommands
+    pub fn usages(&self) -> Vec<String> {
+        self.handler
+            .as_ref()
+            .map(|_| self.syntax(""))
+            .into_iter()
+            .chain(self.subcommands.iter().flat_map(Self::usages))
+            .collect()
+    }
+
+    pub fn usage(&self, prefix: &str) -> String {
+        self.usages()
+            .iter()
+            .map(|usage| format!("{prefix}{usage}"))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    // subcommands are stored with their parent's name, e.g. `!greeting add`
+    fn name(&self) -> &str {
+        self.command
+            .rsplit_once(' ')
+            .map_or(&*self.command, |(_, name)| name)
+    }
+
+    pub fn is_allowed(&self, msg: &Message, permissions: &Permissions) -> bool {
+        permissions
+            .check(&self.command, &msg.sender)
+            .unwrap_or(msg.class >= self.access)
+    }
+
+    pub(crate) fn dispatch(
+        &self,
+        msg: &Message,
+        invocation: &Invocation<'_>,
+        ctx: &Context<'_>,
+        sink: &mut bool,
+    ) {
+        if !invocation.name.eq_ignore_ascii_case(&self.command) {
+            return;
+        }
+        self.invoke(msg, invocation.prefix, invocation.args, ctx, sink)
+    }
+
+    fn invoke(
+        &self,
+        msg: &Message,
+        prefix: &str,
+        data: &str,
+        ctx: &Context<'_>,
+        sink: &mut bool,
+    ) {
+        let Context {
+            lua,
+            responder,
+            permissions,
+            channels,
+            cooldowns,
+            budget,
+            tasks,
+        } = *ctx;
+
+        if !channels.is_enabled(&msg.channel, self) {
+            return;
+        }
+
+        if !self.subcommands.is_empty() {
+            let (head, tail) = data
+                .split_once(char::is_whitespace)
+                .unwrap_or((data, ""));
+
+            if let Some(sub) = self.subcommands.iter().find(|sub| sub.name() == head) {
+                return sub.invoke(msg, prefix, tail.trim(), ctx, sink);
+            }
+
+            // without a handler of its own, this command is just its subcommands
+            if self.handler.is_none() {
+                let names = self
+                    .subcommands
+                    .iter()
+                    .map(|sub| sub.name().to_string())
+                    .collect::<Vec<_>>();
+
+                let out = match fuzzy::closest(head, &names, true) {
+                    Some(name) if !head.is_empty() => format!(
+                        "unknown subcommand '{head}', did you mean '{name}'? usage: {usage}",
+                        usage = self.usage(prefix)
+                    ),
+                    _ => format!("usage: {usage}", usage = self.usage(prefix)),
+                };
+                responder.reply(msg, out);
+                *sink = true;
+                return;
+            }
+        }
+
+        let Some(handler) = &self.handler else {
             return;
         };
 
-        let data = data.trim();
         let value = match &self.pattern {
             Some(pat) if pat.is_optional() && data.is_empty() => {
-                responder.reply(msg, self.make_error());
+                responder.reply(msg, self.make_error(prefix));
                 return;
             }
 
             None if !data.is_empty() => {
-                responder.reply(msg, self.make_error());
+                responder.reply(msg, self.make_error(prefix));
                 return;
             }
 
             Some(pat) => match pat.extract(data) {
                 Extract::NoMatch => {
-                    responder.reply(msg, self.make_error());
+                    responder.reply(msg, self.make_error(prefix));
+                    return;
+                }
+                Extract::Invalid { name, ty, value } => {
+                    responder.reply(
+                        msg,
+                        format!(
+                            "invalid <{name}>: '{value}' is not {ty}. syntax: {syntax}",
+                            syntax = self.syntax(prefix)
+                        ),
+                    );
                     return;
                 }
                 Extract::Match => mlua::Value::Nil,
@@ -51,32 +185,86 @@ impl Mapping {
             None => mlua::Value::Nil,
         };
 
-        // TODO PartialOrd so we can see if this message matches what they
-        // specified as the minimum access level
-        if self.elevated && !msg.is_elevated() {
+        if !self.is_allowed(msg, permissions) {
             responder.reply(msg, "you cannot do that command".to_string());
             return;
         }
 
-        let err = match self.handler.call::<Option<Handled>>((msg, value)) {
-            Ok(res) => {
-                *sink = matches!(res, Some(Handled::Sink));
+        // a bypassed cooldown isn't started either, so it doesn't hold back everyone else
+        if !cooldowns.can_bypass(msg.class) {
+            if let Some(remaining) =
+                cooldowns.remaining(&msg.channel, &self.command, Some(&msg.sender))
+            {
+                if cooldowns.notify(&msg.channel, &self.command, remaining) {
+                    let remaining = Duration::from_secs(remaining.as_secs_f64().ceil() as u64);
+                    responder.reply(
+                        msg,
+                        format!(
+                            "{prefix}{command} is on cooldown for {time}",
+                            command = self.command,
+                            time = remaining.as_readable_time()
+                        ),
+                    );
+                }
+                *sink = true;
                 return;
             }
-            Err(err) => err,
+
+            cooldowns.start(
+                &msg.channel,
+                &self.command,
+                &msg.sender,
+                channels
+                    .cooldown(&msg.channel, &self.command)
+                    .or(self.cooldown),
+                self.user_cooldown,
+            );
+        }
+
+        let report = {
+            let (responder, msg) = (responder.clone(), msg.clone());
+            let (command, notify) = (self.command.clone(), budget.notify());
+            move |failure| report_failure(&responder, &msg, &command, notify, failure)
         };
 
-        if let Some(err) = err.to_string().lines().nth(0).and_then(|c| {
-            c.split_terminator(": ").find(|c| {
-                !(c.contains("runtime error") || c.contains("./scripts") || c.contains("src"))
-            })
-        }) {
-            responder.error(msg, err.to_string());
+        // a handler that's waiting on a request can't stop the other commands from running
+        let handled = tasks.call::<Option<Handled>>(
+            lua,
+            budget,
+            &self.command,
+            handler,
+            (msg, value),
+            report,
+        );
+        *sink = matches!(handled, Some(Some(Handled::Sink)));
+    }
+}
+
+fn report_failure(
+    responder: &Responder,
+    msg: &Message,
+    command: &str,
+    notify: bool,
+    failure: Failure,
+) {
+    let err = match failure {
+        Failure::Disabled => return,
+        Failure::Exceeded { reason, disabled } => {
+            if notify {
+                responder.error(msg, Budget::describe(command, reason, disabled));
+            }
+            return;
         }
+        Failure::Lua(err) => err,
+    };
 
-        log::warn!(
-            "cannot call: {command} because {err}",
-            command = self.command
-        )
+    if let Some(err) = err.to_string().lines().nth(0).and_then(|c| {
+        c.split_terminator(": ").find(|c| {
+            !(c.contains("runtime error") || c.contains("./scripts") || c.contains("src"))
+        })
+    }) {
+        responder.error(msg, err.to_string());
     }
+
+    log::warn!("cannot call: {command} because {err}")
 }
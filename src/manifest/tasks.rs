@@ -0,0 +1,525 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    panic::AssertUnwindSafe,
+    rc::Rc,
+};
+
+use mlua::{FromLuaMulti, IntoLuaMulti, UserData};
+
+use crate::{
+    config,
+    manifest::budget::{Budget, Failure},
+};
+
+// wraps a global so the methods that make requests yield while they wait,
+// the handler is resumed with `(true, ...)` or `(false, err)` once it's done
+const PRELUDE: &str = r#"
+local is_pending = ...
+
+local function resolve(ok, ...)
+    if not ok then
+        error((...), 0)
+    end
+    return ...
+end
+
+local function await(...)
+    if is_pending((...)) then
+        return resolve(coroutine.yield((...)))
+    end
+    return ...
+end
+
+return function(global, names)
+    local wrapped = {}
+
+    -- `this` is the wrapper (or the sandbox's view of it) when this is called as a method
+    local function call(f, this, ...)
+        if type(this) == "table" then
+            this = global
+        end
+        return f(this, ...)
+    end
+
+    for _, name in ipairs(names) do
+        local method = global[name]
+        wrapped[name] = function(...)
+            return await(call(method, ...))
+        end
+    end
+
+    return setmetatable(wrapped, {
+        __index = function(_, key)
+            local value = global[key]
+            if type(value) ~= "function" then
+                return value
+            end
+            return function(...)
+                return call(value, ...)
+            end
+        end,
+    })
+end
+"#;
+
+type Job = Box<dyn FnOnce() + Send>;
+type Finish = Box<dyn FnOnce(&mlua::Lua) -> mlua::Result<mlua::MultiValue> + Send>;
+
+/// A request a handler was waiting on has finished
+pub struct Completed {
+    id: u64,
+    finish: Finish,
+}
+
+impl std::fmt::Debug for Completed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Completed").field("id", &self.id).finish()
+    }
+}
+
+// what a handler yields when it's waiting on a request
+struct Pending {
+    id: u64,
+}
+
+impl UserData for Pending {}
+
+struct Waiting {
+    name: String,
+    thread: mlua::Thread,
+    report: Box<dyn FnOnce(Failure)>,
+    // the event depth it was running at
+    depth: u32,
+}
+
+enum Step {
+    Finished(mlua::MultiValue),
+    Waiting(u64),
+    Failed(Failure),
+}
+
+struct Inner {
+    jobs: flume::Sender<Job>,
+    completed_tx: flume::Sender<Completed>,
+    completed: flume::Receiver<Completed>,
+    next_id: Cell<u64>,
+    in_handler: Cell<bool>,
+    waiting: RefCell<HashMap<u64, Waiting>>,
+    depth: Rc<Cell<u32>>,
+}
+
+/// Handlers run as coroutines, so they can wait on requests without blocking the main loop
+///
+/// Requests are made on a pool of worker threads, and the handler is resumed on the main loop
+/// once its request has finished
+#[derive(Clone)]
+pub struct Tasks {
+    inner: Rc<Inner>,
+}
+
+impl std::fmt::Debug for Tasks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tasks")
+            .field("waiting", &self.inner.waiting.borrow().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Tasks {
+    pub fn new(lua: &mlua::Lua, limits: &config::Limits) -> Self {
+        let (jobs, rx) = flume::unbounded::<Job>();
+        for _ in 0..limits.workers.max(1) {
+            let rx = rx.clone();
+            std::thread::spawn(move || {
+                for job in rx.iter() {
+                    job()
+                }
+            });
+        }
+
+        let (completed_tx, completed) = flume::unbounded();
+        let this = Self {
+            inner: Rc::new(Inner {
+                jobs,
+                completed_tx,
+                completed,
+                next_id: Cell::new(0),
+                in_handler: Cell::new(false),
+                waiting: RefCell::default(),
+                depth: Rc::default(),
+            }),
+        };
+        lua.set_app_data(this.clone());
+        this
+    }
+
+    pub fn completed(&self) -> flume::Receiver<Completed> {
+        self.inner.completed.clone()
+    }
+
+    /// How many event handlers led to the handler that's running, see the `events` global
+    ///
+    /// This is kept for handlers while they wait, and restored when they're resumed
+    pub(crate) fn depth(&self) -> Rc<Cell<u32>> {
+        Rc::clone(&self.inner.depth)
+    }
+
+    /// Forgets the handlers that are waiting, their requests still finish but nothing is resumed
+    pub fn clear(&self) {
+        let mut waiting = self.inner.waiting.borrow_mut();
+        if !waiting.is_empty() {
+            log::debug!("dropping {} waiting handlers", waiting.len());
+        }
+        waiting.clear();
+    }
+
+    /// Calls `function` as a coroutine, returning its result if it finished without waiting
+    ///
+    /// `report` is called if it fails, either now or after it was resumed
+    pub fn call<T: FromLuaMulti>(
+        &self,
+        lua: &mlua::Lua,
+        budget: &Budget,
+        name: &str,
+        function: &mlua::Function,
+        args: impl IntoLuaMulti,
+        report: impl FnOnce(Failure) + 'static,
+    ) -> Option<T> {
+        let start = lua
+            .create_thread(function.clone())
+            .and_then(|thread| Ok((thread, args.into_lua_multi(lua)?)));
+        let (thread, args) = match start {
+            Ok(start) => start,
+            Err(err) => {
+                report(Failure::Lua(err));
+                return None;
+            }
+        };
+
+        match self.step(budget, name, &thread, args) {
+            Step::Finished(values) => match T::from_lua_multi(values, lua) {
+                Ok(value) => Some(value),
+                Err(err) => {
+                    report(Failure::Lua(err));
+                    None
+                }
+            },
+            Step::Waiting(id) => {
+                self.park(id, name.to_string(), thread, Box::new(report));
+                None
+            }
+            Step::Failed(failure) => {
+                report(failure);
+                None
+            }
+        }
+    }
+
+    /// Resumes the handler that was waiting on this request
+    pub fn resume(&self, lua: &mlua::Lua, budget: &Budget, completed: Completed) {
+        let Completed { id, finish } = completed;
+        let Some(Waiting {
+            name,
+            thread,
+            report,
+            depth,
+        }) = self.inner.waiting.borrow_mut().remove(&id)
+        else {
+            return;
+        };
+
+        let args = match finish(lua) {
+            Ok(values) => std::iter::once(mlua::Value::Boolean(true))
+                .chain(values)
+                .collect(),
+            Err(err) => mlua::MultiValue::from_iter([
+                mlua::Value::Boolean(false),
+                mlua::Value::Error(Box::new(err)),
+            ]),
+        };
+
+        let previous = self.inner.depth.replace(depth);
+        match self.step(budget, &name, &thread, args) {
+            Step::Finished(..) => {}
+            Step::Waiting(id) => self.park(id, name, thread, report),
+            Step::Failed(failure) => report(failure),
+        }
+        self.inner.depth.set(previous);
+    }
+
+    fn step(
+        &self,
+        budget: &Budget,
+        name: &str,
+        thread: &mlua::Thread,
+        args: mlua::MultiValue,
+    ) -> Step {
+        let previous = self.inner.in_handler.replace(true);
+        let result = budget.resume(name, thread, args);
+        self.inner.in_handler.set(previous);
+
+        let values = match result {
+            Ok(values) => values,
+            Err(failure) => return Step::Failed(failure),
+        };
+
+        if thread.status() != mlua::ThreadStatus::Resumable {
+            return Step::Finished(values);
+        }
+
+        match values.front().and_then(pending_id) {
+            Some(id) => Step::Waiting(id),
+            None => Step::Failed(Failure::Lua(mlua::Error::runtime(
+                "handlers can only yield while they're waiting on a request",
+            ))),
+        }
+    }
+
+    fn park(&self, id: u64, name: String, thread: mlua::Thread, report: Box<dyn FnOnce(Failure)>) {
+        log::trace!("{name} is waiting on request #{id}");
+        let waiting = Waiting {
+            name,
+            thread,
+            report,
+            depth: self.inner.depth.get(),
+        };
+        self.inner.waiting.borrow_mut().insert(id, waiting);
+    }
+}
+
+/// Runs `f` on the worker pool if a handler is calling it, otherwise it's just called
+///
+/// The handler yields (see the prelude) until `f` is done
+pub(crate) fn spawn<T, F>(lua: &mlua::Lua, f: F) -> mlua::Result<mlua::MultiValue>
+where
+    F: FnOnce() -> mlua::Result<T> + Send + 'static,
+    T: IntoLuaMulti + Send + 'static,
+{
+    let tasks = lua
+        .app_data_ref::<Tasks>()
+        .filter(|tasks| tasks.inner.in_handler.get())
+        .map(|tasks| tasks.clone());
+
+    let Some(tasks) = tasks else {
+        return f()?.into_lua_multi(lua);
+    };
+
+    let id = tasks.inner.next_id.get();
+    tasks.inner.next_id.set(id + 1);
+
+    let completed = tasks.inner.completed_tx.clone();
+    let job = Box::new(move || {
+        // if this panicked the handler would be waiting forever
+        let result = std::panic::catch_unwind(AssertUnwindSafe(f))
+            .unwrap_or_else(|_| Err(mlua::Error::runtime("the request panicked")));
+        let finish: Finish = Box::new(move |lua| result?.into_lua_multi(lua));
+        let _ = completed.send(Completed { id, finish });
+    });
+
+    tasks
+        .inner
+        .jobs
+        .send(job)
+        .map_err(|_| mlua::Error::runtime("the worker pool has stopped"))?;
+
+    Pending { id }.into_lua_multi(lua)
+}
+
+/// Wraps `global` so handlers wait on `methods` without blocking the main loop
+pub(crate) fn wrap(
+    lua: &mlua::Lua,
+    global: mlua::Value,
+    methods: &[&str],
+) -> mlua::Result<mlua::Value> {
+    let is_pending = lua.create_function(|_, value: mlua::Value| Ok(pending_id(&value).is_some()))?;
+    let wrap = lua
+        .load(PRELUDE)
+        .set_name("=tasks")
+        .call::<mlua::Function>(is_pending)?;
+    wrap.call((global, methods.to_vec()))
+}
+
+fn pending_id(value: &mlua::Value) -> Option<u64> {
+    let pending = value.as_userdata()?.borrow::<Pending>().ok()?;
+    Some(pending.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    struct Test {
+        lua: mlua::Lua,
+        tasks: Tasks,
+        budget: Rc<Budget>,
+        failures: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Test {
+        fn new() -> Self {
+            let lua = mlua::Lua::new();
+            let limits = config::Limits::default();
+            let tasks = Tasks::new(&lua, &limits);
+            let budget = Rc::new(Budget::new(&lua, &limits).unwrap());
+
+            let global = lua.create_table().unwrap();
+            let double = lua
+                .create_function(|lua, (_, n): (mlua::Value, i64)| spawn(lua, move || Ok(n * 2)))
+                .unwrap();
+            let fail = lua
+                .create_function(|lua, _: mlua::Value| {
+                    spawn(lua, || Err::<(), _>(mlua::Error::runtime("nope")))
+                })
+                .unwrap();
+            let panic = lua
+                .create_function(|lua, _: mlua::Value| {
+                    spawn(lua, || -> mlua::Result<()> { panic!("oops") })
+                })
+                .unwrap();
+            global.set("double", double).unwrap();
+            global.set("fail", fail).unwrap();
+            global.set("panic", panic).unwrap();
+
+            let methods = ["double", "fail", "panic"];
+            let wrapped = wrap(&lua, mlua::Value::Table(global), &methods).unwrap();
+            lua.globals().set("test", wrapped).unwrap();
+
+            Self {
+                lua,
+                tasks,
+                budget,
+                failures: Rc::default(),
+            }
+        }
+
+        fn call(&self, code: &str) {
+            let function = self.lua.load(code).eval::<mlua::Function>().unwrap();
+            let failures = Rc::clone(&self.failures);
+            let report = move |failure| failures.borrow_mut().push(format!("{failure:?}"));
+            self.tasks.call::<()>(&self.lua, &self.budget, "test", &function, (), report);
+        }
+
+        fn finish(&self, count: usize) {
+            for _ in 0..count {
+                let completed = self
+                    .tasks
+                    .completed()
+                    .recv_timeout(Duration::from_secs(5))
+                    .unwrap();
+                self.tasks.resume(&self.lua, &self.budget, completed);
+            }
+        }
+
+        fn global<T: mlua::FromLua>(&self, name: &str) -> T {
+            self.lua.globals().get(name).unwrap()
+        }
+    }
+
+    #[test]
+    fn yield_resume() {
+        let test = Test::new();
+        test.call("return function() result = test:double(21) end");
+        assert_eq!(test.global::<Option<i64>>("result"), None);
+
+        test.finish(1);
+        assert_eq!(test.global::<i64>("result"), 42);
+        assert!(test.failures.borrow().is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let test = Test::new();
+        test.call(
+            "return function() local ok, err = pcall(function() return test:fail() end) \
+            result = tostring(ok) .. ' ' .. tostring(err) end",
+        );
+        test.finish(1);
+        let result = test.global::<String>("result");
+        assert!(result.starts_with("false") && result.contains("nope"), "{result}");
+
+        test.call("return function() test:fail() end");
+        test.finish(1);
+        let failures = test.failures.borrow();
+        assert!(failures.len() == 1 && failures[0].contains("nope"), "{failures:?}");
+    }
+
+    #[test]
+    fn panics() {
+        let test = Test::new();
+        test.call("return function() test:panic() end");
+        test.finish(1);
+
+        {
+            let failures = test.failures.borrow();
+            assert!(failures.len() == 1 && failures[0].contains("panicked"), "{failures:?}");
+        }
+
+        // the worker is still running
+        test.call("return function() result = test:double(1) end");
+        test.finish(1);
+        assert_eq!(test.global::<i64>("result"), 2);
+    }
+
+    #[test]
+    fn reentrant() {
+        let test = Test::new();
+
+        // a handler that runs another handler, which waits on its own request
+        let nested = {
+            let (tasks, budget) = (test.tasks.clone(), Rc::clone(&test.budget));
+            test.lua
+                .create_function(move |lua, function: mlua::Function| {
+                    tasks.call::<()>(lua, &budget, "inner", &function, (), |_| {});
+                    Ok(())
+                })
+                .unwrap()
+        };
+        test.lua.globals().set("nested", nested).unwrap();
+
+        test.call(
+            "return function() \
+                nested(function() inner = test:double(1) end) \
+                outer = test:double(2) \
+            end",
+        );
+        test.finish(2);
+        assert_eq!(test.global::<i64>("inner"), 2);
+        assert_eq!(test.global::<i64>("outer"), 4);
+
+        // outside of a handler requests are made right away
+        assert!(!test.tasks.inner.in_handler.get());
+        let value = test.lua.load("return test:double(5)").eval::<i64>().unwrap();
+        assert_eq!(value, 10);
+    }
+
+    #[test]
+    fn depth() {
+        let test = Test::new();
+        let depth = test.tasks.depth();
+        let current = {
+            let depth = Rc::clone(&depth);
+            test.lua.create_function(move |_lua, ()| Ok(depth.get())).unwrap()
+        };
+        test.lua.globals().set("depth", current).unwrap();
+
+        depth.set(3);
+        test.call("return function() test:double(1) result = depth() end");
+        depth.set(0);
+
+        test.finish(1);
+        assert_eq!(test.global::<u32>("result"), 3);
+        assert_eq!(depth.get(), 0);
+    }
+
+    #[test]
+    fn clear() {
+        let test = Test::new();
+        test.call("return function() result = test:double(1) end");
+        test.tasks.clear();
+        test.finish(1);
+        assert_eq!(test.global::<Option<i64>>("result"), None);
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+    time::{Duration, Instant},
+};
+
+use mlua::{FromLua, UserData};
+
+use crate::{
+    manifest::{
+        budget::{Budget, Failure},
+        tasks::Tasks,
+    },
+    GlobalItem,
+};
+
+#[derive(Debug)]
+struct Task {
+    id: u64,
+    at: Instant,
+    every: Option<Duration>,
+    gate: Option<Gate>,
+    handler: mlua::Function,
+}
+
+// only run if enough messages were sent since the last run
+#[derive(Debug)]
+struct Gate {
+    messages: usize,
+    channel: Option<String>,
+    seen: usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: u64,
+    tasks: Vec<Task>,
+    total: usize,
+    channels: HashMap<String, usize>,
+}
+
+impl State {
+    fn messages(&self, channel: Option<&str>) -> usize {
+        match channel {
+            Some(channel) => self.channels.get(channel).copied().unwrap_or(0),
+            None => self.total,
+        }
+    }
+
+    fn schedule(
+        &mut self,
+        after: Duration,
+        every: Option<Duration>,
+        gate: Option<Gate>,
+        handler: mlua::Function,
+    ) -> mlua::Result<u64> {
+        let at = Instant::now().checked_add(after).ok_or_else(|| {
+            mlua::Error::runtime(format!("a timer duration is too long: {after:?}"))
+        })?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tasks.push(Task {
+            id,
+            at,
+            every,
+            gate,
+            handler,
+        });
+        Ok(id)
+    }
+}
+
+/// Callbacks scripts want to run later, or periodically
+///
+/// These are run on the main loop, and are all cancelled when the manifest is reloaded
+#[derive(Clone, Debug, Default)]
+pub struct Timers {
+    state: Rc<RefCell<State>>,
+}
+
+impl GlobalItem for Timers {
+    const MODULE: &'static str = "timer";
+}
+
+impl Timers {
+    pub fn clear(&self) {
+        let mut state = self.state.borrow_mut();
+        if !state.tasks.is_empty() {
+            log::debug!("cancelling {} timers", state.tasks.len());
+        }
+        state.tasks.clear();
+    }
+
+    pub fn record_message(&self, channel: &str) {
+        let mut state = self.state.borrow_mut();
+        state.total += 1;
+        *state.channels.entry(channel.to_string()).or_default() += 1;
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.state.borrow().tasks.iter().map(|task| task.at).min()
+    }
+
+    pub fn run_due(&self, lua: &mlua::Lua, budget: &Budget, tasks: &Tasks) {
+        let now = Instant::now();
+
+        let due = {
+            let mut state = self.state.borrow_mut();
+            let State {
+                tasks,
+                total,
+                channels,
+                ..
+            } = &mut *state;
+
+            let mut due = vec![];
+            tasks.retain_mut(|task| {
+                if task.at > now {
+                    return true;
+                }
+
+                let ready = match &mut task.gate {
+                    Some(gate) => {
+                        let seen = match &gate.channel {
+                            Some(channel) => channels.get(channel).copied().unwrap_or(0),
+                            None => *total,
+                        };
+                        let ready = seen - gate.seen >= gate.messages;
+                        if ready {
+                            gate.seen = seen;
+                        }
+                        ready
+                    }
+                    None => true,
+                };
+
+                if ready {
+                    due.push((task.id, task.handler.clone()));
+                }
+
+                match task.every.and_then(|every| now.checked_add(every)) {
+                    Some(at) => {
+                        task.at = at;
+                        true
+                    }
+                    None => false,
+                }
+            });
+            due
+        };
+
+        // the borrow has to be released here, the callbacks can schedule more timers
+        for (id, handler) in due {
+            let handle = Handle {
+                id,
+                state: Rc::downgrade(&self.state),
+            };
+            let name = format!("timer #{id}");
+            let report = {
+                let name = name.clone();
+                move |failure| {
+                    if let Failure::Lua(err) = failure {
+                        log::warn!("cannot call {name} because: {err}");
+                    }
+                }
+            };
+            tasks.call::<()>(lua, budget, &name, &handler, handle, report);
+        }
+    }
+}
+
+impl UserData for Timers {
+    fn add_methods<M>(methods: &mut M)
+    where
+        M: mlua::UserDataMethods<Self>,
+    {
+        methods.add_method(
+            "every",
+            |_lua, this, (secs, handler, opts): (f64, mlua::Function, Option<Options>)| {
+                let every = seconds(secs)?;
+                let mut state = this.state.borrow_mut();
+
+                let gate = opts.and_then(|opts| {
+                    let messages = opts.min_messages?;
+                    let seen = state.messages(opts.channel.as_deref());
+                    Some(Gate {
+                        messages,
+                        channel: opts.channel,
+                        seen,
+                    })
+                });
+
+                let id = state.schedule(every, Some(every), gate, handler)?;
+                Ok(Handle {
+                    id,
+                    state: Rc::downgrade(&this.state),
+                })
+            },
+        );
+
+        methods.add_method("after", |_lua, this, (secs, handler): (f64, mlua::Function)| {
+            let after = seconds(secs)?;
+            let id = this.state.borrow_mut().schedule(after, None, None, handler)?;
+            Ok(Handle {
+                id,
+                state: Rc::downgrade(&this.state),
+            })
+        });
+    }
+}
+
+struct Options {
+    min_messages: Option<usize>,
+    channel: Option<String>,
+}
+
+impl FromLua for Options {
+    fn from_lua(value: mlua::Value, _lua: &mlua::Lua) -> mlua::Result<Self> {
+        let table = value
+            .as_table()
+            .ok_or_else(|| mlua::Error::runtime("timer options must be a table"))?;
+        Ok(Self {
+            min_messages: table.get("min_messages")?,
+            channel: table.get("channel")?,
+        })
+    }
+}
+
+/// A handle to a scheduled timer
+#[derive(Clone)]
+pub struct Handle {
+    id: u64,
+    state: Weak<RefCell<State>>,
+}
+
+impl Handle {
+    fn with_tasks<T>(&self, f: impl FnOnce(&mut Vec<Task>) -> T) -> Option<T> {
+        let state = self.state.upgrade()?;
+        let mut state = state.borrow_mut();
+        Some(f(&mut state.tasks))
+    }
+}
+
+impl UserData for Handle {
+    fn add_methods<M>(methods: &mut M)
+    where
+        M: mlua::UserDataMethods<Self>,
+    {
+        methods.add_method("cancel", |_lua, this, ()| {
+            let removed = this.with_tasks(|tasks| {
+                let len = tasks.len();
+                tasks.retain(|task| task.id != this.id);
+                len != tasks.len()
+            });
+            Ok(removed.unwrap_or(false))
+        });
+
+        methods.add_method("is_active", |_lua, this, ()| {
+            let active = this.with_tasks(|tasks| tasks.iter().any(|task| task.id == this.id));
+            Ok(active.unwrap_or(false))
+        });
+    }
+}
+
+fn seconds(secs: f64) -> mlua::Result<Duration> {
+    match Duration::try_from_secs_f64(secs) {
+        Ok(duration) if !duration.is_zero() => Ok(duration),
+        _ => Err(mlua::Error::runtime(format!(
+            "a timer duration must be a positive number of seconds, got: {secs}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lua() -> (mlua::Lua, Timers) {
+        let lua = mlua::Lua::new();
+        let timers = Timers::default();
+        lua.globals().set("timer", timers.clone()).unwrap();
+        (lua, timers)
+    }
+
+    #[test]
+    fn too_long() {
+        let (lua, timers) = lua();
+        for code in [
+            "timer:after(1e19, function() end)",
+            "timer:every(1e19, function() end)",
+            "timer:after(1e300, function() end)",
+            "timer:every(0, function() end)",
+            "timer:after(-1, function() end)",
+        ] {
+            assert!(lua.load(code).exec().is_err(), "{code} should fail");
+        }
+        assert!(timers.next_deadline().is_none());
+
+        lua.load("timer:after(1, function() end)").exec().unwrap();
+        assert!(timers.next_deadline().is_some());
+    }
+
+    #[test]
+    fn cancel() {
+        let (lua, timers) = lua();
+        let code = r#"
+            local handle = timer:every(60, function() end)
+            handle:cancel()
+            return handle:is_active()
+        "#;
+        let active: bool = lua.load(code).eval().unwrap();
+        assert!(!active);
+        assert!(timers.next_deadline().is_none());
+    }
+}
@@ -1,20 +1,27 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+use mlua::IntoLua;
+
+use crate::time::TimeSpan;
 
 #[derive(Debug, Clone)]
 pub enum Part {
     Exact(String),
-    Argument(String),
-    Optional(String),
-    Variadic(String),
+    Argument(String, Type),
+    Optional(String, Type),
+    Variadic(String, Type),
 }
 
 impl Part {
     fn name(&self) -> &str {
         match self {
             Self::Exact(name)
-            | Self::Argument(name)
-            | Self::Optional(name)
-            | Self::Variadic(name) => name,
+            | Self::Argument(name, ..)
+            | Self::Optional(name, ..)
+            | Self::Variadic(name, ..) => name,
         }
     }
 
@@ -23,6 +30,111 @@ impl Part {
     }
 }
 
+/// The type of an argument, e.g. `<count:int>`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    String,
+    Int,
+    Choice(Vec<String>),
+    User,
+    Url,
+    Duration,
+}
+
+impl Type {
+    fn parse(input: &str) -> Option<Self> {
+        Some(match input {
+            "string" => Self::String,
+            "int" => Self::Int,
+            "@user" => Self::User,
+            "url" => Self::Url,
+            "duration" => Self::Duration,
+            choices if choices.contains('|') => {
+                let choices = choices.split('|').map(str::to_string).collect::<Vec<_>>();
+                if choices.iter().any(|c| c.is_empty()) {
+                    return None;
+                }
+                Self::Choice(choices)
+            }
+            _ => return None,
+        })
+    }
+
+    fn convert<'a>(&self, input: Cow<'a, str>) -> Option<Value<'a>> {
+        Some(match self {
+            Self::String => Value::String(input),
+            Self::Int => Value::Int(input.parse().ok()?),
+            Self::Choice(choices) => {
+                let choice = choices.iter().find(|c| c.eq_ignore_ascii_case(&input))?;
+                Value::String(Cow::Owned(choice.clone()))
+            }
+            Self::User => {
+                let name = input.strip_prefix('@').unwrap_or(&input);
+                let valid = (1..=25).contains(&name.len())
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if !valid {
+                    return None;
+                }
+                Value::String(Cow::Owned(name.to_lowercase()))
+            }
+            Self::Url => {
+                let url = url::Url::parse(&input).ok()?;
+                if !matches!(url.scheme(), "http" | "https") {
+                    return None;
+                }
+                Value::String(input)
+            }
+            Self::Duration => Value::Duration(parse_duration(&input)?),
+        })
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::String => f.write_str("a string"),
+            Self::Int => f.write_str("a whole number"),
+            Self::Choice(choices) => write!(f, "one of {}", choices.join("|")),
+            Self::User => f.write_str("a user name"),
+            Self::Url => f.write_str("a url"),
+            Self::Duration => f.write_str("a duration (e.g. 90s, 5m or 1h30m)"),
+        }
+    }
+}
+
+// either plain seconds, or a sequence of `<n><unit>` where unit is one of `d`, `h`, `m` or `s`
+fn parse_duration(input: &str) -> Option<::time::Duration> {
+    if let Ok(secs) = input.parse::<u32>() {
+        return Some(::time::Duration::seconds(secs as i64));
+    }
+
+    let mut total = 0_i64;
+    let mut rest = input;
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| !c.is_ascii_digit())?;
+        if end == 0 {
+            return None;
+        }
+        let n = rest[..end].parse::<i64>().ok()?;
+        let unit = rest[end..].chars().next()?;
+        let scale = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 60 * 60 * 24,
+            _ => return None,
+        };
+        total = total.checked_add(n.checked_mul(scale)?)?;
+        rest = &rest[end + unit.len_utf8()..];
+    }
+
+    Some(::time::Duration::seconds(total))
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 enum Kind {
     None,
@@ -30,7 +142,7 @@ enum Kind {
     Variadic,
 }
 
-fn lex(input: &mut &str) -> Vec<Part> {
+fn lex(input: &mut &str) -> Result<Vec<Part>, PatternError> {
     fn try_merge(out: Option<&mut Part>, current: Part) -> Option<Part> {
         if let Some(last) = out.and_then(|c| {
             let Part::Exact(last) = c else { return None };
@@ -49,13 +161,13 @@ fn lex(input: &mut &str) -> Vec<Part> {
     loop {
         match unfold(input) {
             Some(head) => {
-                if let Some(part) = classify(head) {
+                if let Some(part) = classify(head)? {
                     let value = try_merge(out.last_mut(), part);
                     out.extend(value);
                 }
             }
             None => {
-                let value = classify(input).and_then(|part| {
+                let value = classify(input)?.and_then(|part| {
                     try_merge(out.last_mut(), part) //
                 });
                 out.extend(value);
@@ -65,37 +177,52 @@ fn lex(input: &mut &str) -> Vec<Part> {
         }
     }
 
-    out
+    Ok(out)
 }
 
-fn classify(head: &str) -> Option<Part> {
+fn classify(head: &str) -> Result<Option<Part>, PatternError> {
     fn is_surround(input: &str, head: &str, tail: &str) -> bool {
         input.starts_with(head) && input.ends_with(tail)
     }
 
     let (n, kind) = match () {
-        _ if head.trim().is_empty() => return None,
+        _ if head.trim().is_empty() => return Ok(None),
         _ if is_surround(head, "<", "...>") => (4, Kind::Variadic),
         _ if is_surround(head, "<", "?>") => (2, Kind::Optional),
         _ if is_surround(head, "<", ">") => (1, Kind::None),
-        _ => return Some(Part::Exact(head.to_string())),
+        _ => return Ok(Some(Part::Exact(head.to_string()))),
     };
     classify_part(head, n, kind)
 }
 
-fn classify_part(arg: &str, tail: usize, kind: Kind) -> Option<Part> {
+fn classify_part(arg: &str, tail: usize, kind: Kind) -> Result<Option<Part>, PatternError> {
     if arg.contains(char::is_whitespace) {
-        return Some(Part::Exact(arg.to_string()));
+        return Ok(Some(Part::Exact(arg.to_string())));
     }
     let arg = &arg[1..arg.len() - tail];
     if arg.is_empty() {
-        return None;
+        return Ok(None);
     }
-    Some(match kind {
-        Kind::None => Part::Argument(arg.to_string()),
-        Kind::Optional => Part::Optional(arg.to_string()),
-        Kind::Variadic => Part::Variadic(arg.to_string()),
-    })
+
+    let (name, ty) = match arg.split_once(':') {
+        Some((name, ty)) => match Type::parse(ty) {
+            Some(ty) => (name, ty),
+            None => {
+                return Err(PatternError::UnknownType {
+                    name: name.to_string(),
+                    ty: ty.to_string(),
+                })
+            }
+        },
+        None => (arg, Type::String),
+    };
+
+    let name = name.to_string();
+    Ok(Some(match kind {
+        Kind::None => Part::Argument(name, ty),
+        Kind::Optional => Part::Optional(name, ty),
+        Kind::Variadic => Part::Variadic(name, ty),
+    }))
 }
 
 fn unfold<'a>(input: &mut &'a str) -> Option<&'a str> {
@@ -115,6 +242,56 @@ fn unfold<'a>(input: &mut &'a str) -> Option<&'a str> {
     Some(head)
 }
 
+// splits the input on whitespace, a double-quoted string is a single token
+fn tokenize(input: &str) -> Vec<Cow<'_, str>> {
+    let mut out = vec![];
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        // an unterminated quote is just part of the word
+        let (token, tail) = match rest.strip_prefix('"').and_then(quoted) {
+            Some((token, tail)) => (token, tail),
+            None => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                (Cow::Borrowed(&rest[..end]), &rest[end..])
+            }
+        };
+        out.push(token);
+        rest = tail.trim_start();
+    }
+
+    out
+}
+
+// the contents of a quoted string (after the opening quote) and whatever follows the closing quote
+fn quoted(input: &str) -> Option<(Cow<'_, str>, &str)> {
+    // only allocate if there are escapes
+    let mut owned = <Option<String>>::None;
+
+    let mut iter = input.char_indices();
+    while let Some((i, ch)) = iter.next() {
+        match ch {
+            '"' => {
+                let token = owned.map_or(Cow::Borrowed(&input[..i]), Cow::Owned);
+                return Some((token, &input[i + 1..]));
+            }
+            '\\' => {
+                let (_, escaped) = iter.next()?;
+                owned
+                    .get_or_insert_with(|| input[..i].to_string())
+                    .push(escaped);
+            }
+            ch => {
+                if let Some(owned) = &mut owned {
+                    owned.push(ch)
+                }
+            }
+        }
+    }
+
+    None
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum PatternError {
     #[error("Duplicate name: {name}")]
@@ -125,6 +302,9 @@ pub enum PatternError {
 
     #[error("Ambigious optional '{optional}' may overlap with '{pattern}'")]
     AmbigiousOptional { optional: String, pattern: String },
+
+    #[error("Unknown type '{ty}' for '{name}'")]
+    UnknownType { name: String, ty: String },
 }
 
 #[derive(Debug)]
@@ -135,7 +315,7 @@ pub enum Pattern {
 
 impl Pattern {
     pub fn parse(mut input: &str) -> Result<Self, PatternError> {
-        let mut args = lex(&mut input);
+        let mut args = lex(&mut input)?;
         let [Part::Exact(ref mut part)] = &mut args[..] else {
             #[derive(Copy, Clone, Default, Debug, PartialEq)]
             enum State {
@@ -152,7 +332,7 @@ impl Pattern {
             for part in &args {
                 match part {
                     Part::Exact(_) => state = State::None,
-                    Part::Argument(binding) => {
+                    Part::Argument(binding, ..) => {
                         if !seen.insert(binding) {
                             return Err(PatternError::DuplicateName {
                                 name: binding.to_string(),
@@ -178,7 +358,7 @@ impl Pattern {
                         state = State::Argument;
                         prev = Some(binding);
                     }
-                    Part::Optional(binding) => {
+                    Part::Optional(binding, ..) => {
                         if !seen.insert(binding) {
                             return Err(PatternError::DuplicateName {
                                 name: binding.to_string(),
@@ -193,7 +373,7 @@ impl Pattern {
                         state = State::Optional;
                         prev = Some(binding)
                     }
-                    Part::Variadic(binding) => {
+                    Part::Variadic(binding, ..) => {
                         if !seen.insert(binding) {
                             return Err(PatternError::DuplicateName {
                                 name: binding.to_string(),
@@ -218,65 +398,71 @@ impl Pattern {
         Ok(Self::Exact(std::mem::take(part)))
     }
 
-    pub fn extract<'a, 'b>(&'a self, mut input: &'b str) -> Extract<'a, 'b> {
+    pub fn extract<'a, 'b>(&'a self, input: &'b str) -> Extract<'a, 'b> {
         let args = match self {
             Self::Exact(data) if data == input => return Extract::Match,
             Self::Exact(..) => return Extract::NoMatch,
             Self::Arguments(args) => args,
         };
 
-        let data = &mut input;
+        let mut tokens = tokenize(input).into_iter().peekable();
         let mut map = HashMap::default();
 
         let mut iter = args.iter().peekable();
         while let Some(arg) = iter.next() {
             match arg {
                 Part::Exact(pat) => {
-                    let Some(tail) = data.strip_prefix(&**pat) else {
-                        return Extract::NoMatch;
-                    };
-                    *data = tail.trim();
+                    for word in pat.split_whitespace() {
+                        if tokens.next().as_deref() != Some(word) {
+                            return Extract::NoMatch;
+                        }
+                    }
                 }
-                Part::Argument(pat) => {
-                    let Some(value) = data.split_terminator(' ').next() else {
+                Part::Argument(pat, ty) => {
+                    let Some(value) = tokens.next() else {
                         return Extract::NoMatch;
                     };
-                    map.insert(&**pat, Value::String(value));
-                    *data = data
-                        .strip_prefix(value)
-                        .expect("strip value from pat::arg")
-                        .trim();
+                    let Some(converted) = ty.convert(value.clone()) else {
+                        return Extract::Invalid { name: pat, ty, value };
+                    };
+                    map.insert(&**pat, converted);
                 }
-                Part::Optional(pat) => {
-                    if let Some(value) = data.split_terminator(' ').next() {
-                        map.insert(&**pat, Value::String(value));
-                        *data = data
-                            .strip_prefix(value)
-                            .expect("strip value from pat::arg")
-                            .trim()
+                Part::Optional(pat, ty) => {
+                    // an optional can only be followed by an exact part, which it leaves alone
+                    let next = iter
+                        .peek()
+                        .and_then(|c| c.name().split_whitespace().next());
+
+                    if let Some(value) = tokens.next_if(|t| Some(&**t) != next) {
+                        let Some(converted) = ty.convert(value.clone()) else {
+                            return Extract::Invalid { name: pat, ty, value };
+                        };
+                        map.insert(&**pat, converted);
                     }
                 }
-                Part::Variadic(pat) => {
-                    let next = iter.peek().map(|c| c.name());
-
-                    let mut out = vec![];
-                    for part in data.split_terminator(' ').filter(|s| !s.is_empty()) {
-                        if Some(part) == next {
-                            break;
-                        }
-                        out.push(part);
+                Part::Variadic(pat, ty) => {
+                    // a variadic can only be followed by an exact part
+                    let next = iter
+                        .peek()
+                        .and_then(|c| c.name().split_whitespace().next());
+
+                    let mut values = vec![];
+                    while let Some(value) = tokens.next_if(|t| Some(&**t) != next) {
+                        let Some(converted) = ty.convert(value.clone()) else {
+                            return Extract::Invalid { name: pat, ty, value };
+                        };
+                        values.push(converted);
                     }
-
-                    let offset = out
-                        .iter()
-                        .fold(out.len(), |n, t| n + t.len())
-                        .min(data.len());
-                    *data = data[offset..].trim();
-                    map.insert(&**pat, Value::List(out));
+                    map.insert(&**pat, Value::List(values));
                 }
             }
         }
 
+        // anything left over doesn't fit the pattern
+        if tokens.next().is_some() {
+            return Extract::NoMatch;
+        }
+
         Extract::Bindings { map }
     }
 
@@ -294,24 +480,364 @@ impl Pattern {
 pub enum Extract<'a, 'b> {
     NoMatch,
     Match,
-    Bindings { map: HashMap<&'a str, Value<'b>> },
+    /// An argument was found, but it couldn't be converted to its type
+    Invalid {
+        name: &'a str,
+        ty: &'a Type,
+        value: Cow<'b, str>,
+    },
+    Bindings {
+        map: HashMap<&'a str, Value<'b>>,
+    },
 }
 
 impl Extract<'_, '_> {
     pub fn map_to_lua(map: HashMap<&str, Value<'_>>, lua: &mlua::Lua) -> mlua::Value {
         let table = lua.create_table().unwrap();
         for (k, v) in map {
-            match v {
-                Value::String(s) => table.set(k, s).unwrap(),
-                Value::List(v) => table.set(k, v.as_slice()).unwrap(),
-            }
+            table.set(k, v).unwrap()
         }
         mlua::Value::Table(table)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Value<'a> {
-    String(&'a str),
-    List(Vec<&'a str>),
+    String(Cow<'a, str>),
+    Int(i64),
+    Duration(::time::Duration),
+    List(Vec<Value<'a>>),
+}
+
+impl IntoLua for Value<'_> {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        match self {
+            Self::String(s) => s.into_lua(lua),
+            Self::Int(n) => n.into_lua(lua),
+            Self::Duration(d) => TimeSpan(d).into_lua(lua),
+            Self::List(list) => lua.create_sequence_from(list).map(mlua::Value::Table),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings<'a, 'b>(pattern: &'a Pattern, input: &'b str) -> HashMap<&'a str, Value<'b>> {
+        match pattern.extract(input) {
+            Extract::Bindings { map } => map,
+            extract => panic!("expected bindings for {input:?}, got: {extract:?}"),
+        }
+    }
+
+    fn string(s: &str) -> Value<'_> {
+        Value::String(Cow::Borrowed(s))
+    }
+
+    fn list<'a>(list: &[&'a str]) -> Value<'a> {
+        Value::List(list.iter().copied().map(string).collect())
+    }
+
+    #[test]
+    fn exact() {
+        let pattern = Pattern::parse("on off").unwrap();
+        assert!(matches!(pattern.extract("on off"), Extract::Match));
+        assert!(matches!(pattern.extract("on"), Extract::NoMatch));
+    }
+
+    #[test]
+    fn argument() {
+        let pattern = Pattern::parse("<name>").unwrap();
+        let map = bindings(&pattern, "foo");
+        assert_eq!(map["name"], string("foo"));
+
+        assert!(matches!(pattern.extract(""), Extract::NoMatch));
+    }
+
+    #[test]
+    fn optional() {
+        let pattern = Pattern::parse("<name?>").unwrap();
+        assert_eq!(bindings(&pattern, "foo")["name"], string("foo"));
+        assert!(!bindings(&pattern, "").contains_key("name"));
+    }
+
+    #[test]
+    fn variadic() {
+        let pattern = Pattern::parse("<words...>").unwrap();
+        assert_eq!(bindings(&pattern, "a b c")["words"], list(&["a", "b", "c"]));
+        assert_eq!(bindings(&pattern, "")["words"], list(&[]));
+    }
+
+    #[test]
+    fn exact_argument() {
+        let pattern = Pattern::parse("add <greeting>").unwrap();
+        assert_eq!(bindings(&pattern, "add hello")["greeting"], string("hello"));
+        assert!(matches!(pattern.extract("remove hello"), Extract::NoMatch));
+        assert!(matches!(pattern.extract("addhello"), Extract::NoMatch));
+    }
+
+    #[test]
+    fn argument_exact_argument() {
+        let pattern = Pattern::parse("<src> to <dst>").unwrap();
+        let map = bindings(&pattern, "!so to !shoutout");
+        assert_eq!(map["src"], string("!so"));
+        assert_eq!(map["dst"], string("!shoutout"));
+
+        assert!(matches!(pattern.extract("!so !shoutout"), Extract::NoMatch));
+    }
+
+    #[test]
+    fn argument_argument() {
+        let pattern = Pattern::parse("<a> <b>").unwrap();
+        let map = bindings(&pattern, "1 2");
+        assert_eq!(map["a"], string("1"));
+        assert_eq!(map["b"], string("2"));
+    }
+
+    #[test]
+    fn argument_optional() {
+        assert!(matches!(
+            Pattern::parse("<a> <b?>"),
+            Err(PatternError::AmbigiousOptional { .. })
+        ));
+    }
+
+    #[test]
+    fn exact_optional() {
+        let pattern = Pattern::parse("show <channel?>").unwrap();
+        assert_eq!(bindings(&pattern, "show foo")["channel"], string("foo"));
+        assert!(!bindings(&pattern, "show").contains_key("channel"));
+    }
+
+    #[test]
+    fn optional_argument() {
+        assert!(matches!(
+            Pattern::parse("<a?> <b>"),
+            Err(PatternError::AmbigiousOptional { .. })
+        ));
+    }
+
+    #[test]
+    fn argument_variadic() {
+        let pattern = Pattern::parse("<name> <body...>").unwrap();
+        let map = bindings(&pattern, "!hi hello there");
+        assert_eq!(map["name"], string("!hi"));
+        assert_eq!(map["body"], list(&["hello", "there"]));
+    }
+
+    #[test]
+    fn variadic_exact() {
+        let pattern = Pattern::parse("<words...> to <target>").unwrap();
+        let map = bindings(&pattern, "a b to c");
+        assert_eq!(map["words"], list(&["a", "b"]));
+        assert_eq!(map["target"], string("c"));
+    }
+
+    #[test]
+    fn variadic_argument() {
+        assert!(matches!(
+            Pattern::parse("<a...> <b>"),
+            Err(PatternError::AmbigiousVariadic { .. })
+        ));
+    }
+
+    #[test]
+    fn variadic_optional() {
+        assert!(matches!(
+            Pattern::parse("<a...> <b?>"),
+            Err(PatternError::AmbigiousOptional { .. })
+        ));
+    }
+
+    #[test]
+    fn optional_optional() {
+        assert!(matches!(
+            Pattern::parse("<a?> <b?>"),
+            Err(PatternError::AmbigiousOptional { .. })
+        ));
+    }
+
+    #[test]
+    fn optional_variadic() {
+        assert!(matches!(
+            Pattern::parse("<a?> <b...>"),
+            Err(PatternError::AmbigiousVariadic { .. })
+        ));
+    }
+
+    #[test]
+    fn variadic_variadic() {
+        assert!(matches!(
+            Pattern::parse("<a...> <b...>"),
+            Err(PatternError::AmbigiousVariadic { .. })
+        ));
+    }
+
+    #[test]
+    fn optional_exact() {
+        let pattern = Pattern::parse("<a?> to <b>").unwrap();
+        let map = bindings(&pattern, "x to y");
+        assert_eq!(map["a"], string("x"));
+        assert_eq!(map["b"], string("y"));
+
+        let map = bindings(&pattern, "to y");
+        assert!(!map.contains_key("a"));
+        assert_eq!(map["b"], string("y"));
+
+        assert!(matches!(pattern.extract("x y"), Extract::NoMatch));
+    }
+
+    #[test]
+    fn trailing() {
+        let pattern = Pattern::parse("<a>").unwrap();
+        assert!(matches!(pattern.extract("x y"), Extract::NoMatch));
+
+        let pattern = Pattern::parse("<a?>").unwrap();
+        assert!(matches!(pattern.extract("x y"), Extract::NoMatch));
+
+        let pattern = Pattern::parse("<src> to <dst>").unwrap();
+        assert!(matches!(pattern.extract("a to b c"), Extract::NoMatch));
+
+        let pattern = Pattern::parse("<greeting>").unwrap();
+        assert_eq!(bindings(&pattern, r#""x y""#)["greeting"], string("x y"));
+    }
+
+    #[test]
+    fn duplicate_name() {
+        assert!(matches!(
+            Pattern::parse("<a> <a>"),
+            Err(PatternError::DuplicateName { .. })
+        ));
+    }
+
+    #[test]
+    fn collapses_whitespace() {
+        let pattern = Pattern::parse("<src> to <dst>").unwrap();
+        let map = bindings(&pattern, "  !so    to \t !shoutout  ");
+        assert_eq!(map["src"], string("!so"));
+        assert_eq!(map["dst"], string("!shoutout"));
+    }
+
+    #[test]
+    fn quoted() {
+        let pattern = Pattern::parse("<src> to <dst>").unwrap();
+        let map = bindings(&pattern, r#""!so" to "!shout out""#);
+        assert_eq!(map["src"], string("!so"));
+        assert_eq!(map["dst"], string("!shout out"));
+
+        let pattern = Pattern::parse("add <greeting>").unwrap();
+        let map = bindings(&pattern, r#"add "good  morning""#);
+        assert_eq!(map["greeting"], string("good  morning"));
+    }
+
+    #[test]
+    fn quoted_variadic() {
+        let pattern = Pattern::parse("<words...>").unwrap();
+        let map = bindings(&pattern, r#"a "b c" d"#);
+        assert_eq!(map["words"], list(&["a", "b c", "d"]));
+    }
+
+    #[test]
+    fn escapes() {
+        let pattern = Pattern::parse("<text>").unwrap();
+        let map = bindings(&pattern, r#""say \"hi\" \\o/""#);
+        assert_eq!(map["text"], string(r#"say "hi" \o/"#));
+    }
+
+    #[test]
+    fn empty_quotes() {
+        let pattern = Pattern::parse("<text>").unwrap();
+        assert_eq!(bindings(&pattern, r#""""#)["text"], string(""));
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        let pattern = Pattern::parse("<a> <b>").unwrap();
+        let map = bindings(&pattern, r#""foo bar"#);
+        assert_eq!(map["a"], string(r#""foo"#));
+        assert_eq!(map["b"], string("bar"));
+    }
+
+    #[test]
+    fn quotes_inside_words() {
+        let pattern = Pattern::parse("<words...>").unwrap();
+        let map = bindings(&pattern, r#"it's a "test"#);
+        assert_eq!(map["words"], list(&["it's", "a", r#""test"#]));
+    }
+
+    #[test]
+    fn typed() {
+        let pattern = Pattern::parse("<count:int> <mode:on|off> <user:@user>").unwrap();
+        let map = bindings(&pattern, "3 ON @Museun");
+        assert_eq!(map["count"], Value::Int(3));
+        assert_eq!(map["mode"], string("on"));
+        assert_eq!(map["user"], string("museun"));
+
+        let pattern = Pattern::parse("<when:duration>").unwrap();
+        let map = bindings(&pattern, "1h30m");
+        assert_eq!(map["when"], Value::Duration(::time::Duration::minutes(90)));
+    }
+
+    #[test]
+    fn typed_invalid() {
+        let pattern = Pattern::parse("<count:int>").unwrap();
+        match pattern.extract("three") {
+            Extract::Invalid { name, ty, value } => {
+                assert_eq!(name, "count");
+                assert_eq!(*ty, Type::Int);
+                assert_eq!(value, "three");
+            }
+            extract => panic!("expected invalid, got: {extract:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_type() {
+        assert!(matches!(
+            Pattern::parse("<a:float>"),
+            Err(PatternError::UnknownType { .. })
+        ));
+        assert!(matches!(
+            Pattern::parse("<a:on|>"),
+            Err(PatternError::UnknownType { .. })
+        ));
+    }
+
+    #[test]
+    fn users() {
+        let user = |input: &str| Type::User.convert(Cow::Owned(input.to_string()));
+        assert!(user("museun").is_some());
+        assert!(user("@shaken_bot").is_some());
+        assert!(user("").is_none());
+        assert!(user("not-a-name").is_none());
+        assert!(user(&"a".repeat(26)).is_none());
+    }
+
+    #[test]
+    fn urls() {
+        let url = |input: &str| Type::Url.convert(Cow::Owned(input.to_string()));
+        assert!(url("https://example.com/a?b=c").is_some());
+        assert!(url("http://example.com").is_some());
+        assert!(url("ftp://example.com").is_none());
+        assert!(url("example.com").is_none());
+    }
+
+    #[test]
+    fn durations() {
+        use ::time::Duration;
+
+        assert_eq!(parse_duration("90"), Some(Duration::seconds(90)));
+        assert_eq!(parse_duration("90s"), Some(Duration::seconds(90)));
+        assert_eq!(parse_duration("5m"), Some(Duration::minutes(5)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("2d"), Some(Duration::days(2)));
+
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("5"), Some(Duration::seconds(5)));
+        assert_eq!(parse_duration("5w"), None);
+        assert_eq!(parse_duration("1h30"), None);
+        assert_eq!(parse_duration("99999999999999999999d"), None);
+    }
 }
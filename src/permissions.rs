@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+
+use mlua::UserData;
+
+use crate::{GlobalItem, ResultExt, SharedKvSqlStore};
+
+// overrides that apply to every command are stored under this
+const ANY_COMMAND: &str = "*";
+
+/// Explicit per-user overrides of a command's access level
+///
+/// A user can be allowed to use a command regardless of their class, or be
+/// denied from using it. An override for a command also applies to its
+/// subcommands, unless they have one of their own. An override for `*` applies
+/// to every command.
+#[derive(Clone, Debug)]
+pub struct Permissions(SharedKvSqlStore);
+
+impl GlobalItem for Permissions {
+    const MODULE: &'static str = "permissions";
+}
+
+impl Permissions {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self(SharedKvSqlStore::new(path))
+    }
+
+    /// `Some(true)` if the user was explicitly allowed, `Some(false)` if they were denied
+    pub fn check(&self, command: &str, user: &str) -> Option<bool> {
+        let db = self
+            .0
+            .get()
+            .inspect_err(|err| log::warn!("cannot open permissions: {err}"))
+            .ok()?;
+
+        // "greeting add" falls back to "greeting", then to every command
+        let parents = std::iter::successors(Some(command), |command| {
+            command.rsplit_once(' ').map(|(parent, _)| parent)
+        });
+
+        parents
+            .chain([ANY_COMMAND])
+            .find_map(|command| db.get(&Self::key(command, user)).ok().flatten())
+            .and_then(|value| value.as_bool())
+    }
+
+    fn key(command: &str, user: &str) -> String {
+        format!("{command} {user}", user = user.to_lowercase())
+    }
+}
+
+impl UserData for Permissions {
+    fn add_methods<M>(methods: &mut M)
+    where
+        M: mlua::UserDataMethods<Self>,
+    {
+        methods.add_method("allow", |_lua, this, (command, user): (String, String)| {
+            this.0
+                .get()
+                .and_then(|db| db.set(&Self::key(&command, &user), true))
+                .map(|_| true)
+                .into_lua_tuple()
+        });
+
+        methods.add_method("deny", |_lua, this, (command, user): (String, String)| {
+            this.0
+                .get()
+                .and_then(|db| db.set(&Self::key(&command, &user), false))
+                .map(|_| true)
+                .into_lua_tuple()
+        });
+
+        methods.add_method("reset", |_lua, this, (command, user): (String, String)| {
+            this.0
+                .get()
+                .and_then(|db| db.remove(&Self::key(&command, &user)))
+                .into_lua_tuple()
+        });
+
+        methods.add_method("check", |_lua, this, (command, user): (String, String)| {
+            Ok(this.check(&command, &user))
+        });
+
+        methods.add_method("list", |lua, this, ()| {
+            let db = this.0.get().map_err(mlua::Error::external)?;
+            let table = lua.create_table()?;
+            for key in db.keys().map_err(mlua::Error::external)? {
+                // subcommands have spaces in their name, but users don't
+                let Some((command, user)) = key.rsplit_once(' ') else {
+                    continue;
+                };
+                let Some(allowed) = db.get(&key).ok().flatten().and_then(|v| v.as_bool()) else {
+                    continue;
+                };
+                let entry = lua.create_table()?;
+                entry.set("command", command)?;
+                entry.set("user", user)?;
+                entry.set("allowed", allowed)?;
+                table.push(entry)?;
+            }
+            Ok(table)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(permissions: &Permissions, command: &str, user: &str, allowed: bool) {
+        let db = permissions.0.get().unwrap();
+        db.set(&Permissions::key(command, user), allowed).unwrap();
+    }
+
+    #[test]
+    fn subcommands() {
+        let permissions = Permissions::new(":memory:");
+        assert_eq!(permissions.check("greeting add", "museun"), None);
+
+        set(&permissions, "greeting", "museun", false);
+        assert_eq!(permissions.check("greeting", "museun"), Some(false));
+        assert_eq!(permissions.check("greeting add", "Museun"), Some(false));
+        assert_eq!(permissions.check("greeting add", "someone"), None);
+
+        // the subcommand's own override wins
+        set(&permissions, "greeting add", "museun", true);
+        assert_eq!(permissions.check("greeting add", "museun"), Some(true));
+        assert_eq!(permissions.check("greeting remove", "museun"), Some(false));
+    }
+
+    #[test]
+    fn any_command() {
+        let permissions = Permissions::new(":memory:");
+        set(&permissions, ANY_COMMAND, "museun", true);
+        assert_eq!(permissions.check("greeting add", "museun"), Some(true));
+
+        set(&permissions, "greeting", "museun", false);
+        assert_eq!(permissions.check("greeting add", "museun"), Some(false));
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use mlua::IntoLua;
+
+use crate::{config, irc::Message};
+
+/// A command being invoked, e.g. `!song` or `@bot song`
+#[derive(Copy, Clone, Debug)]
+pub struct Invocation<'a> {
+    /// The prefix that was used, or the channel's primary prefix if the bot was mentioned
+    pub prefix: &'a str,
+    pub name: &'a str,
+    pub args: &'a str,
+}
+
+impl IntoLua for Invocation<'_> {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        lua.create_table_from([
+            ("prefix", self.prefix),
+            ("name", self.name),
+            ("args", self.args),
+        ])
+        .map(mlua::Value::Table)
+    }
+}
+
+/// The prefixes commands can be invoked with, per channel
+#[derive(Clone, Debug)]
+pub struct Prefixes {
+    default: Vec<String>,
+    channels: HashMap<String, Vec<String>>,
+    mention: bool,
+}
+
+impl Prefixes {
+    pub fn new(settings: &config::Commands) -> Self {
+        fn clean(list: &[String]) -> Vec<String> {
+            list.iter()
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(ToString::to_string)
+                .collect()
+        }
+
+        let channels = settings
+            .channels
+            .iter()
+            .filter_map(|(channel, settings)| {
+                let prefixes = settings.prefixes.as_deref()?;
+                Some((normalize(channel), clean(prefixes)))
+            })
+            .collect();
+
+        Self {
+            default: clean(&settings.prefixes),
+            channels,
+            mention: settings.mention,
+        }
+    }
+
+    pub fn for_channel(&self, channel: &str) -> &[String] {
+        self.channels
+            .get(&normalize(channel))
+            .unwrap_or(&self.default)
+    }
+
+    /// The prefix shown in usage messages for this channel
+    pub fn primary(&self, channel: &str) -> &str {
+        self.for_channel(channel).first().map_or("", |s| s)
+    }
+
+    /// Removes this channel's prefix from `input`, if it has one
+    pub fn strip<'a>(&self, channel: &str, input: &'a str) -> &'a str {
+        self.find(channel, input)
+            .map_or(input, |prefix| &input[prefix.len()..])
+    }
+
+    /// Turns a prefix-free command into something that can be invoked in this channel
+    pub fn invoke(&self, channel: &str, our_user: &str, command: &str) -> String {
+        match self.primary(channel) {
+            "" => format!("@{our_user} {command}"),
+            prefix => format!("{prefix}{command}"),
+        }
+    }
+
+    pub fn parse<'a>(&'a self, msg: &'a Message) -> Option<Invocation<'a>> {
+        let data = msg.data.trim_start();
+
+        let (prefix, rest) = match self.find(&msg.channel, data) {
+            Some(prefix) => (prefix, &data[prefix.len()..]),
+            None => (self.primary(&msg.channel), self.mentioned(msg, data)?),
+        };
+
+        // `! song` isn't a command
+        if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+
+        let (name, args) = rest
+            .split_once(char::is_whitespace)
+            .unwrap_or((rest, ""));
+
+        Some(Invocation {
+            prefix,
+            name,
+            args: args.trim(),
+        })
+    }
+
+    // the longest matching prefix, so `!!` is preferred over `!`
+    fn find<'a>(&'a self, channel: &str, input: &str) -> Option<&'a str> {
+        self.for_channel(channel)
+            .iter()
+            .filter(|prefix| input.starts_with(prefix.as_str()))
+            .max_by_key(|prefix| prefix.len())
+            .map(|s| s.as_str())
+    }
+
+    // `@bot song`, `@bot, song` or `@bot: song`
+    fn mentioned<'a>(&self, msg: &Message, data: &'a str) -> Option<&'a str> {
+        if !self.mention || msg.our_user.is_empty() {
+            return None;
+        }
+
+        let rest = data.strip_prefix('@')?;
+        let user = rest.get(..msg.our_user.len())?;
+        if !user.eq_ignore_ascii_case(&msg.our_user) {
+            return None;
+        }
+
+        let rest = &rest[msg.our_user.len()..];
+        let rest = rest.strip_prefix([',', ':']).unwrap_or(rest);
+        if !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        Some(rest.trim_start())
+    }
+}
+
+pub(crate) fn normalize(channel: &str) -> String {
+    channel.trim().trim_start_matches('#').to_lowercase()
+}
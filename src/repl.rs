@@ -0,0 +1,161 @@
+use std::io::BufRead as _;
+
+use crate::irc::{Event, Message, MessageClass, Response, User};
+
+const HELP: &str = "\
+every line is sent as a chat message, except for these:
+  /as <name> [access]  send messages as someone else
+  /access <access>     change the sender's access, e.g. subscriber or moderator
+  /channel <channel>   send messages to another channel
+  /whoami              show who is sending messages, and where
+  /help                show this
+  /quit                exit";
+
+// who is chatting, and where
+#[derive(Debug)]
+struct Session {
+    bot: User,
+    channel: String,
+    sender: String,
+    class: MessageClass,
+    next_id: u64,
+}
+
+impl Session {
+    fn message(&mut self, data: &str) -> Message {
+        self.next_id += 1;
+        Message {
+            our_user: self.bot.name.clone(),
+            our_id: self.bot.user_id.clone(),
+            channel: self.channel.clone(),
+            channel_id: format!("repl-{}", self.channel.trim_start_matches('#')),
+            msg_id: format!("repl-{}", self.next_id),
+            sender: self.sender.clone(),
+            sender_id: format!("repl-{}", self.sender),
+            data: data.to_string(),
+            class: self.class,
+        }
+    }
+
+    fn whoami(&self) -> String {
+        format!(
+            "* sending to {channel} as {sender} ({class})",
+            channel = self.channel,
+            sender = self.sender,
+            class = self.class.as_str()
+        )
+    }
+
+    // returns false if we should stop
+    fn command(&mut self, input: &str) -> bool {
+        let (command, args) = input.split_once(' ').unwrap_or((input, ""));
+        let mut args = args.split_whitespace();
+
+        match (command, args.next(), args.next()) {
+            ("as", Some(sender), class) => {
+                self.sender = sender.trim_start_matches('@').to_lowercase();
+                if let Some(class) = class {
+                    self.set_class(class);
+                }
+                println!("{}", self.whoami());
+            }
+            ("access", Some(class), None) => {
+                self.set_class(class);
+                println!("{}", self.whoami());
+            }
+            ("channel", Some(channel), None) => {
+                self.channel = format!("#{}", channel.trim_start_matches('#').to_lowercase());
+                println!("{}", self.whoami());
+            }
+            ("whoami", None, None) => println!("{}", self.whoami()),
+            ("quit" | "exit", None, None) => return false,
+            _ => println!("{HELP}"),
+        }
+        true
+    }
+
+    fn set_class(&mut self, class: &str) {
+        match MessageClass::parse_access(class) {
+            Some(class) => self.class = class,
+            None => println!("* unknown access: {class}"),
+        }
+    }
+}
+
+/// Chat with the bot from the terminal, instead of Twitch
+///
+/// Lines from stdin are sent to the bot as messages in `channel`, and
+/// whatever it responds with is printed. The bot is told it has connected
+/// right away, and disconnects when stdin is closed (or `/quit` is used).
+pub fn connect(
+    name: &str,
+    channel: &str,
+    messages: flume::Sender<Message>,
+    responses: flume::Receiver<Response>,
+) -> flume::Receiver<Event> {
+    let bot = User {
+        name: name.to_string(),
+        display: name.to_string(),
+        user_id: String::from("repl"),
+    };
+
+    let (events, out) = flume::unbounded();
+    let _ = events.send(Event::Connected { user: bot.clone() });
+
+    let mut session = Session {
+        bot,
+        channel: format!("#{}", channel.trim_start_matches('#').to_lowercase()),
+        sender: String::from("viewer"),
+        class: MessageClass::User,
+        next_id: 0,
+    };
+
+    let _ = std::thread::spawn({
+        let name = name.to_string();
+        move || print_responses(&name, &responses)
+    });
+
+    let _ = std::thread::spawn(move || {
+        println!("{HELP}");
+        println!("{}", session.whoami());
+
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(command) = line.strip_prefix('/') {
+                if !session.command(command) {
+                    break;
+                }
+                continue;
+            }
+
+            if messages.send(session.message(line)).is_err() {
+                break;
+            }
+        }
+
+        // dropping this tells the main loop we're done
+        drop(events);
+    });
+
+    out
+}
+
+fn print_responses(name: &str, responses: &flume::Receiver<Response>) {
+    for response in responses.iter() {
+        match response {
+            Response::Say { channel, data } => println!("[{channel}] {name}: {data}"),
+            Response::Reply { channel, data, .. } => println!("[{channel}] {name} (reply): {data}"),
+            Response::Error { channel, data } => println!("[{channel}] {name} (error): {data}"),
+            Response::Join { channel } => println!("* joined {channel}"),
+            Response::Part { channel } => println!("* left {channel}"),
+            Response::Disconnect => {
+                println!("* asked to reconnect, but there's nothing to reconnect to")
+            }
+        }
+    }
+}
@@ -1,13 +1,21 @@
-use mlua::UserData;
+use mlua::{FromLua, UserData};
 
 use crate::{
+    config::Split,
     irc::{Message, Response},
     GlobalItem,
 };
 
+// Twitch won't accept a PRIVMSG body longer than this
+const MAX_LENGTH: usize = 500;
+
+// Response::Error is prefixed with this when its sent
+const ERROR_PREFIX: &str = "error: ";
+
 #[derive(Clone, Debug)]
 pub struct Responder {
     tx: flume::Sender<Response>,
+    split: Split,
 }
 
 impl GlobalItem for Responder {
@@ -15,8 +23,27 @@ impl GlobalItem for Responder {
 }
 
 impl Responder {
-    pub const fn new(tx: flume::Sender<Response>) -> Self {
-        Self { tx }
+    pub const fn new(tx: flume::Sender<Response>, split: Split) -> Self {
+        Self { tx, split }
+    }
+}
+
+/// Options for `say` and `reply`, e.g. `msg:say(data, { all = true })`
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SendOptions {
+    /// Send every part of a long message, rather than at most `split.max_parts`
+    pub all: bool,
+}
+
+impl FromLua for SendOptions {
+    fn from_lua(value: mlua::Value, _lua: &mlua::Lua) -> mlua::Result<Self> {
+        match value {
+            mlua::Value::Nil => Ok(Self::default()),
+            mlua::Value::Table(table) => Ok(Self {
+                all: table.get::<Option<bool>>("all")?.unwrap_or_default(),
+            }),
+            _ => Err(mlua::Error::runtime("send options must be a table")),
+        }
     }
 }
 
@@ -25,15 +52,21 @@ impl UserData for Responder {
     where
         M: mlua::UserDataMethods<Self>,
     {
-        methods.add_method("say", |_lua, this, (msg, data): (Message, String)| {
-            this.say(&msg, data);
-            Ok(())
-        });
+        methods.add_method(
+            "say",
+            |_lua, this, (msg, data, options): (Message, String, SendOptions)| {
+                this.say_with(&msg.channel, data, options);
+                Ok(())
+            },
+        );
 
-        methods.add_method("reply", |_lua, this, (msg, data): (Message, String)| {
-            this.reply(&msg, data);
-            Ok(())
-        });
+        methods.add_method(
+            "reply",
+            |_lua, this, (msg, data, options): (Message, String, SendOptions)| {
+                this.reply_with(&msg, data, options);
+                Ok(())
+            },
+        );
 
         methods.add_method("error", |_lua, this, (msg, data): (Message, String)| {
             this.error(&msg, data);
@@ -48,23 +81,202 @@ impl Responder {
     }
 
     pub fn say(&self, msg: &Message, data: String) {
-        self.send(Response::Say {
-            channel: msg.channel.clone(),
-            data,
-        });
+        self.say_to(&msg.channel, data)
+    }
+
+    pub fn say_to(&self, channel: &str, data: String) {
+        self.say_with(channel, data, SendOptions::default())
+    }
+
+    pub fn say_with(&self, channel: &str, data: String, options: SendOptions) {
+        for data in self.split(&data, MAX_LENGTH, options) {
+            self.send(Response::Say {
+                channel: channel.to_string(),
+                data,
+            });
+        }
     }
 
     pub fn reply(&self, msg: &Message, data: String) {
-        self.send(Response::Reply {
-            channel: msg.channel.clone(),
-            msg_id: msg.msg_id.clone(),
-            data,
-        });
+        self.reply_with(msg, data, SendOptions::default())
+    }
+
+    pub fn reply_with(&self, msg: &Message, data: String, options: SendOptions) {
+        for data in self.split(&data, MAX_LENGTH, options) {
+            self.send(Response::Reply {
+                channel: msg.channel.clone(),
+                msg_id: msg.msg_id.clone(),
+                data,
+            });
+        }
     }
+
     pub fn error(&self, msg: &Message, data: String) {
-        self.send(Response::Error {
-            channel: msg.channel.clone(),
-            data,
-        });
+        let max = MAX_LENGTH - ERROR_PREFIX.len();
+        for data in self.split(&data, max, SendOptions::default()) {
+            self.send(Response::Error {
+                channel: msg.channel.clone(),
+                data,
+            });
+        }
+    }
+
+    fn split(&self, data: &str, max: usize, options: SendOptions) -> Vec<String> {
+        let max_parts = if options.all {
+            usize::MAX
+        } else {
+            self.split.max_parts.max(1)
+        };
+        split_message(data, max, &self.split.continuation, max_parts)
+    }
+}
+
+// splits `data` into at most `max_parts` messages of at most `max` bytes each
+//
+// splits happen on whitespace when possible, otherwise on a char boundary that
+// doesn't separate a combining mark (or joiner) from what it is attached to.
+// if the message doesn't fit in `max_parts`, the last part ends with the marker
+fn split_message(data: &str, max: usize, marker: &str, max_parts: usize) -> Vec<String> {
+    let mut remaining = data.trim();
+    if remaining.len() <= max {
+        return vec![remaining.to_string()];
+    }
+
+    let budget = max.saturating_sub(marker.len()).max(1);
+
+    let mut parts = vec![];
+    while !remaining.is_empty() {
+        if remaining.len() <= max {
+            parts.push(remaining.to_string());
+            break;
+        }
+
+        let cut = find_split(remaining, budget);
+        let head = remaining[..cut].trim_end();
+        parts.push(format!("{head}{marker}"));
+        remaining = remaining[cut..].trim_start();
+
+        if parts.len() == max_parts {
+            if !remaining.is_empty() {
+                log::debug!("response was too long, dropped {} bytes", remaining.len());
+            }
+            break;
+        }
+    }
+
+    parts
+}
+
+fn find_split(input: &str, budget: usize) -> usize {
+    let mut end = budget.min(input.len());
+    while !input.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    // prefer breaking between words
+    if let Some(pos) = input[..end].rfind(char::is_whitespace) {
+        if pos > 0 {
+            return pos;
+        }
+    }
+
+    // otherwise don't split a grapheme cluster apart
+    let mut cut = end;
+    while cut > 0 {
+        let next = input[cut..].chars().next();
+        let prev = input[..cut].chars().next_back();
+        if !next.is_some_and(is_extending) && !prev.is_some_and(is_joiner) {
+            break;
+        }
+        cut -= prev.map_or(1, char::len_utf8);
+    }
+
+    // a single cluster larger than the budget, just split on the char boundary
+    if cut == 0 {
+        cut = end.max(input.chars().next().map_or(1, char::len_utf8));
+    }
+    cut
+}
+
+const fn is_joiner(c: char) -> bool {
+    matches!(c, '\u{200D}')
+}
+
+const fn is_extending(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}'     // combining diacritical marks
+        | '\u{1AB0}'..='\u{1AFF}'   // combining diacritical marks extended
+        | '\u{1DC0}'..='\u{1DFF}'   // combining diacritical marks supplement
+        | '\u{20D0}'..='\u{20FF}'   // combining diacritical marks for symbols
+        | '\u{FE20}'..='\u{FE2F}'   // combining half marks
+        | '\u{FE00}'..='\u{FE0F}'   // variation selectors
+        | '\u{200C}'..='\u{200D}'   // zero width (non-)joiner
+        | '\u{1F3FB}'..='\u{1F3FF}' // emoji skin tone modifiers
+        | '\u{E0020}'..='\u{E007F}' // tags
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits() {
+        assert_eq!(split_message("  hello world ", 11, "+", 4), ["hello world"]);
+    }
+
+    #[test]
+    fn words() {
+        let parts = split_message("aaaa bbbb cccc", 9, "+", 4);
+        assert_eq!(parts, ["aaaa+", "bbbb cccc"]);
+    }
+
+    #[test]
+    fn multibyte_boundary() {
+        // each `é` is two bytes, so the budget lands in the middle of one
+        let parts = split_message("éééééé", 5, "", 4);
+        assert_eq!(parts, ["éé", "éé", "éé"]);
+
+        // combining marks stay with the char they're attached to
+        let parts = split_message("xe\u{301}e\u{301}", 3, "", 4);
+        assert_eq!(parts, ["x", "e\u{301}", "e\u{301}"]);
+    }
+
+    #[test]
+    fn long_word() {
+        let parts = split_message("abcdefghij", 4, "-", 4);
+        assert_eq!(parts, ["abc-", "def-", "ghij"]);
+        assert!(parts.iter().all(|part| part.len() <= 4));
+    }
+
+    #[test]
+    fn max_parts() {
+        let parts = split_message("one two three four five", 8, "+", 2);
+        assert_eq!(parts, ["one+", "two+"]);
+
+        let parts = split_message("aaaa bbbb", 5, "+", 1);
+        assert_eq!(parts, ["aaaa+"]);
+    }
+
+    #[test]
+    fn all_parts() {
+        let (tx, rx) = flume::unbounded();
+        let split = Split {
+            continuation: String::from("+"),
+            max_parts: 1,
+        };
+        let responder = Responder::new(tx, split);
+
+        let data = "word ".repeat(300);
+        responder.say_to("#test", data.clone());
+        assert_eq!(rx.drain().count(), 1);
+
+        responder.say_with("#test", data, SendOptions { all: true });
+        let parts: Vec<_> = rx.drain().collect();
+        assert_eq!(parts.len(), 4);
+        assert!(parts.iter().all(|part| match part {
+            Response::Say { data, .. } => data.len() <= MAX_LENGTH,
+            _ => false,
+        }));
     }
 }
@@ -0,0 +1,297 @@
+use std::collections::HashSet;
+
+use mlua::{AnyUserData, UserData};
+
+use crate::{config, GlobalItem, Globals};
+
+// the parts of `os` that can't touch the filesystem or the process
+const SAFE_OS: [&str; 4] = ["clock", "date", "difftime", "time"];
+
+// the parts of the standard library a sandboxed script cannot see
+const UNSAFE: [&str; 7] = [
+    "collectgarbage",
+    "debug",
+    "dofile",
+    "io",
+    "load",
+    "loadfile",
+    "package",
+];
+
+/// Runs scripts in their own environment
+///
+/// A sandboxed script gets a safe subset of the standard library and a
+/// read-only view of the bot's globals. Anything it assigns to a global
+/// stays in its own environment. Files can only be accessed through `store`,
+/// which is limited to its own directory.
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    enabled: bool,
+    trusted: HashSet<String>,
+}
+
+impl Sandbox {
+    pub fn new(config: &config::Sandbox) -> Self {
+        Self {
+            enabled: config.enabled,
+            trusted: config.trusted.iter().cloned().collect(),
+        }
+    }
+
+    pub fn is_sandboxed(&self, module: &str) -> bool {
+        self.enabled && !self.trusted.contains(module)
+    }
+
+    /// Creates an environment for `module`, if it should be sandboxed
+    pub fn environment_for(lua: &mlua::Lua, module: &str) -> mlua::Result<Option<mlua::Table>> {
+        let Ok(this) = lua.globals().get::<AnyUserData>(Self::MODULE) else {
+            return Ok(None);
+        };
+        if !this.borrow::<Self>()?.is_sandboxed(module) {
+            return Ok(None);
+        }
+        Self::environment(lua).map(Some)
+    }
+
+    fn environment(lua: &mlua::Lua) -> mlua::Result<mlua::Table> {
+        let os = safe_os(lua)?;
+
+        // globals are looked up when they're used,
+        // so things registered later (e.g. `BOT_USER`) are visible
+        let index = lua.create_function(move |lua, (_, key): (mlua::Value, mlua::Value)| {
+            let mlua::Value::String(name) = &key else {
+                return Ok(mlua::Value::Nil);
+            };
+
+            let name = name.to_string_lossy();
+            if name == "os" {
+                return Ok(mlua::Value::Table(os.clone()));
+            }
+            if UNSAFE.contains(&&*name) || (name.starts_with('_') && name != "_VERSION") {
+                return Ok(mlua::Value::Nil);
+            }
+
+            protect(lua, lua.globals().get(key)?)
+        })?;
+
+        let meta = lua.create_table()?;
+        meta.set("__index", index)?;
+        meta.set("__metatable", false)?;
+
+        let env = lua.create_table()?;
+        env.set_metatable(Some(meta));
+        Ok(env)
+    }
+
+    fn require(lua: &mlua::Lua, name: &str) -> mlua::Result<mlua::Value> {
+        if UNSAFE.contains(&name) {
+            return Err(mlua::Error::runtime(format!(
+                "module '{name}' is not available in the sandbox"
+            )));
+        }
+        if name == "os" {
+            return safe_os(lua).map(mlua::Value::Table);
+        }
+
+        let package = lua.globals().get::<mlua::Table>("package")?;
+        let loaded = package.get::<mlua::Table>("loaded")?;
+
+        // only hand out modules we loaded, `package.loaded` also has the standard library
+        let modules = lua.globals().get::<mlua::Table>("_LOADED_MODULES")?;
+        if modules.get::<Option<bool>>(name)?.unwrap_or(false) {
+            match loaded.get::<mlua::Value>(name)? {
+                mlua::Value::Nil | mlua::Value::Boolean(false) => {}
+                value => return Ok(value),
+            }
+        }
+
+        let (path, err) = package
+            .get::<mlua::Function>("searchpath")?
+            .call::<(Option<String>, Option<String>)>((name, package.get::<String>("path")?))?;
+
+        let Some(path) = path else {
+            return Err(mlua::Error::runtime(format!(
+                "module '{name}' not found:{err}",
+                err = err.unwrap_or_default()
+            )));
+        };
+
+        let source = std::fs::read_to_string(&path).map_err(mlua::Error::external)?;
+        let value = lua
+            .load(source)
+            .set_name(format!("@{path}"))
+            .set_environment(Self::environment(lua)?)
+            .call::<mlua::Value>((name, &*path))?;
+
+        let value = match value {
+            mlua::Value::Nil => mlua::Value::Boolean(true),
+            value => value,
+        };
+        modules.set(name, true)?;
+        loaded.set(name, value.clone())?;
+        Ok(value)
+    }
+}
+
+impl UserData for Sandbox {}
+
+impl GlobalItem for Sandbox {
+    const MODULE: &'static str = "_SANDBOX";
+
+    // this has to be registered after `LoadedModules`, it wraps its `require`
+    fn register(self, g: Globals<'_>) -> mlua::Result<()> {
+        if !self.enabled {
+            return g.set(Self::MODULE, self);
+        }
+
+        if !self.trusted.is_empty() {
+            log::info!(
+                "sandboxing scripts, except: {}",
+                self.trusted.iter().cloned().collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        // the string metatable is shared by every script
+        g.0.load("getmetatable('').__metatable = false").exec()?;
+
+        let require = g.get::<mlua::Function>("require")?;
+        let trusted = self.clone();
+        let require = g.0.create_function(move |lua, name: String| {
+            if !trusted.is_sandboxed(&name) {
+                return require.call::<mlua::Value>(name);
+            }
+            Self::require(lua, &name)
+        })?;
+
+        g.set(Self::MODULE, self)?;
+        g.set("require", require)
+    }
+}
+
+fn safe_os(lua: &mlua::Lua) -> mlua::Result<mlua::Table> {
+    let real_os = lua.globals().get::<mlua::Table>("os")?;
+    let os = lua.create_table()?;
+    for name in SAFE_OS {
+        os.set(name, real_os.get::<mlua::Value>(name)?)?;
+    }
+    read_only(lua, os)
+}
+
+fn protect(lua: &mlua::Lua, value: mlua::Value) -> mlua::Result<mlua::Value> {
+    match value {
+        mlua::Value::Table(table) => read_only(lua, table).map(mlua::Value::Table),
+        value => Ok(value),
+    }
+}
+
+// a proxy that reads through to `table`, and refuses writes
+fn read_only(lua: &mlua::Lua, table: mlua::Table) -> mlua::Result<mlua::Table> {
+    let meta = lua.create_table()?;
+
+    meta.set(
+        "__index",
+        lua.create_function({
+            let table = table.clone();
+            move |lua, (_, key): (mlua::Value, mlua::Value)| protect(lua, table.get(key)?)
+        })?,
+    )?;
+
+    meta.set(
+        "__newindex",
+        lua.create_function(|_, _: mlua::MultiValue| {
+            Err::<(), _>(mlua::Error::runtime("cannot modify a read-only table"))
+        })?,
+    )?;
+
+    meta.set(
+        "__len",
+        lua.create_function({
+            let table = table.clone();
+            move |_, _: mlua::Value| table.len()
+        })?,
+    )?;
+
+    let next = lua.create_function({
+        let table = table.clone();
+        move |lua, (_, key): (mlua::Value, mlua::Value)| {
+            let (key, value) = lua
+                .globals()
+                .get::<mlua::Function>("next")?
+                .call::<(mlua::Value, mlua::Value)>((table.clone(), key))?;
+            Ok((key, protect(lua, value)?))
+        }
+    })?;
+    meta.set(
+        "__pairs",
+        lua.create_function(move |_, this: mlua::Table| {
+            Ok((next.clone(), this, mlua::Value::Nil))
+        })?,
+    )?;
+
+    meta.set("__metatable", false)?;
+
+    let proxy = lua.create_table()?;
+    proxy.set_metatable(Some(meta));
+    Ok(proxy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LoadedModules;
+
+    fn lua(trusted: &[&str]) -> mlua::Lua {
+        let lua = mlua::Lua::new();
+        let config = config::Sandbox {
+            enabled: true,
+            trusted: trusted.iter().map(ToString::to_string).collect(),
+        };
+        Globals::new(&lua)
+            .register(LoadedModules)
+            .unwrap()
+            .register(Sandbox::new(&config))
+            .unwrap();
+        lua
+    }
+
+    fn eval<T: mlua::FromLua>(lua: &mlua::Lua, code: &str) -> mlua::Result<T> {
+        lua.load(code)
+            .set_environment(Sandbox::environment(lua)?)
+            .eval()
+    }
+
+    #[test]
+    fn unsafe_modules() {
+        let lua = lua(&[]);
+        for name in UNSAFE {
+            let code = format!("return require('{name}')");
+            assert!(eval::<mlua::Value>(&lua, &code).is_err(), "{name} was required");
+        }
+
+        // `os` is the same as the one in the environment
+        let os = eval::<mlua::Table>(&lua, "return require('os')").unwrap();
+        assert!(os.get::<mlua::Function>("time").is_ok());
+        for name in ["execute", "exit", "getenv", "remove", "rename", "tmpname"] {
+            assert!(os.get::<mlua::Value>(name).unwrap().is_nil(), "os.{name} is visible");
+        }
+    }
+
+    #[test]
+    fn environment() {
+        let lua = lua(&[]);
+        for name in UNSAFE {
+            let code = format!("return {name}");
+            assert!(eval::<mlua::Value>(&lua, &code).unwrap().is_nil(), "{name} is visible");
+        }
+        assert!(eval::<mlua::Value>(&lua, "return os.execute").unwrap().is_nil());
+        assert!(eval::<()>(&lua, "string.len = nil").is_err());
+    }
+
+    #[test]
+    fn loaded_modules() {
+        let lua = lua(&[]);
+        // the standard library is in `package.loaded`, but it wasn't loaded by us
+        assert!(eval::<mlua::Value>(&lua, "return require('string')").is_err());
+        assert!(eval::<mlua::Value>(&lua, "return require('coroutine')").is_err());
+    }
+}
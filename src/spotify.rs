@@ -2,13 +2,22 @@
 use std::{
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use mlua::{FromLua, IntoLua, LuaSerdeExt, UserData};
 use url::Url;
 
-use crate::{sql::DbError, time::TimeSpan, GlobalItem, ResultExt};
+use crate::{sql::DbError, time::TimeSpan, Emitter, GlobalItem, ResultExt};
+
+// how often the poller checks what's playing
+const POLL: Duration = Duration::from_secs(30);
+
+// the longest the poller waits while nothing is playing
+const MAX_BACKOFF: u64 = 60;
+
+// a song that ended this much earlier than expected was skipped
+const SKIP_SLACK: Duration = Duration::from_secs(5);
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -91,6 +100,8 @@ pub struct Client {
 
 impl GlobalItem for Client {
     const MODULE: &'static str = "spotify";
+    const ASYNC_METHODS: &'static [&'static str] =
+        &["current", "next", "skip", "search", "add_to_queue"];
 }
 
 impl UserData for Client {
@@ -99,40 +110,47 @@ impl UserData for Client {
         M: mlua::UserDataMethods<Self>,
     {
         methods.add_method("current", |lua, this, ()| {
-            match this.get_currently_playing().ok() {
-                Some(CurrenlyPlaying::Playing(item)) => item.into_lua(lua),
-                Some(CurrenlyPlaying::NotPlaying) | None => Ok(mlua::Value::Nil),
-            }
+            let this = this.clone();
+            crate::manifest::spawn(lua, move || match this.get_currently_playing().ok() {
+                Some(CurrenlyPlaying::Playing(item)) => Ok(Some(item)),
+                Some(CurrenlyPlaying::NotPlaying) | None => Ok(None),
+            })
         });
 
-        methods.add_method("next", |lua, this, ()| match this.get_queue().ok() {
-            Some((_, list)) => match list.first() {
-                Some(item) => item.clone().into_lua(lua),
-                None => Ok(mlua::Value::Nil),
-            },
-            None => Ok(mlua::Value::Nil),
+        methods.add_method("next", |lua, this, ()| {
+            let this = this.clone();
+            crate::manifest::spawn(lua, move || {
+                Ok(this.get_queue().ok().and_then(|(_, list)| list.into_iter().next()))
+            })
         });
 
-        methods.add_method("skip", |_lua, this, ()| {
-            Ok(this.skip_song().ok().unwrap_or(false))
+        methods.add_method("skip", |lua, this, ()| {
+            let this = this.clone();
+            crate::manifest::spawn(lua, move || Ok(this.skip_song().ok().unwrap_or(false)))
         });
 
-        methods.add_method("search", |_lua, this, query: String| {
-            this.search(&query).into_lua_tuple()
+        methods.add_method("search", |lua, this, query: String| {
+            let this = this.clone();
+            crate::manifest::spawn(lua, move || this.search(&query).into_lua_tuple())
         });
 
         methods.add_function("parse", |_lua, input: String| {
             SpotifyUrn::try_from(input.as_str()).into_lua_tuple()
         });
 
-        methods.add_method("add_to_queue", |_lua, this, input: SpotifyUrn| {
-            match this.add_to_queue(&input) {
-                Ok(false) => return Ok((None, Some(String::from("could not add that song")))),
-                Err(err) => return Ok((None, Some(err.to_string()))),
-                _ => {}
-            };
+        methods.add_method("add_to_queue", |lua, this, input: SpotifyUrn| {
+            let this = this.clone();
+            crate::manifest::spawn(lua, move || {
+                match this.add_to_queue(&input) {
+                    Ok(false) => {
+                        return Ok((None, Some(String::from("could not add that song"))))
+                    }
+                    Err(err) => return Ok((None, Some(err.to_string()))),
+                    _ => {}
+                };
 
-            this.lookup_by_urn(&input).into_lua_tuple()
+                this.lookup_by_urn(&input).into_lua_tuple()
+            })
         });
     }
 }
@@ -156,27 +174,58 @@ impl Client {
         })
     }
 
-    pub fn listen_for_changes(this: &Self, path: impl Into<PathBuf>) {
+    /// Polls for the playing song, adding it to the history and emitting `spotify_track_changed`
+    /// when it changes
+    pub fn listen_for_changes(
+        this: &Self,
+        path: impl Into<PathBuf>,
+        emitter: Emitter,
+        announce: Option<String>,
+    ) {
         std::thread::spawn({
             let path = path.into();
             let this = this.clone();
             move || {
+                let mut tracker = Tracker::default();
                 let mut backoff = 10;
                 loop {
-                    match this.get_currently_playing() {
-                        Ok(CurrenlyPlaying::Playing(item)) => {
+                    let playing = match this.get_currently_playing() {
+                        Ok(playing) => playing,
+                        Err(..) => {
+                            std::thread::sleep(Duration::from_secs(backoff));
+                            backoff = (backoff + 10).min(MAX_BACKOFF);
+                            continue;
+                        }
+                    };
+
+                    // check again when the song should end, so the next one is seen sooner
+                    let wait = match &playing {
+                        CurrenlyPlaying::Playing(item) => {
                             {
                                 let history = History::open(&path).unwrap();
-                                let _ = history.push(&item.id, &item).unwrap();
+                                let _ = history.push(&item.id, item).unwrap();
                             }
                             backoff = 10;
-                            std::thread::sleep(Duration::from_secs(30));
+                            (item.remaining() + Duration::from_secs(1)).min(POLL)
                         }
-                        Ok(CurrenlyPlaying::NotPlaying) | Err(..) => {
-                            std::thread::sleep(Duration::from_secs(backoff));
-                            backoff += 10;
+                        CurrenlyPlaying::NotPlaying => {
+                            let wait = Duration::from_secs(backoff);
+                            backoff = (backoff + 10).min(MAX_BACKOFF);
+                            wait
                         }
+                    };
+
+                    if let Some((change, item)) = tracker.update(playing) {
+                        log::debug!("spotify track changed ({change:?}): {}", item.name);
+                        let changed = TrackChanged {
+                            change,
+                            channel: announce.clone(),
+                            item,
+                        };
+                        emitter.emit("spotify_track_changed", changed);
                     }
+
+                    std::thread::sleep(wait);
                 }
             }
         });
@@ -349,6 +398,13 @@ pub struct Item {
     pub progress: Option<Duration>,
 }
 
+impl Item {
+    // how long is left of the song, when it was last seen
+    fn remaining(&self) -> Duration {
+        self.duration.saturating_sub(self.progress.unwrap_or_default())
+    }
+}
+
 impl IntoLua for Item {
     fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
         let table = lua.create_table()?;
@@ -384,6 +440,81 @@ pub enum CurrenlyPlaying {
     NotPlaying,
 }
 
+/// How the playing song changed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// A new song started, after the last one ended (or after being paused)
+    New,
+    /// Nothing is playing anymore
+    Paused,
+    /// The same song is playing again
+    Resumed,
+    /// A new song started before the last one ended
+    Skipped,
+}
+
+impl Change {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::New => "new",
+            Self::Paused => "paused",
+            Self::Resumed => "resumed",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+/// The payload for the `spotify_track_changed` event, an [`Item`] with what changed
+#[derive(Debug)]
+pub struct TrackChanged {
+    pub change: Change,
+    /// Where to announce the change, from `spotify.announce`
+    pub channel: Option<String>,
+    pub item: Item,
+}
+
+impl IntoLua for TrackChanged {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let value = self.item.into_lua(lua)?;
+        if let Some(table) = value.as_table() {
+            table.set("change", self.change.as_str())?;
+            table.set("channel", self.channel)?;
+        }
+        Ok(value)
+    }
+}
+
+// what the poller saw the last time it checked
+#[derive(Debug, Default)]
+struct Tracker {
+    last: Option<(Item, Instant)>,
+    paused: bool,
+}
+
+impl Tracker {
+    fn update(&mut self, playing: CurrenlyPlaying) -> Option<(Change, Item)> {
+        let item = match playing {
+            CurrenlyPlaying::Playing(item) => item,
+            CurrenlyPlaying::NotPlaying if self.paused => return None,
+            CurrenlyPlaying::NotPlaying => {
+                let (item, _) = self.last.as_ref()?;
+                self.paused = true;
+                return Some((Change::Paused, item.clone()));
+            }
+        };
+
+        let paused = std::mem::take(&mut self.paused);
+        let change = match self.last.replace((item.clone(), Instant::now())) {
+            Some((last, _)) if last.id == item.id => paused.then_some(Change::Resumed),
+            Some((last, seen)) if !paused && seen.elapsed() + SKIP_SLACK < last.remaining() => {
+                Some(Change::Skipped)
+            }
+            _ => Some(Change::New),
+        };
+        change.map(|change| (change, item))
+    }
+}
+
 mod spotify_duration {
     use serde::{Deserialize, Serialize};
     use std::time::Duration;
@@ -546,3 +677,90 @@ impl History {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing(id: &str, duration: u64, progress: u64) -> CurrenlyPlaying {
+        CurrenlyPlaying::Playing(Item {
+            duration: Duration::from_secs(duration),
+            name: format!("song {id}"),
+            id: id.to_string(),
+            artists: vec![],
+            progress: Some(Duration::from_secs(progress)),
+        })
+    }
+
+    fn update(tracker: &mut Tracker, playing: CurrenlyPlaying) -> Option<(Change, String)> {
+        tracker.update(playing).map(|(change, item)| (change, item.id))
+    }
+
+    fn change(change: Change, id: &str) -> Option<(Change, String)> {
+        Some((change, id.to_string()))
+    }
+
+    #[test]
+    fn new() {
+        let mut tracker = Tracker::default();
+        assert_eq!(update(&mut tracker, playing("a", 180, 0)), change(Change::New, "a"));
+        assert_eq!(update(&mut tracker, playing("a", 180, 30)), None);
+
+        // the last song was about to end
+        assert_eq!(update(&mut tracker, playing("a", 180, 178)), None);
+        assert_eq!(update(&mut tracker, playing("b", 200, 1)), change(Change::New, "b"));
+    }
+
+    #[test]
+    fn skipped() {
+        let mut tracker = Tracker::default();
+        update(&mut tracker, playing("a", 180, 10));
+        assert_eq!(update(&mut tracker, playing("b", 200, 0)), change(Change::Skipped, "b"));
+    }
+
+    #[test]
+    fn paused() {
+        let mut tracker = Tracker::default();
+        assert_eq!(update(&mut tracker, CurrenlyPlaying::NotPlaying), None);
+
+        update(&mut tracker, playing("a", 180, 10));
+        let paused = update(&mut tracker, CurrenlyPlaying::NotPlaying);
+        assert_eq!(paused, change(Change::Paused, "a"));
+        assert_eq!(update(&mut tracker, CurrenlyPlaying::NotPlaying), None);
+    }
+
+    #[test]
+    fn resumed() {
+        let mut tracker = Tracker::default();
+        update(&mut tracker, playing("a", 180, 10));
+        update(&mut tracker, CurrenlyPlaying::NotPlaying);
+        assert_eq!(update(&mut tracker, playing("a", 180, 10)), change(Change::Resumed, "a"));
+        assert_eq!(update(&mut tracker, playing("a", 180, 40)), None);
+
+        // a different song after a pause wasn't skipped
+        update(&mut tracker, CurrenlyPlaying::NotPlaying);
+        assert_eq!(update(&mut tracker, playing("b", 200, 0)), change(Change::New, "b"));
+    }
+
+    #[test]
+    fn payload() {
+        let lua = mlua::Lua::new();
+        let CurrenlyPlaying::Playing(item) = playing("a", 180, 10) else {
+            unreachable!()
+        };
+        let changed = TrackChanged {
+            change: Change::Skipped,
+            channel: Some("#museun".to_string()),
+            item,
+        };
+
+        let table = changed.into_lua(&lua).unwrap();
+        let table = table.as_table().unwrap();
+        assert_eq!(table.get::<String>("change").unwrap(), "skipped");
+        assert_eq!(table.get::<String>("channel").unwrap(), "#museun");
+        assert_eq!(table.get::<String>("id").unwrap(), "a");
+        assert!(table.get::<mlua::AnyUserData>("duration").is_ok());
+        assert!(table.get::<mlua::AnyUserData>("progress").is_ok());
+        assert!(table.get::<mlua::Value>("duration_ms").unwrap().is_nil());
+    }
+}
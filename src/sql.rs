@@ -1,3 +1,7 @@
+use std::path::Path;
+
+use rusqlite::types::ValueRef;
+
 #[derive(Debug, thiserror::Error)]
 pub enum DbError {
     #[error("cannot open db: {0}")]
@@ -6,3 +10,155 @@ pub enum DbError {
     #[error("sql error: {0}")]
     Sql(#[from] rusqlite::Error),
 }
+
+/// Every table in the db at `path`, along with how many rows it has
+pub fn tables(path: impl AsRef<Path>) -> Result<Vec<(String, usize)>, DbError> {
+    let conn = open_read_only(path.as_ref())?;
+    table_names(&conn)?
+        .into_iter()
+        .map(|table| {
+            let sql = format!("select count(*) from \"{table}\"");
+            let count = conn.query_row(&sql, [], |row| row.get(0))?;
+            Ok((table, count))
+        })
+        .collect()
+}
+
+/// Every row of every table in the db at `path`, keyed by the table name
+///
+/// Text and numbers are kept as they are, blobs are hex encoded.
+pub fn dump(path: impl AsRef<Path>) -> Result<serde_json::Value, DbError> {
+    let conn = open_read_only(path.as_ref())?;
+
+    let mut out = serde_json::Map::new();
+    for table in table_names(&conn)? {
+        let mut stmt = conn.prepare(&format!("select * from \"{table}\""))?;
+        let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+
+        let mut rows = vec![];
+        let mut iter = stmt.query([])?;
+        while let Some(row) = iter.next()? {
+            let mut map = serde_json::Map::new();
+            for (i, column) in columns.iter().enumerate() {
+                map.insert(column.clone(), to_json(row.get_ref(i)?));
+            }
+            rows.push(serde_json::Value::Object(map));
+        }
+        out.insert(table, serde_json::Value::Array(rows));
+    }
+    Ok(serde_json::Value::Object(out))
+}
+
+// commands used to be stored with the prefix they were invoked with
+const OLD_PREFIX: &str = "!";
+
+// the `user_version` of a db whose commands no longer have the old prefix
+const PREFIX_FREE: i64 = 1;
+
+/// Removes the old `!` prefix from the command names in each `(table, column)`
+///
+/// This only happens once per db, afterwards its `user_version` marks it as migrated.
+/// Names that would collide with one that is already prefix-free are left alone.
+pub fn strip_old_prefix(
+    path: impl AsRef<Path>,
+    columns: &[(&str, &str)],
+) -> Result<usize, DbError> {
+    let path = path.as_ref();
+    if !path.is_file() {
+        return Ok(0);
+    }
+
+    let mut conn = rusqlite::Connection::open(path)
+        .map_err(|err| DbError::CannotOpenDb(err.to_string()))?;
+
+    let version: i64 = conn.query_row("pragma user_version", [], |row| row.get(0))?;
+    if version >= PREFIX_FREE {
+        return Ok(0);
+    }
+
+    let tx = conn.transaction()?;
+    let tables = table_names(&tx)?;
+
+    let mut changed = 0;
+    for (table, column) in columns {
+        if !tables.iter().any(|name| name == table) {
+            continue;
+        }
+        let sql = format!(
+            "update or ignore \"{table}\" \
+             set \"{column}\" = substr(\"{column}\", length(?1) + 1) \
+             where substr(\"{column}\", 1, length(?1)) = ?1"
+        );
+        changed += tx.execute(&sql, [OLD_PREFIX])?;
+    }
+
+    tx.pragma_update(None, "user_version", PREFIX_FREE)?;
+    tx.commit()?;
+    Ok(changed)
+}
+
+fn open_read_only(path: &Path) -> Result<rusqlite::Connection, DbError> {
+    rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|err| DbError::CannotOpenDb(err.to_string()))
+}
+
+fn table_names(conn: &rusqlite::Connection) -> Result<Vec<String>, DbError> {
+    let mut stmt = conn.prepare(
+        "select name from sqlite_master where type = 'table' \
+         and name not like 'sqlite_%' order by name",
+    )?;
+    let names = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(names)
+}
+
+fn to_json(value: ValueRef<'_>) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(n) => n.into(),
+        ValueRef::Real(n) => n.into(),
+        ValueRef::Text(s) => String::from_utf8_lossy(s).into(),
+        ValueRef::Blob(b) => b.iter().map(|b| format!("{b:02x}")).collect::<String>().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_old_prefix() {
+        let path = std::env::temp_dir().join(format!(
+            "yomi-prefix-test-{}-{}.db",
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "create table kv (key text primary key not null, value json not null);
+             insert into kv values ('!hi', '\"hello\"'), ('!so', '\"shoutout\"'),
+                                   ('so', '\"new\"'), ('bye', '\"later\"');",
+        )
+        .unwrap();
+
+        assert_eq!(super::strip_old_prefix(&path, &[("kv", "key")]).unwrap(), 1);
+
+        let keys = |conn: &rusqlite::Connection| {
+            let mut stmt = conn.prepare("select key from kv order by key").unwrap();
+            let keys = stmt.query_map([], |row| row.get(0)).unwrap();
+            keys.collect::<Result<Vec<String>, _>>().unwrap()
+        };
+        // `!so` would replace the one that was added without a prefix
+        assert_eq!(keys(&conn), ["!so", "bye", "hi", "so"]);
+
+        // it only happens once
+        conn.execute("insert into kv values ('!again', '1')", []).unwrap();
+        assert_eq!(super::strip_old_prefix(&path, &[("kv", "key")]).unwrap(), 0);
+        assert!(keys(&conn).contains(&String::from("!again")));
+
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,7 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError},
+};
 
 use mlua::{LuaSerdeExt, UserData};
 use rusqlite::OptionalExtension;
@@ -13,6 +16,31 @@ impl Store {
     pub fn new(dir: impl Into<PathBuf>) -> Self {
         Self { dir: dir.into() }
     }
+
+    // keys and namespaces are file names in our directory, so they can't be paths
+    fn path(&self, name: &str, extension: &str) -> mlua::Result<PathBuf> {
+        let path = Path::new(name);
+        let valid = !name.contains(['/', '\\'])
+            && !name.contains("..")
+            && !path.is_absolute()
+            && matches!(
+                path.components().collect::<Vec<_>>()[..],
+                [Component::Normal(part)] if part == name
+            );
+
+        if !valid {
+            return Err(mlua::Error::runtime(format!(
+                "invalid store name: {name:?}, it cannot be a path"
+            )));
+        }
+
+        std::fs::create_dir_all(&self.dir).map_err(mlua::Error::external)?;
+        Ok(self.dir.join(name).with_extension(extension))
+    }
+
+    fn open(&self, ns: &str) -> mlua::Result<KvSqlStore> {
+        KvSqlStore::open(self.path(ns, "db")?).map_err(mlua::Error::external)
+    }
 }
 
 impl GlobalItem for Store {
@@ -25,7 +53,7 @@ impl UserData for Store {
         M: mlua::UserDataMethods<Self>,
     {
         methods.add_method("load", |lua, this, key: String| {
-            let path = this.dir.join(key).with_extension("json");
+            let path = this.path(&key, "json")?;
             let data = match std::fs::read_to_string(&path) {
                 Ok(data) => data,
                 Err(_) => {
@@ -40,7 +68,7 @@ impl UserData for Store {
         });
 
         methods.add_method("save", |lua, this, (key, value): (String, mlua::Table)| {
-            let path = this.dir.join(key).with_extension("json");
+            let path = this.path(&key, "json")?;
             let t: serde_json::Value = lua.from_value(mlua::Value::Table(value))?;
             let data = serde_json::to_string_pretty(&t).map_err(mlua::Error::external)?;
             std::fs::write(path, &data).map_err(mlua::Error::external)?;
@@ -48,23 +76,20 @@ impl UserData for Store {
         });
 
         methods.add_method("keys", |_lua, this, ns: String| {
-            let db = KvSqlStore::open(this.dir.join(ns).with_extension("db"))
-                .map_err(mlua::Error::external)?;
+            let db = this.open(&ns)?;
             Ok(db.keys().ok())
         });
 
         methods.add_method(
             "set",
             |_lua, this, (ns, key, value): (String, String, mlua::Value)| {
-                let db = KvSqlStore::open(this.dir.join(ns).with_extension("db"))
-                    .map_err(mlua::Error::external)?;
+                let db = this.open(&ns)?;
                 db.set(&key, value).map_err(mlua::Error::external)
             },
         );
 
         methods.add_method("get", |lua, this, (ns, key): (String, String)| {
-            let db = KvSqlStore::open(this.dir.join(ns).with_extension("db"))
-                .map_err(mlua::Error::external)?;
+            let db = this.open(&ns)?;
             match db.get(&key) {
                 Ok(val) => match lua.to_value(&val) {
                     Ok(mlua::Value::LightUserData(..)) => Ok(mlua::Value::Nil),
@@ -76,8 +101,7 @@ impl UserData for Store {
         });
 
         methods.add_method("remove", |_lua, this, (ns, key): (String, String)| {
-            let db = KvSqlStore::open(this.dir.join(ns).with_extension("db"))
-                .map_err(mlua::Error::external)?;
+            let db = this.open(&ns)?;
             match db.remove(&key) {
                 Ok(val) => Ok(val),
                 Err(..) => Ok(false),
@@ -135,3 +159,66 @@ impl KvSqlStore {
         Ok(self.conn.execute(REMOVE, [key])? > 0)
     }
 }
+
+/// A [`KvSqlStore`] that is opened on first use and kept open afterwards
+///
+/// Clones share the same connection, and can be sent to other threads.
+/// If opening fails it is tried again the next time
+#[derive(Clone)]
+pub struct SharedKvSqlStore {
+    path: PathBuf,
+    db: Arc<OnceLock<Mutex<KvSqlStore>>>,
+}
+
+impl std::fmt::Debug for SharedKvSqlStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedKvSqlStore")
+            .field("path", &self.path)
+            .field("open", &self.db.get().is_some())
+            .finish()
+    }
+}
+
+impl SharedKvSqlStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            db: Arc::default(),
+        }
+    }
+
+    /// The connection is locked until the returned guard is dropped
+    pub fn get(&self) -> Result<MutexGuard<'_, KvSqlStore>, DbError> {
+        let db = match self.db.get() {
+            Some(db) => db,
+            None => {
+                let db = KvSqlStore::open(&self.path)?;
+                self.db.get_or_init(|| Mutex::new(db))
+            }
+        };
+        // a panic while it was locked can't leave the connection in a bad state
+        Ok(db.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let dir = std::env::temp_dir().join("yomi-store-test");
+        let store = Store::new(&dir);
+
+        let path = store.path("greetings", "json").unwrap();
+        assert_eq!(path, dir.join("greetings.json"));
+
+        for name in [
+            "", ".", "..", "../commands", "a/b", "a\\b", "/etc/passwd", "foo/..", "..foo",
+        ] {
+            assert!(store.path(name, "db").is_err(), "{name:?} should be rejected");
+        }
+
+        _ = std::fs::remove_dir_all(&dir);
+    }
+}
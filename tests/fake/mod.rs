@@ -0,0 +1,154 @@
+use std::{
+    io::{self, BufRead as _, BufReader, Write as _},
+    net::{Shutdown, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+// how long to wait for the bot before giving up
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A fake Twitch IRC server, for tests
+///
+/// Point `twitch.address` at [`FakeServer::address`] with the `tcp` transport,
+/// then script what the server says and check what the bot sent back.
+pub struct FakeServer {
+    listener: TcpListener,
+}
+
+impl FakeServer {
+    /// Listens on a random local port
+    pub fn bind() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener })
+    }
+
+    /// The address to connect to, e.g. `127.0.0.1:12345`
+    pub fn address(&self) -> String {
+        self.listener
+            .local_addr()
+            .expect("bound listener")
+            .to_string()
+    }
+
+    /// Waits for the bot to connect
+    pub fn accept(&self) -> io::Result<Client> {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => return Client::new(stream),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "the bot didn't connect",
+                        ));
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// A connected bot
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    next_id: u64,
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            next_id: 0,
+        })
+    }
+
+    /// Sends a raw line, the `\r\n` is added
+    pub fn send(&mut self, line: &str) -> io::Result<()> {
+        write!(self.writer, "{line}\r\n")?;
+        self.writer.flush()
+    }
+
+    /// Reads the next line the bot sent, without the `\r\n`
+    pub fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the bot disconnected",
+            ));
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Reads the next line, which has to start with `prefix`
+    pub fn expect(&mut self, prefix: &str) -> io::Result<String> {
+        let line = self.read_line()?;
+        if !line.starts_with(prefix) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected `{prefix}`, got `{line}`"),
+            ));
+        }
+        Ok(line)
+    }
+
+    /// Accepts the bot's registration, returning the lines it sent
+    pub fn handshake(&mut self, name: &str, user_id: &str) -> io::Result<Vec<String>> {
+        let mut lines = vec![];
+        loop {
+            let line = self.read_line()?;
+            let done = line.starts_with("NICK ");
+            lines.push(line);
+            if done {
+                break;
+            }
+        }
+
+        self.send(&format!(":tmi.twitch.tv 001 {name} :Welcome, GLHF!"))?;
+        self.send(&format!(
+            "@badge-info=;badges=;color=;display-name={name};emote-sets=0;\
+            user-id={user_id};user-type= :tmi.twitch.tv GLOBALUSERSTATE"
+        ))?;
+        Ok(lines)
+    }
+
+    /// Rejects the bot's token, the way Twitch does
+    pub fn reject_auth(&mut self) -> io::Result<()> {
+        self.send(":tmi.twitch.tv NOTICE * :Login authentication failed")
+    }
+
+    pub fn ping(&mut self, token: &str) -> io::Result<()> {
+        self.send(&format!("PING :{token}"))
+    }
+
+    /// Asks the bot to reconnect
+    pub fn reconnect(&mut self) -> io::Result<()> {
+        self.send(":tmi.twitch.tv RECONNECT")
+    }
+
+    /// Sends a chat message from `sender`, with the tags the bot expects
+    pub fn privmsg(&mut self, channel: &str, sender: &str, data: &str) -> io::Result<()> {
+        self.next_id += 1;
+        let channel = channel.trim_start_matches('#');
+        self.send(&format!(
+            "@badge-info=;badges=;color=;display-name={sender};emotes=;first-msg=0;flags=;\
+            id=msg-{id};mod=0;room-id=1;subscriber=0;tmi-sent-ts=0;turbo=0;\
+            user-id=user-{sender};user-type= \
+            :{sender}!{sender}@{sender}.tmi.twitch.tv PRIVMSG #{channel} :{data}",
+            id = self.next_id,
+        ))
+    }
+
+    /// Drops the connection
+    pub fn disconnect(self) {
+        let _ = self.writer.shutdown(Shutdown::Both);
+    }
+}
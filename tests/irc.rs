@@ -0,0 +1,241 @@
+use std::{path::PathBuf, time::Duration};
+
+use yomi::{
+    irc::{self, Disconnect, Event, Response},
+    Joined, Twitch,
+};
+
+mod fake;
+use fake::FakeServer;
+
+const NAME: &str = "shaken_bot";
+const USER_ID: &str = "12345";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// a directory for the bot's databases, it is removed when the test is done
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "yomi-test-{}-{}",
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+struct Bot {
+    events: flume::Receiver<Event>,
+    responses: flume::Sender<Response>,
+    _dir: TempDir,
+}
+
+impl Bot {
+    fn connect(server: &FakeServer, channels: &[&str]) -> Self {
+        let config: Twitch = serde_json::from_value(serde_json::json!({
+            "name": NAME,
+            "helix_oauth": "oauth:hunter2",
+            "transport": "tcp",
+            "address": server.address(),
+            "reconnect": { "min_delay": 0.05, "max_delay": 0.1 },
+        }))
+        .unwrap();
+
+        let channels: Vec<String> = channels.iter().map(ToString::to_string).collect();
+        let dir = TempDir::new();
+        let joined = Joined::new(&channels, dir.0.join("joined.db"));
+
+        let (responses, rx) = flume::unbounded();
+        let events = irc::connect(config, rx, irc::QueueDepth::default(), joined);
+        Self {
+            events,
+            responses,
+            _dir: dir,
+        }
+    }
+
+    fn next_event(&self) -> Event {
+        self.events.recv_timeout(TIMEOUT).expect("an event")
+    }
+
+    fn expect_connected(&self) -> irc::User {
+        match self.next_event() {
+            Event::Connected { user } => user,
+            event => panic!("expected to be connected, got: {event:?}"),
+        }
+    }
+
+    fn expect_disconnected(&self) -> irc::Disconnected {
+        match self.next_event() {
+            Event::Disconnected { disconnected } => disconnected,
+            event => panic!("expected to be disconnected, got: {event:?}"),
+        }
+    }
+
+    fn say(&self, channel: &str, data: &str) {
+        self.responses
+            .send(Response::Say {
+                channel: channel.to_string(),
+                data: data.to_string(),
+            })
+            .unwrap();
+    }
+}
+
+#[test]
+fn registers_and_joins() {
+    let server = FakeServer::bind().unwrap();
+    let bot = Bot::connect(&server, &["#museun"]);
+
+    let mut client = server.accept().unwrap();
+    let lines = client.handshake(NAME, USER_ID).unwrap();
+    let sent = |prefix: &str, suffix: &str| {
+        lines
+            .iter()
+            .any(|line| line.starts_with(prefix) && line.ends_with(suffix))
+    };
+    assert!(sent("PASS ", "hunter2"), "{lines:?}");
+    assert!(sent("NICK ", NAME), "{lines:?}");
+
+    client.expect("JOIN #museun").unwrap();
+
+    let user = bot.expect_connected();
+    assert_eq!(user.name, NAME);
+    assert_eq!(user.user_id, USER_ID);
+}
+
+#[test]
+fn answers_pings() {
+    let server = FakeServer::bind().unwrap();
+    let bot = Bot::connect(&server, &[]);
+
+    let mut client = server.accept().unwrap();
+    client.handshake(NAME, USER_ID).unwrap();
+    bot.expect_connected();
+
+    client.ping("tmi.twitch.tv").unwrap();
+    client.expect("PONG").unwrap();
+}
+
+#[test]
+fn forwards_messages() {
+    let server = FakeServer::bind().unwrap();
+    let bot = Bot::connect(&server, &["#museun"]);
+
+    let mut client = server.accept().unwrap();
+    client.handshake(NAME, USER_ID).unwrap();
+    client.expect("JOIN #museun").unwrap();
+    bot.expect_connected();
+
+    client.privmsg("#museun", "someone", "hello world").unwrap();
+    match bot.next_event() {
+        Event::Message { msg } => {
+            assert_eq!(msg.channel.to_string(), "#museun");
+            assert_eq!(msg.sender.to_string(), "someone");
+            assert_eq!(msg.data.to_string(), "hello world");
+            assert_eq!(msg.room_id().map(|id| id.to_string()).as_deref(), Some("1"));
+            assert_eq!(msg.msg_id().map(|id| id.to_string()).as_deref(), Some("msg-1"));
+        }
+        event => panic!("expected a message, got: {event:?}"),
+    }
+}
+
+#[test]
+fn sends_responses() {
+    let server = FakeServer::bind().unwrap();
+    let bot = Bot::connect(&server, &["#museun"]);
+
+    let mut client = server.accept().unwrap();
+    client.handshake(NAME, USER_ID).unwrap();
+    client.expect("JOIN #museun").unwrap();
+    bot.expect_connected();
+
+    bot.say("#museun", "hello");
+    client.expect("PRIVMSG #museun :hello").unwrap();
+}
+
+#[test]
+fn reconnects_when_asked() {
+    let server = FakeServer::bind().unwrap();
+    let bot = Bot::connect(&server, &["#museun"]);
+
+    let mut client = server.accept().unwrap();
+    client.handshake(NAME, USER_ID).unwrap();
+    client.expect("JOIN #museun").unwrap();
+    bot.expect_connected();
+
+    client.reconnect().unwrap();
+    let disconnected = bot.expect_disconnected();
+    assert!(matches!(disconnected.reason, Disconnect::Reconnect));
+    assert!(disconnected.retry_in.is_some());
+
+    let mut client = server.accept().unwrap();
+    client.handshake(NAME, USER_ID).unwrap();
+    client.expect("JOIN #museun").unwrap();
+    bot.expect_connected();
+}
+
+#[test]
+fn reconnects_after_losing_the_connection() {
+    let server = FakeServer::bind().unwrap();
+    let bot = Bot::connect(&server, &[]);
+
+    let mut client = server.accept().unwrap();
+    client.handshake(NAME, USER_ID).unwrap();
+    bot.expect_connected();
+
+    client.disconnect();
+    let disconnected = bot.expect_disconnected();
+    assert!(matches!(disconnected.reason, Disconnect::Network { .. }));
+
+    let mut client = server.accept().unwrap();
+    client.handshake(NAME, USER_ID).unwrap();
+    bot.expect_connected();
+}
+
+#[test]
+fn sends_the_backlog_after_reconnecting() {
+    let server = FakeServer::bind().unwrap();
+    let bot = Bot::connect(&server, &["#museun"]);
+
+    let mut client = server.accept().unwrap();
+    client.handshake(NAME, USER_ID).unwrap();
+    client.expect("JOIN #museun").unwrap();
+    bot.expect_connected();
+
+    client.disconnect();
+    bot.expect_disconnected();
+    bot.say("#museun", "sent while disconnected");
+
+    let mut client = server.accept().unwrap();
+    client.handshake(NAME, USER_ID).unwrap();
+    client.expect("JOIN #museun").unwrap();
+    client
+        .expect("PRIVMSG #museun :sent while disconnected")
+        .unwrap();
+}
+
+#[test]
+fn stops_after_an_auth_failure() {
+    let server = FakeServer::bind().unwrap();
+    let bot = Bot::connect(&server, &[]);
+
+    let mut client = server.accept().unwrap();
+    client.reject_auth().unwrap();
+
+    let disconnected = bot.expect_disconnected();
+    assert!(matches!(disconnected.reason, Disconnect::Auth { .. }));
+    assert!(disconnected.retry_in.is_none());
+
+    // the connection thread is gone
+    assert!(bot.events.recv_timeout(TIMEOUT).is_err());
+}